@@ -0,0 +1,163 @@
+/**
+ * Video and audio recording, a frame at a time as the emulation produces them, so
+ * nothing is dropped however fast the host runs. Video goes to a Y4M file, raw
+ * 4:4:4 YCbCr at the native resolution and exact frame rate, and audio to a WAV
+ * file alongside, 16-bit stereo at 44.1kHz. There's no SPU emulation yet, so the
+ * audio is silence of the right length, which keeps the two in sync once muxed.
+ *
+ * Y4M can't change resolution midway: frames after a display mode change are
+ * scaled to the size of the first one.
+ */
+use crate::error::Error;
+use crate::renderer::Renderer;
+use crate::system::{System, CPU_CLOCK};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const SAMPLE_RATE: u64 = 44_100;
+const CHANNELS: u16 = 2;
+const BYTES_PER_SAMPLE: u16 = 2;
+const WAV_HEADER_SIZE: u32 = 44;
+
+pub struct AVRecorder {
+    video: BufWriter<File>,
+    audio: BufWriter<File>,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    /// Set by the first frame.
+    size: Option<(u32, u32)>,
+    frames: u64,
+    samples: u64,
+}
+
+impl AVRecorder {
+    /// Starts recording to `path` with the .y4m and .wav extensions.
+    pub fn create(path: &Path) -> Result<AVRecorder, Error> {
+        let video_path = path.with_extension("y4m");
+        let audio_path = path.with_extension("wav");
+        let create = |path: &Path| {
+            File::create(path)
+                .map(BufWriter::new)
+                .map_err(|source| Error::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+        };
+
+        let mut recorder = AVRecorder {
+            video: create(&video_path)?,
+            audio: create(&audio_path)?,
+            video_path,
+            audio_path,
+            size: None,
+            frames: 0,
+            samples: 0,
+        };
+        recorder.write_audio(&[])?;
+        info!(
+            "Recording to {} and {}",
+            recorder.video_path.display(),
+            recorder.audio_path.display()
+        );
+        Ok(recorder)
+    }
+
+    /// Records the frame `system` just ran. Both files are flushed, and stay
+    /// playable if the process exits without dropping us.
+    pub fn frame<R: Renderer>(&mut self, system: &System<R>) -> Result<(), Error> {
+        let (width, height, rgb) = system.cpu().bus().gpu().display_rgb();
+        let video_path = &self.video_path;
+        let video_error = |source| Error::Io {
+            path: video_path.clone(),
+            source,
+        };
+        let (out_width, out_height) = match self.size {
+            Some(size) => size,
+            None => {
+                let header = format!(
+                    "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n",
+                    width,
+                    height,
+                    CPU_CLOCK,
+                    system.cycles_per_frame()
+                );
+                self.video
+                    .write_all(header.as_bytes())
+                    .map_err(video_error)?;
+                *self.size.insert((width, height))
+            }
+        };
+
+        // Planes one after the other, nearest pixel if the size changed
+        let mut planes = vec![0; (out_width * out_height * 3) as usize];
+        let plane_size = (out_width * out_height) as usize;
+        for y in 0..out_height {
+            for x in 0..out_width {
+                let source = ((y * height / out_height) * width + x * width / out_width) as usize;
+                let [r, g, b] = [0, 1, 2].map(|i| rgb[source * 3 + i] as i32);
+                let pixel = (y * out_width + x) as usize;
+                // BT.601, studio range
+                planes[pixel] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+                planes[plane_size + pixel] =
+                    (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+                planes[plane_size * 2 + pixel] =
+                    (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+            }
+        }
+        self.video
+            .write_all(b"FRAME\n")
+            .and_then(|_| self.video.write_all(&planes))
+            .and_then(|_| self.video.flush())
+            .map_err(video_error)?;
+        self.frames += 1;
+
+        // As many samples as the frames so far last, so rounding doesn't add up
+        let cycles = self.frames * system.cycles_per_frame();
+        let samples = cycles * SAMPLE_RATE / CPU_CLOCK;
+        let silence = vec![0; ((samples - self.samples) * sample_bytes()) as usize];
+        self.samples = samples;
+        self.write_audio(&silence)
+    }
+
+    /// Frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Appends samples, and updates the header to cover them.
+    fn write_audio(&mut self, data: &[u8]) -> Result<(), Error> {
+        let data_size = (self.samples * sample_bytes()) as u32;
+        let mut header = Vec::with_capacity(WAV_HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&CHANNELS.to_le_bytes());
+        header.extend_from_slice(&(SAMPLE_RATE as u32).to_le_bytes());
+        header.extend_from_slice(&((SAMPLE_RATE * sample_bytes()) as u32).to_le_bytes());
+        header.extend_from_slice(&(sample_bytes() as u16).to_le_bytes());
+        header.extend_from_slice(&(BYTES_PER_SAMPLE * 8).to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+
+        let audio = &mut self.audio;
+        audio
+            .seek(SeekFrom::End(0))
+            .and_then(|_| audio.write_all(data))
+            .and_then(|_| audio.seek(SeekFrom::Start(0)))
+            .and_then(|_| audio.write_all(&header))
+            .and_then(|_| audio.flush())
+            .map_err(|source| Error::Io {
+                path: self.audio_path.clone(),
+                source,
+            })
+    }
+}
+
+/// Bytes per sample, all channels together.
+fn sample_bytes() -> u64 {
+    (CHANNELS * BYTES_PER_SAMPLE) as u64
+}
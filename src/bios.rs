@@ -1,30 +1,230 @@
+use crate::error::Error;
 use crate::memory::BIOS_SIZE;
 use crate::utils;
 use std::fs::File;
 use std::io::{ErrorKind, Read};
 use std::path::Path;
 
-use log::info;
+use log::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Japan,
+    NorthAmerica,
+    Europe,
+}
+
+impl Region {
+    /// European consoles are PAL, the others NTSC.
+    pub fn is_pal(self) -> bool {
+        self == Region::Europe
+    }
+
+    /// What the CD-ROM controller expects in the licence sector of a disc.
+    pub fn licence(self) -> &'static str {
+        match self {
+            Region::Japan => "SCEI",
+            Region::NorthAmerica => "SCEA",
+            Region::Europe => "SCEE",
+        }
+    }
+}
+
+/// A known BIOS dump.
+pub struct BiosInfo {
+    pub model: &'static str,
+    pub version: &'static str,
+    pub date: &'static str,
+    pub region: Region,
+}
+
+const fn dump(
+    model: &'static str,
+    version: &'static str,
+    date: &'static str,
+    region: Region,
+) -> BiosInfo {
+    BiosInfo {
+        model,
+        version,
+        date,
+        region,
+    }
+}
+
+/// Retail BIOS images, by MD5. Hashes, models and dates as listed by DuckStation
+/// (src/core/bios.cpp) and the libretro Beetle PSX BIOS table.
+const KNOWN_DUMPS: [(&str, BiosInfo); 21] = [
+    (
+        "239665b1a3dade1b5a52c06338011044",
+        dump("SCPH-1000", "1.0", "1994-09-22", Region::Japan),
+    ),
+    (
+        "849515939161e62f6b866f6853006780",
+        dump("SCPH-3000", "1.1", "1995-01-22", Region::Japan),
+    ),
+    (
+        "dc2b9bf8da62ec93e868cfd29f0d067d",
+        dump("SCPH-1001", "2.0", "1995-05-07", Region::NorthAmerica),
+    ),
+    (
+        "54847e693405ffeb0359c6287434cbef",
+        dump("SCPH-1002", "2.0", "1995-05-10", Region::Europe),
+    ),
+    (
+        "cba733ceeff5aef5c32254f1d617fa62",
+        dump("SCPH-3500", "2.1", "1995-07-17", Region::Japan),
+    ),
+    (
+        "da27e8b6dab242d8f91a9b25d80c63b8",
+        dump("SCPH-1001", "2.1", "1995-07-17", Region::NorthAmerica),
+    ),
+    (
+        "417b34706319da7cf001e76e40136c23",
+        dump("SCPH-1002", "2.1", "1995-07-17", Region::Europe),
+    ),
+    (
+        "57a06303dfa9cf9351222dfcbb4a29d9",
+        dump("SCPH-5000", "2.2", "1995-12-04", Region::Japan),
+    ),
+    (
+        "924e392ed05558ffdb115408c263dccf",
+        dump("SCPH-1001", "2.2", "1995-12-04", Region::NorthAmerica),
+    ),
+    (
+        "e2110b8a2b97a8e0b857a45d32f7e187",
+        dump("SCPH-1002", "2.2", "1995-12-04", Region::Europe),
+    ),
+    (
+        "8dd7d5296a650fac7319bce665a6a53c",
+        dump("SCPH-5500", "3.0", "1996-09-09", Region::Japan),
+    ),
+    (
+        "490f666e1afb15b7362b406ed1cea246",
+        dump("SCPH-5501", "3.0", "1996-11-18", Region::NorthAmerica),
+    ),
+    (
+        "32736f17079d0b2b7024407c39bd3050",
+        dump("SCPH-5502", "3.0", "1997-01-06", Region::Europe),
+    ),
+    (
+        "8e4c14f567745eff2f0408c8129f72a6",
+        dump("SCPH-7000", "4.0", "1997-08-18", Region::Japan),
+    ),
+    (
+        "1e68c231d0896b7eadcad1d7d8e76129",
+        dump("SCPH-7001", "4.1", "1997-12-16", Region::NorthAmerica),
+    ),
+    (
+        "b9d9a0286c33dc6b7237bb13cd46fdee",
+        dump("SCPH-7502", "4.1", "1997-12-16", Region::Europe),
+    ),
+    (
+        "8abc1b549a4a80954addc48ef02c4521",
+        dump("SCPH-100", "4.3", "2000-03-11", Region::Japan),
+    ),
+    (
+        "9a09ab7e49b422c007e6d54d7c49b965",
+        dump("SCPH-101", "4.4", "2000-03-24", Region::NorthAmerica),
+    ),
+    (
+        "b10f5e0e3d9eb60e5159690680b1e774",
+        dump("SCPH-102", "4.4", "2000-03-24", Region::Europe),
+    ),
+    (
+        "6e3735ff4c7dc899ee98981385f6f3d0",
+        dump("SCPH-101", "4.5", "2000-05-25", Region::NorthAmerica),
+    ),
+    (
+        "de93caec13d1a141a40a79f5c86168d6",
+        dump("SCPH-102", "4.5", "2000-05-25", Region::Europe),
+    ),
+];
 
 pub struct BIOS {
     pub data: Vec<u8>,
+    info: Option<&'static BiosInfo>,
+    version: Option<String>,
 }
 
 impl BIOS {
-    pub fn new(path: &Path) -> std::io::Result<BIOS> {
+    pub fn new(path: &Path) -> Result<BIOS, Error> {
         info!("Reading BIOS from {}.", path.display());
-        let file = File::open(path)?;
+        let file = File::open(path).map_err(Error::BiosLoad)?;
         let mut data = Vec::new();
 
-        file.take(BIOS_SIZE as u64).read_to_end(&mut data)?;
+        file.take(BIOS_SIZE as u64)
+            .read_to_end(&mut data)
+            .map_err(Error::BiosLoad)?;
 
-        if data.len() == BIOS_SIZE as usize {
-            Ok(BIOS { data })
-        } else {
-            Err(std::io::Error::new(
+        if data.len() != BIOS_SIZE as usize {
+            return Err(Error::BiosLoad(std::io::Error::new(
                 ErrorKind::InvalidInput,
                 "Invalid BIOS file.",
-            ))
+            )));
+        }
+
+        let hash = format!("{:x}", md5::compute(&data));
+        let info = KNOWN_DUMPS
+            .iter()
+            .find(|(h, _)| *h == hash)
+            .map(|(_, info)| info);
+        let version = version_string(&data);
+        match (info, &version) {
+            (Some(info), _) => info!(
+                "BIOS is {} v{} ({}, {:?})",
+                info.model, info.version, info.date, info.region
+            ),
+            (None, Some(version)) => {
+                warn!("Patched BIOS image (MD5 {}): {}", hash, version)
+            }
+            (None, None) => warn!(
+                "Unknown BIOS image (MD5 {}) without a version string, can't tell its region",
+                hash
+            ),
+        }
+
+        Ok(BIOS {
+            data,
+            info,
+            version,
+        })
+    }
+
+    /// A blank ROM, for booting with the HLE kernel.
+    pub fn empty() -> BIOS {
+        BIOS {
+            data: vec![0; BIOS_SIZE as usize],
+            info: None,
+            version: None,
+        }
+    }
+
+    /// MD5 of the image, which is what identifies it.
+    pub fn hash(&self) -> [u8; 16] {
+        md5::compute(&self.data).0
+    }
+
+    /// None for unknown images.
+    pub fn info(&self) -> Option<&'static BiosInfo> {
+        self.info
+    }
+
+    /// Like "System ROM Version 4.1 12/16/97 A".
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// From the hash or, for patched images, the letter ending the version string.
+    pub fn region(&self) -> Option<Region> {
+        if let Some(info) = self.info {
+            return Some(info.region);
+        }
+        match self.version.as_ref()?.trim_end().chars().last()? {
+            'J' => Some(Region::Japan),
+            'A' => Some(Region::NorthAmerica),
+            'E' => Some(Region::Europe),
+            _ => None,
         }
     }
 
@@ -33,3 +233,53 @@ impl BIOS {
         utils::load(&self.data, addr)
     }
 }
+
+/// The "System ROM Version" string, which all but the first BIOS version (1.0,
+/// Japan only) have.
+fn version_string(data: &[u8]) -> Option<String> {
+    let marker = b"System ROM Version";
+    let start = data.windows(marker.len()).position(|w| w == marker)?;
+    let end = start + data[start..].iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&data[start..end]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_version(version: &[u8]) -> BIOS {
+        let mut data = vec![0; BIOS_SIZE as usize];
+        data[0x7ff32..0x7ff32 + version.len()].copy_from_slice(version);
+        let version = version_string(&data);
+        BIOS {
+            data,
+            info: None,
+            version,
+        }
+    }
+
+    #[test]
+    fn region_from_version_string() {
+        let bios = with_version(b"System ROM Version 4.1 12/16/97 E");
+        assert_eq!(bios.version(), Some("System ROM Version 4.1 12/16/97 E"));
+        assert_eq!(bios.region(), Some(Region::Europe));
+    }
+
+    #[test]
+    fn no_version_string() {
+        let bios = with_version(b"");
+        assert_eq!(bios.version(), None);
+        assert_eq!(bios.region(), None);
+    }
+
+    #[test]
+    fn known_dump_region() {
+        // The hash wins over the version string
+        let (_, info) = &KNOWN_DUMPS[8];
+        let bios = BIOS {
+            info: Some(info),
+            ..with_version(b"System ROM Version 2.2 12/04/95 E")
+        };
+        assert_eq!(bios.region(), Some(Region::NorthAmerica));
+    }
+}
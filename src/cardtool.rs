@@ -0,0 +1,120 @@
+// `rstationx memcard ...`: command line front for the memory card library.
+use rstationx::error::{Error, MemoryCardError};
+use rstationx::memcard::{Card, Format, Region, SaveFormat};
+use std::path::Path;
+use std::string::String;
+
+const USAGE: &str = "Usage:
+    rstationx memcard list <card>
+    rstationx memcard icon <card> <block>
+    rstationx memcard export <card> <block> <save.mcs|save.psv>
+    rstationx memcard import <card> <save.mcs|save.psv>
+    rstationx memcard delete <card> <block>
+    rstationx memcard undelete <card> <block>
+    rstationx memcard convert <card> <output card>
+
+Cards can be raw (.mcr/.mcd), DexDrive (.gme) or PSP (.vmp) images, and are
+written back in the format they were read in.";
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+
+    match args.as_slice() {
+        ["list", card] => list(&Card::load(Path::new(card))?),
+        ["icon", card, block] => icon(&Card::load(Path::new(card))?, parse_block(block)?),
+        ["export", card, block, output] => {
+            let format = SaveFormat::from_extension(Path::new(output))?;
+            let data = Card::load(Path::new(card))?.export(parse_block(block)?, format)?;
+            write(output, &data)
+        }
+        ["import", card, save] => {
+            let data = std::fs::read(save).map_err(|source| Error::Io {
+                path: save.into(),
+                source,
+            })?;
+            modify(card, |c| {
+                let block = c.import(&data)?;
+                println!("Imported {} into block {}", save, block + 1);
+                Ok(())
+            })
+        }
+        ["delete", card, block] => modify(card, |c| c.delete(parse_block(block)?)),
+        ["undelete", card, block] => modify(card, |c| c.undelete(parse_block(block)?)),
+        ["convert", card, output] => {
+            let output = Path::new(output);
+            Card::load(Path::new(card))?.save(output, Format::from_extension(output)?)
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            Err(Error::MemoryCard(MemoryCardError::InvalidCommand))
+        }
+    }
+}
+
+fn list(card: &Card) -> Result<(), Error> {
+    println!("Block  Blocks  Size  Icon  Region   Filename              Title");
+    for save in card.saves() {
+        let region = match save.region {
+            Region::Japan => "Japan",
+            Region::America => "America",
+            Region::Europe => "Europe",
+            Region::Unknown => "?",
+        };
+        println!(
+            "{:>5}  {:>6}  {:>3}K  {:>4}  {:<7}  {:<20}  {}{}",
+            save.first_block + 1,
+            save.blocks.len(),
+            save.size / 1024,
+            save.icon.frames,
+            region,
+            save.filename,
+            save.title,
+            if save.deleted { " (deleted)" } else { "" }
+        );
+    }
+    println!("{} free blocks", card.free_blocks().len());
+    Ok(())
+}
+
+/// Draws the icon with 24-bit ANSI colors, two pixels per character cell.
+fn icon(card: &Card, block: usize) -> Result<(), Error> {
+    let icon = card.save_info(block)?.icon;
+
+    for y in (0..16).step_by(2) {
+        for x in 0..16 {
+            let (r1, g1, b1) = icon.rgb(x, y);
+            let (r2, g2, b2) = icon.rgb(x, y + 1);
+            print!("\x1b[38;2;{r1};{g1};{b1}m\x1b[48;2;{r2};{g2};{b2}m\u{2580}");
+        }
+        println!("\x1b[0m");
+    }
+    Ok(())
+}
+
+/// Applies `f` to the card at `path` and writes it back in the format it was in.
+fn modify<F>(path: &str, f: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut Card) -> Result<(), Error>,
+{
+    let path = Path::new(path);
+    let (mut card, format) = Card::open(path)?;
+    f(&mut card)?;
+    card.save(path, format)
+}
+
+/// Blocks are numbered 1 to 15, like in the BIOS memory card manager.
+fn parse_block(block: &str) -> Result<usize, Error> {
+    match block.parse::<usize>() {
+        Ok(n @ 1..=15) => Ok(n - 1),
+        _ => Err(Error::MemoryCard(MemoryCardError::InvalidBlockNumber(
+            block.to_string(),
+        ))),
+    }
+}
+
+fn write(path: &str, data: &[u8]) -> Result<(), Error> {
+    std::fs::write(path, data).map_err(|source| Error::Io {
+        path: path.into(),
+        source,
+    })
+}
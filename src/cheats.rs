@@ -0,0 +1,452 @@
+/**
+ * GameShark / Action Replay codes, applied to RAM once per frame. Cheat files list
+ * the cheats with their codes, one code per line, the box in front of the name
+ * says whether the cheat starts enabled:
+ *
+ *     # Comments start with '#'
+ *     [x] Infinite health
+ *     800A1234 0063
+ *     [ ] Level select
+ *     D01F0000 1234
+ *     801F0002 0001
+ *
+ * Codes supported:
+ *
+ *     30aaaaaa 00vv   write the byte vv at aaaaaa
+ *     80aaaaaa vvvv   write the halfword vvvv at aaaaaa
+ *     D0aaaaaa vvvv   apply the next code if the halfword at aaaaaa is vvvv
+ *     D1aaaaaa vvvv   ... if it isn't vvvv
+ *     E0aaaaaa 00vv   apply the next code if the byte at aaaaaa is vv
+ *     E1aaaaaa 00vv   ... if it isn't vv
+ *     5000nnss vvvv   apply the next write nn times, moving the address by ss and
+ *                     adding vvvv to the value each time
+ *     C0aaaaaa vvvv   apply the rest of the cheat only if the halfword at aaaaaa
+ *                     is vvvv
+ *     C1000000 nnnn   apply the rest of the cheat once it's been enabled for nnnn
+ *                     frames
+ *
+ * Addresses are in main RAM, mirrors included.
+ */
+use crate::error::{CheatError, Error};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy)]
+enum Code {
+    Write8 {
+        addr: u32,
+        value: u8,
+    },
+    Write16 {
+        addr: u32,
+        value: u16,
+    },
+    /// Applies the next code only if the byte or halfword at `addr` is (or isn't)
+    /// `value`.
+    If {
+        addr: u32,
+        value: u16,
+        halfword: bool,
+        equal: bool,
+    },
+    /// Applies the next code, a write, `count` times.
+    Repeat {
+        count: u8,
+        step: u8,
+        increment: u16,
+    },
+    Activator {
+        addr: u32,
+        value: u16,
+    },
+    Delay(u16),
+}
+
+impl Code {
+    fn parse(line: &str) -> Result<Code, CheatError> {
+        let digits: String = line.split_whitespace().collect();
+        if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(CheatError::NotACode(line.to_string()));
+        }
+        let word = u32::from_str_radix(&digits[..8], 16).unwrap();
+        let value = u16::from_str_radix(&digits[8..], 16).unwrap();
+        let addr = word & 0xff_ffff;
+
+        let code = match word >> 24 {
+            0x30 => Code::Write8 {
+                addr,
+                value: value as u8,
+            },
+            0x80 => Code::Write16 { addr, value },
+            0xd0 | 0xd1 | 0xe0 | 0xe1 => Code::If {
+                addr,
+                value,
+                halfword: word >> 28 == 0xd,
+                equal: word & (1 << 24) == 0,
+            },
+            0x50 => Code::Repeat {
+                count: (word >> 8) as u8,
+                step: word as u8,
+                increment: value,
+            },
+            0xc0 => Code::Activator { addr, value },
+            0xc1 => Code::Delay(value),
+            kind => return Err(CheatError::UnsupportedCodeType(kind as u8)),
+        };
+        Ok(code)
+    }
+}
+
+pub struct Cheat {
+    name: String,
+    enabled: bool,
+    codes: Vec<Code>,
+    /// Frames since it was enabled, for delays.
+    frames: u32,
+}
+
+impl Cheat {
+    /// A cheat from its codes, one per line.
+    pub fn new(name: &str, codes: &str) -> Result<Cheat, Error> {
+        let mut cheat = Cheat {
+            name: name.to_string(),
+            enabled: false,
+            codes: Vec::new(),
+            frames: 0,
+        };
+        for line in codes.lines().filter(|l| !l.trim().is_empty()) {
+            cheat.push(line).map_err(Error::InvalidCheat)?;
+        }
+        cheat.check().map_err(Error::InvalidCheat)?;
+        Ok(cheat)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.frames = 0;
+    }
+
+    fn push(&mut self, line: &str) -> Result<(), CheatError> {
+        self.codes.push(Code::parse(line)?);
+        Ok(())
+    }
+
+    /// Conditionals and repeaters need a code after them.
+    fn check(&self) -> Result<(), CheatError> {
+        for (i, code) in self.codes.iter().enumerate() {
+            let next = self.codes.get(i + 1);
+            match code {
+                Code::If { .. } if next.is_none() => {
+                    return Err(CheatError::MissingConditionalTarget(self.name.clone()))
+                }
+                Code::Repeat { .. }
+                    if !matches!(next, Some(Code::Write8 { .. } | Code::Write16 { .. })) =>
+                {
+                    return Err(CheatError::MissingRepeatedWrite(self.name.clone()))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, ram: &mut [u8]) {
+        self.frames = self.frames.saturating_add(1);
+
+        let mut i = 0;
+        while i < self.codes.len() {
+            match self.codes[i] {
+                Code::Write8 { addr, value } => write8(ram, addr, value),
+                Code::Write16 { addr, value } => write16(ram, addr, value),
+                Code::If {
+                    addr,
+                    value,
+                    halfword,
+                    equal,
+                } => {
+                    let current = match halfword {
+                        true => read16(ram, addr),
+                        false => read8(ram, addr) as u16,
+                    };
+                    if (current == value) != equal {
+                        // A repeater takes its write along
+                        i += match self.codes[i + 1] {
+                            Code::Repeat { .. } => 2,
+                            _ => 1,
+                        };
+                    }
+                }
+                Code::Repeat {
+                    count,
+                    step,
+                    increment,
+                } => {
+                    i += 1;
+                    for n in 0..count as u32 {
+                        let offset = n * step as u32;
+                        let increment = (n as u16).wrapping_mul(increment);
+                        match self.codes[i] {
+                            Code::Write8 { addr, value } => {
+                                write8(ram, addr + offset, value.wrapping_add(increment as u8))
+                            }
+                            Code::Write16 { addr, value } => {
+                                write16(ram, addr + offset, value.wrapping_add(increment))
+                            }
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+                Code::Activator { addr, value } => {
+                    if read16(ram, addr) != value {
+                        return;
+                    }
+                }
+                Code::Delay(frames) => {
+                    if self.frames <= frames as u32 {
+                        return;
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats { cheats: Vec::new() }
+    }
+
+    pub fn load(path: &Path) -> Result<Cheats, Error> {
+        let text = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Cheats::parse(&text)
+    }
+
+    /// Reads a cheat file, see the top of this file for the format.
+    pub fn parse(text: &str) -> Result<Cheats, Error> {
+        let mut cheats = Cheats::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |error| {
+                Error::InvalidCheat(CheatError::Line {
+                    line: number + 1,
+                    error: Box::new(error),
+                })
+            };
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let (enabled, name) = match name.split_once(']') {
+                    Some((" " | "", name)) => (false, name),
+                    Some(("x" | "X", name)) => (true, name),
+                    _ => return Err(error(CheatError::NotAName(line.to_string()))),
+                };
+                cheats.cheats.push(Cheat {
+                    name: name.trim().to_string(),
+                    enabled,
+                    codes: Vec::new(),
+                    frames: 0,
+                });
+                continue;
+            }
+
+            match cheats.cheats.last_mut() {
+                Some(cheat) => cheat.push(line).map_err(error)?,
+                None => return Err(error(CheatError::CodeBeforeName)),
+            }
+        }
+
+        for cheat in &cheats.cheats {
+            cheat.check().map_err(Error::InvalidCheat)?;
+        }
+        Ok(cheats)
+    }
+
+    /// Adds a cheat at the end of the list, returns its index.
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(cheat);
+        self.cheats.len() - 1
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Cheat> {
+        self.cheats.get_mut(index)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Once per frame.
+    pub fn apply(&mut self, ram: &mut [u8]) {
+        for cheat in self.cheats.iter_mut().filter(|c| c.enabled) {
+            cheat.apply(ram);
+        }
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Cheats {
+        Cheats::new()
+    }
+}
+
+// Codes address RAM through any of its mirrors
+fn index(ram: &[u8], addr: u32) -> usize {
+    addr as usize % ram.len()
+}
+
+fn read8(ram: &[u8], addr: u32) -> u8 {
+    ram[index(ram, addr)]
+}
+
+fn read16(ram: &[u8], addr: u32) -> u16 {
+    u16::from_le_bytes([read8(ram, addr), read8(ram, addr + 1)])
+}
+
+fn write8(ram: &mut [u8], addr: u32, value: u8) {
+    let index = index(ram, addr);
+    ram[index] = value;
+}
+
+fn write16(ram: &mut [u8], addr: u32, value: u16) {
+    let [low, high] = value.to_le_bytes();
+    write8(ram, addr, low);
+    write8(ram, addr + 1, high);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RAM after the codes run for a frame, enabled from the start.
+    fn apply(codes: &str, ram: &mut [u8]) {
+        let mut cheat = Cheat::new("Test", codes).unwrap();
+        cheat.set_enabled(true);
+        cheat.apply(ram);
+    }
+
+    #[test]
+    fn parse() {
+        let cheats = Cheats::parse(
+            "# Comment\n\
+             [x] Infinite health\n\
+             800A1234 0063\n\
+             \n\
+             [ ] Level select\n\
+             D01F0000 1234\n\
+             301F0002 0001\n",
+        )
+        .unwrap();
+
+        let cheats = cheats.cheats();
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(
+            (cheats[0].name(), cheats[0].enabled()),
+            ("Infinite health", true)
+        );
+        assert_eq!(
+            (cheats[1].name(), cheats[1].enabled()),
+            ("Level select", false)
+        );
+        assert_eq!(cheats[1].codes.len(), 2);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let line = |text: &str| match Cheats::parse(text) {
+            Err(Error::InvalidCheat(CheatError::Line { line, .. })) => line,
+            _ => panic!("{:?} parsed", text),
+        };
+        assert_eq!(line("800A1234 0063"), 1);
+        assert_eq!(line("[x] Cheat\n800A1234"), 2);
+        assert_eq!(line("[x] Cheat\nFF0A1234 0063"), 2);
+        assert_eq!(line("[?] Cheat"), 1);
+
+        // Conditionals and repeaters need a code to act on
+        assert!(Cheats::parse("[x] Cheat\nD00A1234 0063").is_err());
+        assert!(Cheats::parse("[x] Cheat\n50000402 0001\nD00A1234 0063").is_err());
+    }
+
+    #[test]
+    fn writes() {
+        let mut ram = [0; 16];
+        // Addresses wrap around RAM, like its mirrors
+        apply("30000001 00AB\n80000012 1234", &mut ram);
+        assert_eq!(ram[..4], [0, 0xab, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn conditionals_skip_the_next_code() {
+        let mut ram = [0; 16];
+        ram[0] = 0x12;
+        apply(
+            "D0000000 0012\n30000004 0001\n\
+             D1000000 0012\n30000005 0001\n\
+             E0000000 0013\n30000006 0001\n\
+             E1000000 0013\n30000007 0001",
+            &mut ram,
+        );
+        assert_eq!(ram[4..8], [1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn skipped_repeater_takes_its_write_along() {
+        let mut ram = [0; 16];
+        apply(
+            "D0000000 0001\n50000201 0000\n30000004 0001\n30000008 0002",
+            &mut ram,
+        );
+        assert_eq!(ram[4..9], [0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn repeater() {
+        let mut ram = [0; 16];
+        // 3 halfwords, 4 bytes apart, going up by 0x10
+        apply("50000304 0010\n80000000 0100", &mut ram);
+        assert_eq!(ram[..12], [0, 1, 0, 0, 0x10, 1, 0, 0, 0x20, 1, 0, 0]);
+    }
+
+    #[test]
+    fn activator_ends_the_cheat() {
+        let mut ram = [0; 16];
+        apply("30000004 0001\nC0000000 0001\n30000005 0001", &mut ram);
+        assert_eq!(ram[4..6], [1, 0]);
+
+        ram[0] = 1;
+        apply("30000004 0001\nC0000000 0001\n30000005 0001", &mut ram);
+        assert_eq!(ram[4..6], [1, 1]);
+    }
+
+    #[test]
+    fn delay() {
+        let mut ram = [0; 16];
+        let mut cheat = Cheat::new("Test", "C1000000 0002\n30000000 0001").unwrap();
+        cheat.set_enabled(true);
+        cheat.apply(&mut ram);
+        cheat.apply(&mut ram);
+        assert_eq!(ram[0], 0);
+        cheat.apply(&mut ram);
+        assert_eq!(ram[0], 1);
+    }
+}
@@ -0,0 +1,197 @@
+// The 4 KiB instruction cache and the CacheControl register (0xfffe0130).
+use crate::error::Error;
+use crate::savestate::{StateReader, StateWriter};
+
+pub const CACHE_CONTROL: u32 = 0xfffe0130;
+
+/// Extra cycles for an instruction fetched from uncached memory.
+pub const UNCACHED_FETCH_CYCLES: u64 = 4;
+/// Extra cycles to start a cache line refill, on top of one per word fetched.
+pub const REFILL_CYCLES: u64 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheControl(pub u32);
+
+impl CacheControl {
+    /// Isolated writes set the line tags instead of the data.
+    pub fn tag_test_mode(self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    pub fn icache_enabled(self) -> bool {
+        self.0 & (1 << 11) != 0
+    }
+}
+
+#[derive(Clone, Copy)]
+struct CacheLine {
+    // Bits 31:12 of the address the line holds
+    tag: u32,
+    valid: [bool; 4],
+    words: [u32; 4],
+}
+
+/// 256 lines of four words each, direct mapped.
+pub struct ICache {
+    lines: [CacheLine; 256],
+}
+
+impl ICache {
+    pub fn new() -> ICache {
+        ICache {
+            lines: [CacheLine {
+                tag: 0,
+                valid: [false; 4],
+                words: [0; 4],
+            }; 256],
+        }
+    }
+
+    fn line(addr: u32) -> usize {
+        ((addr >> 4) & 0xff) as usize
+    }
+
+    fn index(addr: u32) -> usize {
+        ((addr >> 2) & 3) as usize
+    }
+
+    fn tag(addr: u32) -> u32 {
+        addr & 0x7fff_f000
+    }
+
+    /// Returns the cached word for `addr`, if there is one.
+    pub fn lookup(&self, addr: u32) -> Option<u32> {
+        let line = &self.lines[ICache::line(addr)];
+        let index = ICache::index(addr);
+
+        match line.tag == ICache::tag(addr) && line.valid[index] {
+            true => Some(line.words[index]),
+            false => None,
+        }
+    }
+
+    /// Refills the line holding `addr` from `addr` to the end of the line. The
+    /// words before `addr` are not fetched and become invalid.
+    pub fn refill<F, E>(&mut self, addr: u32, mut fetch: F) -> Result<u64, E>
+    where
+        F: FnMut(u32) -> Result<u32, E>,
+    {
+        let line = &mut self.lines[ICache::line(addr)];
+        let start = ICache::index(addr);
+
+        line.tag = ICache::tag(addr);
+        line.valid = [false; 4];
+        for index in start..4 {
+            line.words[index] = fetch((addr & !0xf) | (index as u32) << 2)?;
+            line.valid[index] = true;
+        }
+        Ok(REFILL_CYCLES + (4 - start) as u64)
+    }
+
+    /// Word read while the cache is isolated.
+    pub fn isolated_load(&self, addr: u32) -> u32 {
+        self.lines[ICache::line(addr)].words[ICache::index(addr)]
+    }
+
+    /// Write while the cache is isolated. That's how the BIOS flushes it: in tag test
+    /// mode the write invalidates the line, otherwise it replaces the data word.
+    pub fn isolated_store(&mut self, control: CacheControl, addr: u32, value: u32) {
+        let line = &mut self.lines[ICache::line(addr)];
+
+        if control.tag_test_mode() {
+            line.tag = ICache::tag(addr);
+            line.valid = [false; 4];
+        } else {
+            line.words[ICache::index(addr)] = value;
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for line in &self.lines {
+            w.u32(line.tag);
+            for &valid in &line.valid {
+                w.bool(valid);
+            }
+            w.u32s(&line.words);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        for line in &mut self.lines {
+            line.tag = r.u32()?;
+            for valid in &mut line.valid {
+                *valid = r.bool()?;
+            }
+            r.u32s(&mut line.words)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAG_TEST: CacheControl = CacheControl(1 << 2 | 1 << 11);
+    const ENABLED: CacheControl = CacheControl(1 << 11);
+
+    fn refilled(addr: u32) -> ICache {
+        let mut icache = ICache::new();
+        icache
+            .refill(addr, |a| Ok::<u32, ()>(a ^ 0xffff_ffff))
+            .unwrap();
+        icache
+    }
+
+    #[test]
+    fn misses_until_refilled() {
+        let icache = ICache::new();
+        assert_eq!(icache.lookup(0x8000_1000), None);
+
+        let icache = refilled(0x8000_1000);
+        for addr in (0x8000_1000..0x8000_1010).step_by(4) {
+            assert_eq!(icache.lookup(addr), Some(addr ^ 0xffff_ffff));
+        }
+        assert_eq!(icache.lookup(0x8000_1010), None);
+    }
+
+    #[test]
+    fn refill_from_the_middle_of_a_line() {
+        let mut icache = ICache::new();
+        let cycles = icache.refill(0x8000_1008, Ok::<u32, ()>).unwrap();
+        assert_eq!(cycles, REFILL_CYCLES + 2);
+        assert_eq!(icache.lookup(0x8000_1004), None);
+        assert_eq!(icache.lookup(0x8000_1008), Some(0x8000_1008));
+        assert_eq!(icache.lookup(0x8000_100c), Some(0x8000_100c));
+    }
+
+    #[test]
+    fn other_tag_misses() {
+        // Same line, 4 KiB further
+        let icache = refilled(0x8000_1000);
+        assert_eq!(icache.lookup(0x8000_2000), None);
+    }
+
+    #[test]
+    fn kuseg_and_kseg0_share_lines() {
+        let icache = refilled(0x8000_1000);
+        assert_eq!(icache.lookup(0x0000_1000), Some(0x8000_1000 ^ 0xffff_ffff));
+    }
+
+    #[test]
+    fn isolated_tag_test_write_invalidates_the_line() {
+        let mut icache = refilled(0x8000_1000);
+        icache.isolated_store(TAG_TEST, 0x1000, 0);
+        for addr in (0x8000_1000..0x8000_1010).step_by(4) {
+            assert_eq!(icache.lookup(addr), None);
+        }
+    }
+
+    #[test]
+    fn isolated_write_replaces_the_word() {
+        let mut icache = refilled(0x8000_1000);
+        icache.isolated_store(ENABLED, 0x1004, 0x1234_5678);
+        assert_eq!(icache.lookup(0x8000_1004), Some(0x1234_5678));
+        assert_eq!(icache.isolated_load(0x1004), 0x1234_5678);
+    }
+}
@@ -0,0 +1,100 @@
+// COP0 debug registers: the hardware breakpoints and their control register (DCIC).
+use crate::error::Error;
+use crate::savestate::{StateReader, StateWriter};
+
+/// COP0 r15, CXD8530 revision of the R3000A.
+pub const PRID: u32 = 0x0000_0002;
+
+// DCIC status bits, set when a breakpoint hits
+const HIT_ANY: u32 = 1 << 0;
+const HIT_EXECUTION: u32 = 1 << 1;
+const HIT_DATA: u32 = 1 << 2;
+const HIT_DATA_READ: u32 = 1 << 3;
+const HIT_DATA_WRITE: u32 = 1 << 4;
+
+// DCIC enable bits. A breakpoint needs its own bit, the master enable and both
+// super-master enables.
+const ENABLE_EXECUTION: u32 = 1 << 24;
+const ENABLE_DATA: u32 = 1 << 25;
+const ENABLE_DATA_READ: u32 = 1 << 26;
+const ENABLE_DATA_WRITE: u32 = 1 << 27;
+const MASTER_ENABLE: u32 = 1 << 30;
+const SUPER_MASTER_ENABLE: u32 = (1 << 23) | (1 << 31);
+
+pub struct DebugRegisters {
+    /// r3, breakpoint on execute address
+    pub bpc: u32,
+    /// r5, breakpoint on data access address
+    pub bda: u32,
+    /// r6, address of the last taken jump or branch
+    pub jumpdest: u32,
+    /// r7, breakpoint control
+    pub dcic: u32,
+    /// r9, breakpoint on data access mask
+    pub bdam: u32,
+    /// r11, breakpoint on execute mask
+    pub bpcm: u32,
+}
+
+impl DebugRegisters {
+    pub fn new() -> DebugRegisters {
+        DebugRegisters {
+            bpc: 0,
+            bda: 0,
+            jumpdest: 0,
+            dcic: 0,
+            bdam: 0,
+            bpcm: 0,
+        }
+    }
+
+    fn enabled(&self, bits: u32) -> bool {
+        let bits = bits | MASTER_ENABLE | SUPER_MASTER_ENABLE;
+        self.dcic & bits == bits
+    }
+
+    /// Returns true, and latches the status bits, if fetching `pc` hits the
+    /// execution breakpoint.
+    pub fn check_execution(&mut self, pc: u32) -> bool {
+        if !self.enabled(ENABLE_EXECUTION) || (pc ^ self.bpc) & self.bpcm != 0 {
+            return false;
+        }
+        self.dcic |= HIT_ANY | HIT_EXECUTION;
+        true
+    }
+
+    /// Same for a data access to `addr`.
+    pub fn check_data(&mut self, addr: u32, write: bool) -> bool {
+        let (enable, hit) = match write {
+            true => (ENABLE_DATA_WRITE, HIT_DATA_WRITE),
+            false => (ENABLE_DATA_READ, HIT_DATA_READ),
+        };
+
+        if !self.enabled(ENABLE_DATA | enable) || (addr ^ self.bda) & self.bdam != 0 {
+            return false;
+        }
+        self.dcic |= HIT_ANY | HIT_DATA | hit;
+        true
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u32s(&[
+            self.bpc,
+            self.bda,
+            self.jumpdest,
+            self.dcic,
+            self.bdam,
+            self.bpcm,
+        ]);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.bpc = r.u32()?;
+        self.bda = r.u32()?;
+        self.jumpdest = r.u32()?;
+        self.dcic = r.u32()?;
+        self.bdam = r.u32()?;
+        self.bpcm = r.u32()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,374 @@
+// R3000A disassembler, with the usual assembler aliases (nop, move, li, b, ...) and
+// the GTE commands.
+use super::instruction::Instruction;
+use super::REGISTER_NAMES;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Names for addresses, shown next to branch and jump targets.
+pub trait Symbols {
+    fn symbol(&self, addr: u32) -> Option<&str>;
+}
+
+pub struct NoSymbols;
+
+impl Symbols for NoSymbols {
+    fn symbol(&self, _addr: u32) -> Option<&str> {
+        None
+    }
+}
+
+impl Symbols for HashMap<u32, String> {
+    fn symbol(&self, addr: u32) -> Option<&str> {
+        self.get(&addr).map(|s| s.as_str())
+    }
+}
+
+impl Instruction {
+    /// Assembly text for the instruction at `pc`, e.g. `addiu $sp, $sp, -0x18`.
+    pub fn disassemble(&self, pc: u32, symbols: &dyn Symbols) -> String {
+        Disassembly {
+            instruction: *self,
+            pc: Some(pc),
+            symbols,
+        }
+        .to_string()
+    }
+}
+
+/// Without an address, branch targets are shown relative to the branch.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Disassembly {
+            instruction: *self,
+            pc: None,
+            symbols: &NoSymbols,
+        }
+        .fmt(f)
+    }
+}
+
+struct Disassembly<'a> {
+    instruction: Instruction,
+    pc: Option<u32>,
+    symbols: &'a dyn Symbols,
+}
+
+fn gpr(index: u32) -> String {
+    format!("${}", REGISTER_NAMES[index as usize])
+}
+
+fn signed(value: u32) -> String {
+    let value = value as i16;
+    match value < 0 {
+        true => format!("-0x{:x}", -(value as i32)),
+        false => format!("0x{:x}", value),
+    }
+}
+
+fn gte_command(funct: u32) -> Option<&'static str> {
+    let name = match funct {
+        0x01 => "rtps",
+        0x06 => "nclip",
+        0x0c => "op",
+        0x10 => "dpcs",
+        0x11 => "intpl",
+        0x12 => "mvmva",
+        0x13 => "ncds",
+        0x14 => "cdp",
+        0x16 => "ncdt",
+        0x1b => "nccs",
+        0x1c => "cc",
+        0x1e => "ncs",
+        0x20 => "nct",
+        0x28 => "sqr",
+        0x29 => "dcpl",
+        0x2a => "dpct",
+        0x2d => "avsz3",
+        0x2e => "avsz4",
+        0x30 => "rtpt",
+        0x3d => "gpf",
+        0x3e => "gpl",
+        0x3f => "ncct",
+        _ => return None,
+    };
+    Some(name)
+}
+
+impl Disassembly<'_> {
+    fn target(&self, f: &mut fmt::Formatter, target: u32) -> fmt::Result {
+        write!(f, "0x{:08x}", target)?;
+        match self.symbols.symbol(target) {
+            Some(name) => write!(f, " <{}>", name),
+            None => Ok(()),
+        }
+    }
+
+    fn branch_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Relative to the delay slot
+        let offset = self.instruction.imm16_se() << 2;
+        match self.pc {
+            Some(pc) => self.target(f, pc.wrapping_add(4).wrapping_add(offset)),
+            None => {
+                let relative = offset as i32 + 4;
+                let sign = if relative < 0 { '-' } else { '+' };
+                write!(f, ".{}0x{:x}", sign, relative.unsigned_abs())
+            }
+        }
+    }
+
+    fn jump_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let low = self.instruction.imm_jump() << 2;
+        match self.pc {
+            Some(pc) => self.target(f, (pc.wrapping_add(4) & 0xf000_0000) | low),
+            None => write!(f, "0x{:07x}", low),
+        }
+    }
+
+    fn special(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let i = self.instruction;
+        let (rs, rt, rd) = (gpr(i.rs().0), gpr(i.rt().0), gpr(i.rd().0));
+
+        let mnemonic = match i.secondary_opcode() {
+            0x00 if i.value == 0 => return write!(f, "nop"),
+            0x00 => "sll",
+            0x02 => "srl",
+            0x03 => "sra",
+            0x04 => "sllv",
+            0x06 => "srlv",
+            0x07 => "srav",
+            0x08 => return write!(f, "jr {}", rs),
+            0x09 if i.rd().0 == 31 => return write!(f, "jalr {}", rs),
+            0x09 => return write!(f, "jalr {}, {}", rd, rs),
+            0x0c => return write!(f, "syscall 0x{:x}", (i.value >> 6) & 0xfffff),
+            0x0d => return write!(f, "break 0x{:x}", (i.value >> 6) & 0xfffff),
+            0x10 => return write!(f, "mfhi {}", rd),
+            0x11 => return write!(f, "mthi {}", rs),
+            0x12 => return write!(f, "mflo {}", rd),
+            0x13 => return write!(f, "mtlo {}", rs),
+            0x18 => "mult",
+            0x19 => "multu",
+            0x1a => "div",
+            0x1b => "divu",
+            0x21 | 0x25 if i.rt().0 == 0 => return write!(f, "move {}, {}", rd, rs),
+            0x23 if i.rs().0 == 0 => return write!(f, "negu {}, {}", rd, rt),
+            0x27 if i.rt().0 == 0 => return write!(f, "not {}, {}", rd, rs),
+            0x20 => "add",
+            0x21 => "addu",
+            0x22 => "sub",
+            0x23 => "subu",
+            0x24 => "and",
+            0x25 => "or",
+            0x26 => "xor",
+            0x27 => "nor",
+            0x2a => "slt",
+            0x2b => "sltu",
+            _ => return write!(f, "illegal 0x{:08x}", i.value),
+        };
+
+        match i.secondary_opcode() {
+            0x00..=0x03 => write!(f, "{} {}, {}, {}", mnemonic, rd, rt, i.imm5()),
+            0x04..=0x07 => write!(f, "{} {}, {}, {}", mnemonic, rd, rt, rs),
+            0x18..=0x1b => write!(f, "{} {}, {}", mnemonic, rs, rt),
+            _ => write!(f, "{} {}, {}, {}", mnemonic, rd, rs, rt),
+        }
+    }
+
+    /// COP0 and COP2 share the move encodings. Coprocessor registers are shown by
+    /// number.
+    fn cop(&self, f: &mut fmt::Formatter, n: u32) -> fmt::Result {
+        let i = self.instruction;
+        let (rt, rd) = (gpr(i.rt().0), i.rd().0);
+
+        if i.value & (1 << 25) != 0 {
+            let command = i.value & 0x1ff_ffff;
+            return match (n, gte_command(command & 0x3f)) {
+                (0, _) if command == 0x10 => write!(f, "rfe"),
+                (2, Some(name)) => write!(f, "{} 0x{:07x}", name, command),
+                _ => write!(f, "cop{} 0x{:07x}", n, command),
+            };
+        }
+
+        match i.cop_opcode() {
+            0x00 => write!(f, "mfc{} {}, ${}", n, rt, rd),
+            0x02 => write!(f, "cfc{} {}, ${}", n, rt, rd),
+            0x04 => write!(f, "mtc{} {}, ${}", n, rt, rd),
+            0x06 => write!(f, "ctc{} {}, ${}", n, rt, rd),
+            _ => write!(f, "illegal 0x{:08x}", i.value),
+        }
+    }
+}
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let i = self.instruction;
+        let (rs, rt) = (gpr(i.rs().0), gpr(i.rt().0));
+        let imm = i.imm16();
+
+        let (mnemonic, form) = match i.opcode() {
+            0x00 => return self.special(f),
+            0x01 => {
+                let mnemonic = match (i.rt().0 & 1 != 0, i.rt().0 & 0x1e == 0x10) {
+                    (false, false) => "bltz",
+                    (true, false) => "bgez",
+                    (false, true) => "bltzal",
+                    (true, true) => "bgezal",
+                };
+                write!(f, "{} {}, ", mnemonic, rs)?;
+                return self.branch_target(f);
+            }
+            0x02 => {
+                write!(f, "j ")?;
+                return self.jump_target(f);
+            }
+            0x03 => {
+                write!(f, "jal ")?;
+                return self.jump_target(f);
+            }
+            0x04 if i.rs().0 == 0 && i.rt().0 == 0 => {
+                write!(f, "b ")?;
+                return self.branch_target(f);
+            }
+            0x04 | 0x05 => {
+                match (i.opcode(), i.rt().0) {
+                    (0x04, 0) => write!(f, "beqz {}, ", rs)?,
+                    (0x05, 0) => write!(f, "bnez {}, ", rs)?,
+                    (0x04, _) => write!(f, "beq {}, {}, ", rs, rt)?,
+                    _ => write!(f, "bne {}, {}, ", rs, rt)?,
+                }
+                return self.branch_target(f);
+            }
+            0x06 | 0x07 => {
+                let mnemonic = if i.opcode() == 0x06 { "blez" } else { "bgtz" };
+                write!(f, "{} {}, ", mnemonic, rs)?;
+                return self.branch_target(f);
+            }
+            0x09 if i.rs().0 == 0 => return write!(f, "li {}, {}", rt, signed(imm)),
+            0x0d if i.rs().0 == 0 => return write!(f, "li {}, 0x{:x}", rt, imm),
+            0x0f => return write!(f, "lui {}, 0x{:x}", rt, imm),
+            0x08 => ("addi", Form::Signed),
+            0x09 => ("addiu", Form::Signed),
+            0x0a => ("slti", Form::Signed),
+            0x0b => ("sltiu", Form::Signed),
+            0x0c => ("andi", Form::Unsigned),
+            0x0d => ("ori", Form::Unsigned),
+            0x0e => ("xori", Form::Unsigned),
+            0x10 => return self.cop(f, 0),
+            0x11 => return self.cop(f, 1),
+            0x12 => return self.cop(f, 2),
+            0x13 => return self.cop(f, 3),
+            0x20 => ("lb", Form::Memory),
+            0x21 => ("lh", Form::Memory),
+            0x22 => ("lwl", Form::Memory),
+            0x23 => ("lw", Form::Memory),
+            0x24 => ("lbu", Form::Memory),
+            0x25 => ("lhu", Form::Memory),
+            0x26 => ("lwr", Form::Memory),
+            0x28 => ("sb", Form::Memory),
+            0x29 => ("sh", Form::Memory),
+            0x2a => ("swl", Form::Memory),
+            0x2b => ("sw", Form::Memory),
+            0x2e => ("swr", Form::Memory),
+            0x30 => ("lwc0", Form::CopMemory),
+            0x31 => ("lwc1", Form::CopMemory),
+            0x32 => ("lwc2", Form::CopMemory),
+            0x33 => ("lwc3", Form::CopMemory),
+            0x38 => ("swc0", Form::CopMemory),
+            0x39 => ("swc1", Form::CopMemory),
+            0x3a => ("swc2", Form::CopMemory),
+            0x3b => ("swc3", Form::CopMemory),
+            _ => return write!(f, "illegal 0x{:08x}", i.value),
+        };
+
+        match form {
+            Form::Signed => write!(f, "{} {}, {}, {}", mnemonic, rt, rs, signed(imm)),
+            Form::Unsigned => write!(f, "{} {}, {}, 0x{:x}", mnemonic, rt, rs, imm),
+            Form::Memory => write!(f, "{} {}, {}({})", mnemonic, rt, signed(imm), rs),
+            Form::CopMemory => write!(f, "{} ${}, {}({})", mnemonic, i.rt().0, signed(imm), rs),
+        }
+    }
+}
+
+/// Operand layouts of the I-type instructions.
+enum Form {
+    Signed,
+    Unsigned,
+    Memory,
+    CopMemory,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PC: u32 = 0x8001_0000;
+
+    #[test]
+    fn disassemble() {
+        let symbols: HashMap<u32, String> = [(PC, "start"), (0x8002_0000, "main")]
+            .into_iter()
+            .map(|(addr, name)| (addr, name.to_string()))
+            .collect();
+
+        let table = [
+            // Aliases
+            (0x0000_0000, "nop"),
+            (0x0080_1021, "move $v0, $a0"),
+            (0x0080_1025, "move $v0, $a0"),
+            (0x0005_1023, "negu $v0, $a1"),
+            (0x0080_1027, "not $v0, $a0"),
+            (0x2402_ffff, "li $v0, -0x1"),
+            (0x3402_8000, "li $v0, 0x8000"),
+            (0x1000_0003, "b 0x80010010"),
+            (0x1080_ffff, "beqz $a0, 0x80010000 <start>"),
+            (0x0100_f809, "jalr $t0"),
+            // Plain forms
+            (0x0085_1021, "addu $v0, $a0, $a1"),
+            (0x0004_1080, "sll $v0, $a0, 2"),
+            (0x03e0_0008, "jr $ra"),
+            (0x27bd_ffe8, "addiu $sp, $sp, -0x18"),
+            (0x8fbf_0014, "lw $ra, 0x14($sp)"),
+            (0x1485_0001, "bne $a0, $a1, 0x80010008"),
+            (0x0c00_8000, "jal 0x80020000 <main>"),
+            // BCONDZ: bit 0 of rt picks bgez, links only when rt is 0x10 or 0x11
+            (0x0480_0001, "bltz $a0, 0x80010008"),
+            (0x0481_0001, "bgez $a0, 0x80010008"),
+            (0x0490_0001, "bltzal $a0, 0x80010008"),
+            (0x0491_0001, "bgezal $a0, 0x80010008"),
+            (0x0483_0001, "bgez $a0, 0x80010008"),
+            (0x049e_0001, "bltz $a0, 0x80010008"),
+            // Coprocessors
+            (0x4200_0010, "rfe"),
+            (0x4008_6000, "mfc0 $t0, $12"),
+            (0x4888_4800, "mtc2 $t0, $9"),
+            (0xc880_0000, "lwc2 $0, 0x0($a0)"),
+            (0x4a18_0001, "rtps 0x0180001"),
+            (0x4b40_0006, "nclip 0x1400006"),
+            (0x4a48_6012, "mvmva 0x0486012"),
+            (0xfc00_0000, "illegal 0xfc000000"),
+        ];
+
+        for (value, text) in table {
+            let instruction = Instruction { value };
+            assert_eq!(
+                instruction.disassemble(PC, &symbols),
+                text,
+                "0x{:08x}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn relative_targets() {
+        assert_eq!(Instruction { value: 0x1000_0003 }.to_string(), "b .+0x10");
+        assert_eq!(
+            Instruction { value: 0x1080_fffd }.to_string(),
+            "beqz $a0, .-0x8"
+        );
+        assert_eq!(
+            Instruction { value: 0x0c00_8000 }.to_string(),
+            "jal 0x0020000"
+        );
+    }
+}
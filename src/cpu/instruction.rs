@@ -1,5 +1,3 @@
-use core::fmt;
-
 #[derive(Clone, Copy)]
 pub struct RegisterIndex(pub u32);
 
@@ -48,97 +46,4 @@ impl Instruction {
     pub fn imm_jump(&self) -> u32 {
         self.value & 0x3ffffff
     }
-
-    fn instruction(&self) -> &'static str {
-        match self.opcode() {
-            0x00 => match self.secondary_opcode() {
-                0x00 => "sll",
-                0x02 => "srl",
-                0x03 => "sra",
-                0x04 => "sllv",
-                0x08 => "jr",
-                0x06 => "srlv",
-                0x07 => "srav",
-                0x09 => "jalr",
-                0x0C => "syscall",
-                0x0D => "break",
-                0x10 => "mfhi",
-                0x11 => "mthi",
-                0x12 => "mflo",
-                0x13 => "mtlo",
-                0x18 => "mult",
-                0x19 => "multu",
-                0x1A => "div",
-                0x1B => "divu",
-                0x20 => "add",
-                0x21 => "addu",
-                0x22 => "sub",
-                0x23 => "subu",
-                0x24 => "and",
-                0x25 => "or",
-                0x26 => "xor",
-                0x27 => "nor",
-                0x2A => "slt",
-                0x2B => "sltu",
-                _ => "Invalid secondary opcode",
-            },
-            0x01 => "bcondz",
-            0x02 => "j",
-            0x03 => "jal",
-            0x04 => "beq",
-            0x05 => "bne",
-            0x06 => "blez",
-            0x07 => "bgtz",
-            0x08 => "addi",
-            0x09 => "addiu",
-            0x0A => "slti",
-            0x0B => "sltiu",
-            0x0C => "andi",
-            0x0D => "ori",
-            0x0E => "xori",
-            0x0F => "lui",
-
-            0x10 => match self.cop_opcode() {
-                0x00 => "mfc0",
-                0x04 => "mtc0",
-                0x10 => "rfe",
-                _ => "Invalid cop0 opcode",
-            },
-            0x11 => "cop1",
-            0x12 => "gte",
-            0x13 => "cop3",
-
-            0x20 => "lb",
-            0x21 => "lh",
-            0x22 => "lwl",
-            0x23 => "lw",
-            0x24 => "lbu",
-            0x25 => "lhu",
-            0x26 => "lwr",
-
-            0x28 => "sb",
-            0x29 => "sh",
-            0x2A => "swl",
-            0x2B => "sw",
-            0x2E => "swr",
-
-            0x30 => "lwc0",
-            0x31 => "lwc1",
-            0x32 => "lwc2",
-            0x33 => "lwc3",
-
-            0x38 => "swc0",
-            0x39 => "swc1",
-            0x3A => "swc2",
-            0x3B => "swc3",
-
-            _ => "Invalid opcode",
-        }
-    }
-}
-
-impl fmt::Display for Instruction {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "0x{:08X} - {}", self.value, self.instruction())
-    }
 }
@@ -1,26 +1,50 @@
+mod cache;
+mod cop0;
+mod disasm;
 mod instruction;
+#[cfg(test)]
+mod tests;
 
-use self::instruction::{Instruction, RegisterIndex};
+use self::cache::{CacheControl, ICache, CACHE_CONTROL, UNCACHED_FETCH_CYCLES};
+use self::cop0::{DebugRegisters, PRID};
+use self::instruction::RegisterIndex;
+use crate::error::Error;
 use crate::memory::Bus;
 use crate::memory::BIOS_START;
 use crate::renderer::Renderer;
-use crate::utils::Error;
+use crate::savestate::{StateReader, StateWriter};
+use crate::utils;
 use log::debug;
-use std::string::String;
+
+pub use self::disasm::{NoSymbols, Symbols};
+pub use self::instruction::Instruction;
+
+/// Conventional names of the general purpose registers.
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
 
 pub struct CPU<R: Renderer> {
     pc: u32,
     current_pc: u32,
     next_pc: u32,
     counter: u32,
+    cycles: u64,
     pending_load: (RegisterIndex, u32),
     bus: Bus<R>,
 
+    icache: ICache,
+    cache_control: CacheControl,
+
     sr: u32,
     hi: u32,
     lo: u32,
     cause: u32,
     epc: u32,
+    badvaddr: u32,
+    debug: DebugRegisters,
 
     branch: bool,
     delay: bool,
@@ -28,14 +52,15 @@ pub struct CPU<R: Renderer> {
     registers: [u32; 32],
 }
 
-macro_rules! ignore_cache {
-    ($self:ident) => {
-        if $self.sr & 0x10000 != 0 {
-            trace!("Ignoring load call while cache is isolated.");
-            return Ok(());
-        }
-    };
-}
+// SR bit 16: loads and stores go to the cache instead of memory
+const SR_ISOLATE_CACHE: u32 = 1 << 16;
+// SR bit 22: exception vectors in the BIOS instead of RAM
+const SR_BOOT_VECTORS: u32 = 1 << 22;
+
+// CAUSE bits 8-9 are the two software interrupts, the only writable ones
+const CAUSE_SOFTWARE_INTERRUPTS: u32 = 0x300;
+// CAUSE bit 10 follows the interrupt controller's output
+const CAUSE_HARDWARE_INTERRUPT: u32 = 0x400;
 
 impl<R: Renderer> CPU<R> {
     pub fn new(bus: Bus<R>) -> Self {
@@ -48,13 +73,19 @@ impl<R: Renderer> CPU<R> {
             next_pc: BIOS_START.wrapping_add(4),
             bus,
             counter: 0,
+            cycles: 0,
             pending_load: (RegisterIndex(0), 0),
 
+            icache: ICache::new(),
+            cache_control: CacheControl(0),
+
             sr: 0,
             hi: 0x42042069,
             lo: 0x42042069,
-            cause: 0x69696969,
+            cause: 0,
             epc: 0xB00B1E5,
+            badvaddr: 0,
+            debug: DebugRegisters::new(),
 
             branch: false,
             delay: false,
@@ -63,12 +94,265 @@ impl<R: Renderer> CPU<R> {
         }
     }
 
-    fn load<T: TryFrom<u32>>(&self, addr: u32) -> Result<T, String> {
-        self.bus.load(addr)
+    /// Returns None if the access raised an exception, the instruction must stop there.
+    fn load<T: TryFrom<u32>>(&mut self, addr: u32) -> Result<Option<T>, Error> {
+        if !addr.is_multiple_of(std::mem::size_of::<T>() as u32) {
+            self.address_error(Exception::AddressErrorLoad, addr);
+            return Ok(None);
+        }
+
+        if self.debug.check_data(addr, false) {
+            self.debug_exception();
+            return Ok(None);
+        }
+
+        if addr == CACHE_CONTROL {
+            return Ok(Some(utils::to_t(self.cache_control.0)));
+        }
+
+        if self.sr & SR_ISOLATE_CACHE != 0 {
+            let word = self.icache.isolated_load(addr);
+            let bits = std::mem::size_of::<T>() as u32 * 8;
+            let value = (word >> ((addr & 3) * 8)) & (u32::MAX >> (32 - bits));
+            return Ok(Some(utils::to_t(value)));
+        }
+
+        match self.bus.load(addr) {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::Unmapped { addr, .. }) => {
+                debug!("Bus error loading from 0x{:08X}", addr);
+                self.exception(Exception::BusErrorLoad);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn store<T: Into<u32> + Copy>(&mut self, addr: u32, value: T) -> Result<(), Error> {
+        if !addr.is_multiple_of(std::mem::size_of::<T>() as u32) {
+            self.address_error(Exception::AddressErrorStore, addr);
+            return Ok(());
+        }
+
+        if self.debug.check_data(addr, true) {
+            self.debug_exception();
+            return Ok(());
+        }
+
+        if addr == CACHE_CONTROL {
+            self.cache_control = CacheControl(value.into());
+            return Ok(());
+        }
+
+        if self.sr & SR_ISOLATE_CACHE != 0 {
+            self.icache
+                .isolated_store(self.cache_control, addr, value.into());
+            return Ok(());
+        }
+
+        match self.bus.store(addr, value) {
+            Ok(()) => Ok(()),
+            // The data bus error code covers stores as well
+            Err(Error::Unmapped { addr, .. }) => {
+                debug!("Bus error storing to 0x{:08X}", addr);
+                Ok(self.exception(Exception::BusErrorLoad))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// KUSEG and KSEG0 fetches go through the instruction cache when it's enabled,
+    /// KSEG1 and KSEG2 always go to the bus.
+    fn fetch_instruction(&mut self, addr: u32) -> Result<u32, Error> {
+        let cached = addr < 0xa000_0000 && self.cache_control.icache_enabled();
+        if !cached {
+            self.cycles += UNCACHED_FETCH_CYCLES;
+            return self.bus.fetch(addr);
+        }
+
+        if let Some(word) = self.icache.lookup(addr) {
+            return Ok(word);
+        }
+
+        let bus = &mut self.bus;
+        self.cycles += self.icache.refill(addr, |a| bus.fetch(a))?;
+        Ok(self.icache.isolated_load(addr))
     }
 
-    fn store<T: Into<u32>>(&mut self, addr: u32, value: T) -> Result<(), String> {
-        self.bus.store(addr, value)
+    /// Reset line: jumps back to the BIOS entry point with the cache and COP0 in
+    /// their power-on state, and resets the hardware on the bus. The instruction
+    /// and cycle counters keep going.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.pc = BIOS_START;
+        self.current_pc = BIOS_START;
+        self.next_pc = BIOS_START.wrapping_add(4);
+        self.pending_load = (RegisterIndex(0), 0);
+
+        self.icache = ICache::new();
+        self.cache_control = CacheControl(0);
+
+        self.sr = 0;
+        self.cause = 0;
+        self.badvaddr = 0;
+        self.debug = DebugRegisters::new();
+
+        self.branch = false;
+        self.delay = false;
+
+        self.bus.reset()
+    }
+
+    /// CPU clock cycles since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn bus(&self) -> &Bus<R> {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus<R> {
+        &mut self.bus
+    }
+
+    /// Address of the next instruction to execute.
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    /// Jumps to `pc`, dropping any branch in progress.
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+        self.next_pc = pc.wrapping_add(4);
+        self.branch = false;
+    }
+
+    /// Whether the next instruction is in a branch delay slot.
+    pub fn in_delay_slot(&self) -> bool {
+        self.branch
+    }
+
+    pub fn registers(&self) -> &[u32; 32] {
+        &self.registers
+    }
+
+    /// Sets a general purpose register, bypassing any pending load. Writes to
+    /// $zero are ignored.
+    pub fn set_gpr(&mut self, index: usize, value: u32) {
+        self.registers[index] = value;
+        self.registers[0] = 0;
+    }
+
+    pub fn hi(&self) -> u32 {
+        self.hi
+    }
+
+    pub fn set_hi(&mut self, value: u32) {
+        self.hi = value;
+    }
+
+    pub fn lo(&self) -> u32 {
+        self.lo
+    }
+
+    pub fn set_lo(&mut self, value: u32) {
+        self.lo = value;
+    }
+
+    /// None for the COP0 registers that don't exist.
+    pub fn cop0_register(&self, index: u32) -> Option<u32> {
+        let value = match index {
+            3 => self.debug.bpc,
+            5 => self.debug.bda,
+            6 => self.debug.jumpdest,
+            7 => self.debug.dcic,
+            8 => self.badvaddr,
+            9 => self.debug.bdam,
+            11 => self.debug.bpcm,
+            12 => self.sr,
+            13 => self.cause,
+            14 => self.epc,
+            15 => PRID,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Returns false for the COP0 registers that don't exist. Writes to the
+    /// read-only ones are ignored.
+    pub fn set_cop0_register(&mut self, index: u32, value: u32) -> bool {
+        match index {
+            3 => self.debug.bpc = value,
+            5 => self.debug.bda = value,
+            7 => self.debug.dcic = value,
+            9 => self.debug.bdam = value,
+            11 => self.debug.bpcm = value,
+            12 => self.sr = value,
+            13 => {
+                self.cause &= !CAUSE_SOFTWARE_INTERRUPTS;
+                self.cause |= value & CAUSE_SOFTWARE_INTERRUPTS;
+            }
+            14 => self.epc = value,
+            // JUMPDEST, BadVaddr and PRId are read-only
+            6 | 8 | 15 => (),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Everything down to the load delay and branch delay slots, then the bus.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.pc);
+        w.u32(self.current_pc);
+        w.u32(self.next_pc);
+        w.u32(self.counter);
+        w.u64(self.cycles);
+        w.u32(self.pending_load.0 .0);
+        w.u32(self.pending_load.1);
+
+        self.icache.save_state(w);
+        w.u32(self.cache_control.0);
+
+        w.u32(self.sr);
+        w.u32(self.hi);
+        w.u32(self.lo);
+        w.u32(self.cause);
+        w.u32(self.epc);
+        w.u32(self.badvaddr);
+        self.debug.save_state(w);
+
+        w.bool(self.branch);
+        w.bool(self.delay);
+        w.u32s(&self.registers);
+
+        self.bus.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.pc = r.u32()?;
+        self.current_pc = r.u32()?;
+        self.next_pc = r.u32()?;
+        self.counter = r.u32()?;
+        self.cycles = r.u64()?;
+        self.pending_load = (RegisterIndex(r.u32()? & 0x1f), r.u32()?);
+
+        self.icache.load_state(r)?;
+        self.cache_control = CacheControl(r.u32()?);
+
+        self.sr = r.u32()?;
+        self.hi = r.u32()?;
+        self.lo = r.u32()?;
+        self.cause = r.u32()?;
+        self.epc = r.u32()?;
+        self.badvaddr = r.u32()?;
+        self.debug.load_state(r)?;
+
+        self.branch = r.bool()?;
+        self.delay = r.bool()?;
+        r.u32s(&mut self.registers)?;
+        self.registers[0] = 0;
+
+        self.bus.load_state(r)
     }
 
     fn register(&self, index: RegisterIndex) -> u32 {
@@ -102,7 +386,7 @@ impl<R: Renderer> CPU<R> {
         self.branch = true;
     }
 
-    fn decode_and_execute(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn decode_and_execute(&mut self, instruction: Instruction) -> Result<(), Error> {
         self.counter += 1;
         trace!(
             "({}): Executing instruction: 0x{:02X}",
@@ -170,10 +454,9 @@ impl<R: Renderer> CPU<R> {
                 0x00 => self.op_mfc0(instruction),
                 0x04 => self.op_mtc0(instruction),
                 0x10 => self.op_rfe(instruction),
-                _ => Error!(
-                    "Unhandled cop0 instruction: 0x{:08X}",
-                    instruction.cop_opcode()
-                ),
+                _ => Err(Error::UnimplementedInstruction {
+                    word: instruction.value,
+                }),
             },
 
             0x11 => Ok(self.exception(Exception::CoprocessorError)), // COP1
@@ -208,7 +491,7 @@ impl<R: Renderer> CPU<R> {
         };
     }
 
-    fn op_bcondz(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_bcondz(&mut self, instruction: Instruction) -> Result<(), Error> {
         let imm = instruction.imm16_se();
         let source = self.register(instruction.rs()) as i32;
 
@@ -231,7 +514,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_j(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_j(&mut self, instruction: Instruction) -> Result<(), Error> {
         let imm = instruction.imm_jump() << 2;
         self.next_pc = (self.next_pc & 0xf0000000) | imm;
         self.branch = true;
@@ -239,7 +522,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_jal(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_jal(&mut self, instruction: Instruction) -> Result<(), Error> {
         let ra = self.next_pc;
         self.op_j(instruction)?;
         self.set_register(RegisterIndex(31), ra);
@@ -247,7 +530,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_beq(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_beq(&mut self, instruction: Instruction) -> Result<(), Error> {
         let branch_offset = instruction.imm16_se();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -260,7 +543,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_bne(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_bne(&mut self, instruction: Instruction) -> Result<(), Error> {
         let branch_offset = instruction.imm16_se();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -272,7 +555,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_blez(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_blez(&mut self, instruction: Instruction) -> Result<(), Error> {
         let branch_offset = instruction.imm16_se();
         let left = self.register(instruction.rs()) as i32;
 
@@ -283,7 +566,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_bgtz(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_bgtz(&mut self, instruction: Instruction) -> Result<(), Error> {
         let branch_offset = instruction.imm16_se();
         let left = self.register(instruction.rs()) as i32;
 
@@ -294,7 +577,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_slt(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_slt(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs()) as i32;
         let right = self.register(instruction.rt()) as i32;
@@ -304,7 +587,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, (left < right) as u32))
     }
 
-    fn op_sltu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_sltu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -315,26 +598,26 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, result as u32))
     }
 
-    fn op_cop2(&mut self, instruction: Instruction) -> Result<(), String> {
-        Error!("Unimplemented cop2 opcode: {}", instruction)
+    fn op_cop2(&mut self, instruction: Instruction) -> Result<(), Error> {
+        Err(Error::UnimplementedInstruction {
+            word: instruction.value,
+        })
     }
 
-    fn op_mfc0(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_mfc0(&mut self, instruction: Instruction) -> Result<(), Error> {
         let cpu_r = instruction.rt();
         let cop_r = instruction.rd();
 
-        let value = match cop_r.0 {
-            12 => self.sr,
-            13 => self.cause,
-            14 => self.epc,
-            _ => return Error!("Unhandled read from cop0 register."),
+        let Some(value) = self.cop0_register(cop_r.0) else {
+            self.exception(Exception::IllegalInstruction);
+            return Ok(());
         };
 
         self.delayed_load_chain(cpu_r, value);
         Ok(())
     }
 
-    fn op_mtc0(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_mtc0(&mut self, instruction: Instruction) -> Result<(), Error> {
         let cpu_r = instruction.rt();
         let cop_r = instruction.rd();
 
@@ -342,23 +625,17 @@ impl<R: Renderer> CPU<R> {
 
         self.delayed_load();
 
-        match cop_r {
-            RegisterIndex(3 | 5 | 6 | 7 | 9 | 11) => {
-                if value != 0 {
-                    return Error!("Unhandled write to cop0 register.");
-                }
-            }
-            RegisterIndex(12) => self.sr = value,
-            RegisterIndex(13) => self.cause = value,
-            RegisterIndex(14) => self.epc = value,
-            _ => return Error!("Unhandled cop0 register."),
+        if !self.set_cop0_register(cop_r.0, value) {
+            self.exception(Exception::IllegalInstruction);
         }
         Ok(())
     }
 
-    fn op_rfe(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_rfe(&mut self, instruction: Instruction) -> Result<(), Error> {
         if instruction.value & 0x3f != 0x10 {
-            return Error!("Invalid cop0 instruction.");
+            return Err(Error::UnimplementedInstruction {
+                word: instruction.value,
+            });
         }
 
         self.delayed_load();
@@ -371,7 +648,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_sll(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_sll(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let value = self.register(instruction.rt());
         let shift = instruction.imm5();
@@ -381,7 +658,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, value << shift))
     }
 
-    fn op_srl(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_srl(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let value = self.register(instruction.rt());
         let shift = instruction.imm5();
@@ -391,7 +668,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, value >> shift))
     }
 
-    fn op_sra(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_sra(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let shift = instruction.imm5();
         let value = (self.register(instruction.rt()) as i32) >> shift;
@@ -401,7 +678,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, value as u32))
     }
 
-    fn op_sllv(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_sllv(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let shift = self.register(instruction.rs());
         let value = self.register(instruction.rt()) << shift & 0x1f;
@@ -411,7 +688,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, value))
     }
 
-    fn op_srlv(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_srlv(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let shift = self.register(instruction.rs());
         let value = (self.register(instruction.rt()) as u32) >> shift & 0x1f;
@@ -421,7 +698,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, value as u32))
     }
 
-    fn op_srav(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_srav(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let shift = self.register(instruction.rs());
         let value = (self.register(instruction.rt()) as i32) >> shift & 0x1f;
@@ -431,7 +708,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, value as u32))
     }
 
-    fn op_jr(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_jr(&mut self, instruction: Instruction) -> Result<(), Error> {
         let value = instruction.rs();
         self.next_pc = self.register(value);
         self.branch = true;
@@ -439,7 +716,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_jalr(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_jalr(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let value = self.register(instruction.rs());
         let ra = self.next_pc;
@@ -453,15 +730,15 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_syscall(&mut self, _instruction: Instruction) -> Result<(), String> {
+    fn op_syscall(&mut self, _instruction: Instruction) -> Result<(), Error> {
         Ok(self.exception(Exception::Syscall))
     }
 
-    fn op_break(&mut self, _instruction: Instruction) -> Result<(), String> {
+    fn op_break(&mut self, _instruction: Instruction) -> Result<(), Error> {
         Ok(self.exception(Exception::Break))
     }
 
-    fn op_mfhi(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_mfhi(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let hi = self.hi;
 
@@ -470,14 +747,14 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, hi))
     }
 
-    fn op_mthi(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_mthi(&mut self, instruction: Instruction) -> Result<(), Error> {
         let source = self.register(instruction.rs());
         self.hi = source;
         self.delayed_load();
         Ok(())
     }
 
-    fn op_mflo(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_mflo(&mut self, instruction: Instruction) -> Result<(), Error> {
         let destination = instruction.rd();
         let lo = self.lo;
 
@@ -486,14 +763,14 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(destination, lo))
     }
 
-    fn op_mtlo(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_mtlo(&mut self, instruction: Instruction) -> Result<(), Error> {
         let source = self.register(instruction.rs());
         self.lo = source;
         self.delayed_load();
         Ok(())
     }
 
-    fn op_mult(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_mult(&mut self, instruction: Instruction) -> Result<(), Error> {
         let a = self.register(instruction.rs()) as i64;
         let b = self.register(instruction.rt()) as i64;
 
@@ -504,7 +781,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_multu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_multu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let a = self.register(instruction.rs()) as u64;
         let b = self.register(instruction.rt()) as u64;
 
@@ -515,7 +792,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_div(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_div(&mut self, instruction: Instruction) -> Result<(), Error> {
         let dimmadome = self.register(instruction.rs()) as i32;
         let divisor = self.register(instruction.rt()) as i32;
 
@@ -539,7 +816,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_divu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_divu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let dimmadome = self.register(instruction.rs());
         let divisor = self.register(instruction.rt());
 
@@ -555,7 +832,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_add(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_add(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs()) as i32;
         let right = self.register(instruction.rt()) as i32;
@@ -569,7 +846,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_addu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_addu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -579,7 +856,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, left.wrapping_add(right)))
     }
 
-    fn op_sub(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_sub(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs()) as i32;
         let right = self.register(instruction.rt()) as i32;
@@ -593,7 +870,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_subu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_subu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -603,7 +880,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, left.wrapping_sub(right)))
     }
 
-    fn op_and(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_and(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -613,7 +890,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, left & right))
     }
 
-    fn op_or(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_or(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -623,7 +900,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, left | right))
     }
 
-    fn op_xor(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_xor(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -633,7 +910,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, left ^ right))
     }
 
-    fn op_nor(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_nor(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rd();
         let left = self.register(instruction.rs());
         let right = self.register(instruction.rt());
@@ -643,7 +920,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, !(left | right)))
     }
 
-    fn op_addi(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_addi(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rt();
         let imm = instruction.imm16_se() as i32;
         let source = self.register(instruction.rs()) as i32;
@@ -657,7 +934,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_addiu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_addiu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rt();
         let imm = instruction.imm16_se();
         let source = self.register(instruction.rs());
@@ -667,7 +944,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, source.wrapping_add(imm)))
     }
 
-    fn op_slti(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_slti(&mut self, instruction: Instruction) -> Result<(), Error> {
         let imm = instruction.imm16_se() as i32;
         let source = self.register(instruction.rs()) as i32;
         let target = instruction.rt();
@@ -677,7 +954,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, (source < imm) as u32))
     }
 
-    fn op_sltiu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_sltiu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let imm = instruction.imm16_se();
         let source = self.register(instruction.rs());
         let target = instruction.rt();
@@ -687,7 +964,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, (source < imm) as u32))
     }
 
-    fn op_andi(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_andi(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rt();
         let imm = instruction.imm16();
         let source = instruction.rs();
@@ -697,7 +974,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, self.register(source) & imm))
     }
 
-    fn op_ori(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_ori(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rt();
         let imm = instruction.imm16();
         let source = instruction.rs();
@@ -707,7 +984,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, self.register(source) | imm))
     }
 
-    fn op_xori(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_xori(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rt();
         let imm = instruction.imm16();
         let source = instruction.rs();
@@ -717,7 +994,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, self.register(source) ^ imm))
     }
 
-    fn op_lui(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_lui(&mut self, instruction: Instruction) -> Result<(), Error> {
         let target = instruction.rt();
         let value = instruction.imm16();
 
@@ -726,9 +1003,7 @@ impl<R: Renderer> CPU<R> {
         Ok(self.set_register(target, value << 16))
     }
 
-    fn op_sb(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_sb(&mut self, instruction: Instruction) -> Result<(), Error> {
         let value = self.register(instruction.rt());
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
@@ -738,48 +1013,36 @@ impl<R: Renderer> CPU<R> {
         self.store::<u8>(base.wrapping_add(offset), value as u8)
     }
 
-    fn op_sh(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_sh(&mut self, instruction: Instruction) -> Result<(), Error> {
         let value = self.register(instruction.rt());
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
 
         self.delayed_load();
 
-        if offset % 2 == 0 {
-            self.store::<u16>(base.wrapping_add(offset), value as u16)
-        } else {
-            Ok(self.exception(Exception::AddressErrorStore))
-        }
+        self.store::<u16>(base.wrapping_add(offset), value as u16)
     }
 
-    fn op_sw(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_sw(&mut self, instruction: Instruction) -> Result<(), Error> {
         let value = self.register(instruction.rt());
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
 
         self.delayed_load();
 
-        if offset % 4 == 0 {
-            self.store::<u32>(base.wrapping_add(offset), value)
-        } else {
-            Ok(self.exception(Exception::AddressErrorStore))
-        }
+        self.store::<u32>(base.wrapping_add(offset), value)
     }
 
-    fn op_swl(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_swl(&mut self, instruction: Instruction) -> Result<(), Error> {
         let value = self.register(instruction.rt());
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
 
         let addr = base.wrapping_add(offset);
         let aligned_addr = addr & !0b11;
-        let aligned_word = self.load::<u32>(aligned_addr)?;
+        let Some(aligned_word) = self.load::<u32>(aligned_addr)? else {
+            return Ok(());
+        };
 
         let new_val = match addr & 0b11 {
             0 => (aligned_word & 0xffff_ff00) | (value >> 24),
@@ -794,16 +1057,16 @@ impl<R: Renderer> CPU<R> {
         self.store::<u32>(aligned_addr, new_val)
     }
 
-    fn op_swr(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_swr(&mut self, instruction: Instruction) -> Result<(), Error> {
         let value = self.register(instruction.rt());
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
 
         let addr = base.wrapping_add(offset);
         let aligned_addr = addr & !0b11;
-        let aligned_word = self.load::<u32>(aligned_addr)?;
+        let Some(aligned_word) = self.load::<u32>(aligned_addr)? else {
+            return Ok(());
+        };
 
         let new_val = match addr & 0b11 {
             0 => (aligned_word & 0x0000_0000) | (value << 0),
@@ -818,51 +1081,43 @@ impl<R: Renderer> CPU<R> {
         self.store::<u32>(aligned_addr, new_val)
     }
 
-    fn op_lb(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_lb(&mut self, instruction: Instruction) -> Result<(), Error> {
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
         let target_index = instruction.rt();
 
-        let value = (self.load::<u8>(base.wrapping_add(offset))? as i8) as i32;
-        self.delayed_load_chain(target_index, value as u32);
+        let Some(value) = self.load::<u8>(base.wrapping_add(offset))? else {
+            return Ok(());
+        };
+        self.delayed_load_chain(target_index, value as i8 as u32);
         Ok(())
     }
 
-    fn op_lh(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_lh(&mut self, instruction: Instruction) -> Result<(), Error> {
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
         let target_index = instruction.rt();
 
-        if offset % 2 == 0 {
-            let value = (self.load::<u16>(base.wrapping_add(offset))? as i16) as i32;
-            self.delayed_load_chain(target_index, value as u32);
-            Ok(())
-        } else {
-            Ok(self.exception(Exception::AddressErrorLoad))
-        }
+        let Some(value) = self.load::<u16>(base.wrapping_add(offset))? else {
+            return Ok(());
+        };
+        self.delayed_load_chain(target_index, value as i16 as u32);
+        Ok(())
     }
 
-    fn op_lw(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_lw(&mut self, instruction: Instruction) -> Result<(), Error> {
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
         let target_index = instruction.rt();
 
-        if offset % 4 == 0 {
-            let value = self.load::<u32>(base.wrapping_add(offset))?;
-            self.delayed_load_chain(target_index, value as u32);
-            Ok(())
-        } else {
-            Ok(self.exception(Exception::AddressErrorLoad))
-        }
+        let Some(value) = self.load::<u32>(base.wrapping_add(offset))? else {
+            return Ok(());
+        };
+        self.delayed_load_chain(target_index, value);
+        Ok(())
     }
 
-    fn op_lwl(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_lwl(&mut self, instruction: Instruction) -> Result<(), Error> {
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
         let addr = base.wrapping_add(offset);
@@ -877,7 +1132,9 @@ impl<R: Renderer> CPU<R> {
         };
 
         let aligned_addr = addr & !0x3;
-        let aligned_word = self.load::<u32>(aligned_addr)?;
+        let Some(aligned_word) = self.load::<u32>(aligned_addr)? else {
+            return Ok(());
+        };
 
         let value = match addr & 0b11 {
             0 => (cur_v & 0x00FF_FFFF) | (aligned_word << 24),
@@ -890,9 +1147,7 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_lwr(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_lwr(&mut self, instruction: Instruction) -> Result<(), Error> {
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
         let addr = base.wrapping_add(offset);
@@ -907,7 +1162,9 @@ impl<R: Renderer> CPU<R> {
         };
 
         let aligned_addr = addr & !0x3;
-        let aligned_word = self.load::<u32>(aligned_addr)?;
+        let Some(aligned_word) = self.load::<u32>(aligned_addr)? else {
+            return Ok(());
+        };
 
         let value = match addr & 0b11 {
             0 => (cur_v & 0x0000_0000) | (aligned_word >> 0),
@@ -920,43 +1177,67 @@ impl<R: Renderer> CPU<R> {
         Ok(())
     }
 
-    fn op_lbu(&mut self, instruction: Instruction) -> Result<(), String> {
+    fn op_lbu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
         let target_index = instruction.rt();
 
-        let value = self.load::<u8>(base.wrapping_add(offset))?;
+        let Some(value) = self.load::<u8>(base.wrapping_add(offset))? else {
+            return Ok(());
+        };
         self.delayed_load_chain(target_index, value as u32);
         Ok(())
     }
 
-    fn op_lhu(&mut self, instruction: Instruction) -> Result<(), String> {
-        ignore_cache!(self);
-
+    fn op_lhu(&mut self, instruction: Instruction) -> Result<(), Error> {
         let base = instruction.imm16_se();
         let offset = self.register(instruction.rs());
         let target_index = instruction.rt();
 
-        let value = self.load::<u16>(base.wrapping_add(offset))?;
+        let Some(value) = self.load::<u16>(base.wrapping_add(offset))? else {
+            return Ok(());
+        };
         self.delayed_load_chain(target_index, value as u32);
         Ok(())
     }
 
-    fn op_lwc2(&mut self, instruction: Instruction) -> Result<(), String> {
-        Error!("Unimplemented instruction: {}", instruction)
+    fn op_lwc2(&mut self, instruction: Instruction) -> Result<(), Error> {
+        Err(Error::UnimplementedInstruction {
+            word: instruction.value,
+        })
     }
 
-    fn op_swc2(&mut self, instruction: Instruction) -> Result<(), String> {
-        Error!("Unimplemented instruction: {}", instruction)
+    fn op_swc2(&mut self, instruction: Instruction) -> Result<(), Error> {
+        Err(Error::UnimplementedInstruction {
+            word: instruction.value,
+        })
     }
 
-    fn exception(&mut self, cause: Exception) {
-        self.delayed_load();
+    /// Address errors also latch the offending address in BadVaddr.
+    fn address_error(&mut self, cause: Exception, addr: u32) {
+        self.badvaddr = addr;
+        self.exception(cause);
+    }
 
-        let handler = match self.sr & (1 << 22) != 0 {
-            true => 0x80000180,
+    fn exception(&mut self, cause: Exception) {
+        let handler = match self.sr & SR_BOOT_VECTORS != 0 {
+            true => 0xbfc00180,
             false => 0x80000080,
         };
+        self.enter_exception(cause, handler);
+    }
+
+    /// Hardware breakpoint hit, these have their own vector.
+    fn debug_exception(&mut self) {
+        let handler = match self.sr & SR_BOOT_VECTORS != 0 {
+            true => 0xbfc00140,
+            false => 0x80000040,
+        };
+        self.enter_exception(Exception::Break, handler);
+    }
+
+    fn enter_exception(&mut self, cause: Exception, handler: u32) {
+        self.delayed_load();
 
         // <magic version=1>
         let mode = self.sr & 0x3f;
@@ -965,10 +1246,11 @@ impl<R: Renderer> CPU<R> {
         // </magic>
 
         self.cause &= !0x7c;
-        self.cause = (cause as u32) << 2;
+        self.cause |= (cause as u32) << 2;
 
+        // EPC points to the branch when the exception hits its delay slot
         if self.delay {
-            self.epc = self.current_pc.wrapping_add(4);
+            self.epc = self.current_pc.wrapping_sub(4);
             self.cause |= 1 << 31;
         } else {
             self.epc = self.current_pc;
@@ -979,29 +1261,61 @@ impl<R: Renderer> CPU<R> {
         self.next_pc = self.pc.wrapping_add(4);
     }
 
-    pub fn exec_next_instruction(&mut self) {
+    fn interrupt_pending(&self) -> bool {
+        let enabled = self.sr & 1 != 0;
+        enabled && self.cause & self.sr & 0xff00 != 0
+    }
+
+    /// Errors are things the emulator can't handle, the guest's own faults become
+    /// exceptions.
+    pub fn exec_next_instruction(&mut self) -> Result<(), Error> {
         self.current_pc = self.pc;
 
+        self.delay = self.branch;
+        self.branch = false;
+
+        self.cause &= !CAUSE_HARDWARE_INTERRUPT;
+        if self.bus.interrupt_pending() {
+            self.cause |= CAUSE_HARDWARE_INTERRUPT;
+        }
+        if self.interrupt_pending() {
+            self.exception(Exception::Interrupt);
+            return Ok(());
+        }
+
+        if self.debug.check_execution(self.current_pc) {
+            self.debug_exception();
+            return Ok(());
+        }
+
         if self.current_pc % 4 != 0 {
-            self.exception(Exception::AddressErrorLoad);
-            return;
+            self.address_error(Exception::AddressErrorLoad, self.current_pc);
+            return Ok(());
         }
 
-        let instruction = Instruction {
-            value: self
-                .load::<u32>(self.pc)
-                .expect("Failed to load instruction from self.pc"),
+        let instruction = match self.fetch_instruction(self.pc) {
+            Ok(value) => Instruction { value },
+            Err(Error::Unmapped { addr, .. }) => {
+                debug!("Bus error fetching from 0x{:08X}", addr);
+                self.exception(Exception::BusErrorFetch);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
         };
+        self.cycles += 1;
 
         self.pc = self.next_pc;
         self.next_pc = self.next_pc.wrapping_add(4);
 
-        self.delay = self.branch;
-        self.branch = false;
+        if let Err(e) = self.decode_and_execute(instruction) {
+            self.report_error(instruction, &e);
+            return Err(e);
+        }
 
-        if let Err(msg) = self.decode_and_execute(instruction) {
-            self.panic_message(instruction, msg.as_str());
+        if self.branch {
+            self.debug.jumpdest = self.next_pc;
         }
+        Ok(())
     }
 
     #[allow(dead_code)]
@@ -1012,18 +1326,22 @@ impl<R: Renderer> CPU<R> {
         }
     }
 
-    fn panic_message(&self, instruction: Instruction, message: &str) {
+    /// Where the error happened, for the debug log. Reporting the error itself is
+    /// up to the caller, which may well carry on after it.
+    fn report_error(&self, instruction: Instruction, error: &Error) {
+        debug!(
+            "{} at 0x{:08X}: 0x{:08X} {}, after {} instructions ({} cycles)",
+            error,
+            self.current_pc,
+            instruction.value,
+            instruction.disassemble(self.current_pc, &NoSymbols),
+            self.counter,
+            self.cycles
+        );
         self.dump_registers();
-        eprintln!("----------------------------------------------------------------");
-        eprintln!("[-] Instruction: {}", instruction);
-        eprintln!("[-] Executed {} instructions", self.counter);
-        eprintln!("{}", message);
-        eprintln!("----------------------------------------------------------------");
-        panic!();
     }
 }
 
-#[allow(dead_code)]
 enum Exception {
     Interrupt = 0x0,
     AddressErrorLoad = 0x4,
@@ -0,0 +1,385 @@
+use super::cache::CacheControl;
+use super::{CPU, SR_BOOT_VECTORS, SR_ISOLATE_CACHE};
+use crate::bios::BIOS;
+use crate::gpu::GPU;
+use crate::memory::{Bus, WatchKind, Watchpoint, RAM};
+use crate::renderer::NullRenderer;
+
+const SYSCALL: u32 = 0x0000_000c;
+// beq $zero, $zero, +8
+const BRANCH: u32 = 0x1000_0001;
+const NOP: u32 = 0;
+// addiu $t0, $zero, 1 and 2
+const SET_T0_1: u32 = 0x2408_0001;
+const SET_T0_2: u32 = 0x2408_0002;
+// sw $zero, 0($t1)
+const STORE_ZERO: u32 = 0xad20_0000;
+// lw $t0, 0($t1)
+const LOAD_T0: u32 = 0x8d28_0000;
+// mfc0 $t0, $15
+const READ_PRID: u32 = 0x4008_7800;
+
+const CODE: u32 = 0x8000_1000;
+const T0: usize = 8;
+const T1: usize = 9;
+// The scratchpad, where the uncached segment can't see it
+const UNMAPPED: u32 = 0xbf80_0000;
+const CAUSE_BRANCH_DELAY: u32 = 1 << 31;
+// DCIC: execution, data, data read and data write enables with the master
+// enables, and the matching status bits
+const DCIC_EXECUTION: u32 = 0xc080_0000 | 1 << 24;
+const DCIC_DATA_READ: u32 = 0xc080_0000 | 1 << 25 | 1 << 26;
+const DCIC_DATA_WRITE: u32 = 0xc080_0000 | 1 << 25 | 1 << 27;
+const HIT_EXECUTION: u32 = 1 << 0 | 1 << 1;
+const HIT_DATA_READ: u32 = 1 << 0 | 1 << 2 | 1 << 3;
+const HIT_DATA_WRITE: u32 = 1 << 0 | 1 << 2 | 1 << 4;
+const ICACHE_ENABLED: CacheControl = CacheControl(1 << 11);
+const TAG_TEST: CacheControl = CacheControl(1 << 2 | 1 << 11);
+
+/// A CPU with no BIOS about to run `code`, placed in RAM.
+fn cpu(code: &[u32]) -> CPU<NullRenderer> {
+    let bus = Bus::new(BIOS::empty(), RAM::new(), GPU::new(NullRenderer));
+    let mut cpu = CPU::new(bus);
+    poke_code(&mut cpu, CODE, code);
+    cpu.set_pc(CODE);
+    cpu
+}
+
+fn cause(cpu: &CPU<NullRenderer>) -> u32 {
+    cpu.cop0_register(13).unwrap()
+}
+
+fn epc(cpu: &CPU<NullRenderer>) -> u32 {
+    cpu.cop0_register(14).unwrap()
+}
+
+fn badvaddr(cpu: &CPU<NullRenderer>) -> u32 {
+    cpu.cop0_register(8).unwrap()
+}
+
+fn dcic(cpu: &CPU<NullRenderer>) -> u32 {
+    cpu.cop0_register(7).unwrap()
+}
+
+fn exception_code(cpu: &CPU<NullRenderer>) -> u32 {
+    cause(cpu) >> 2 & 0x1f
+}
+
+fn poke_code(cpu: &mut CPU<NullRenderer>, addr: u32, code: &[u32]) {
+    for (i, word) in code.iter().enumerate() {
+        for (j, byte) in word.to_le_bytes().into_iter().enumerate() {
+            cpu.bus_mut().poke(addr + (i * 4 + j) as u32, byte);
+        }
+    }
+}
+
+#[test]
+fn cause_starts_clear() {
+    let cpu = cpu(&[]);
+    assert_eq!(cause(&cpu), 0);
+}
+
+#[test]
+fn exception_vector() {
+    let mut cpu = cpu(&[SYSCALL]);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.pc(), 0x8000_0080);
+    assert_eq!(epc(&cpu), CODE);
+    assert_eq!(cause(&cpu) >> 2 & 0x1f, 8);
+}
+
+#[test]
+fn boot_exception_vector() {
+    let mut cpu = cpu(&[SYSCALL]);
+    cpu.set_cop0_register(12, SR_BOOT_VECTORS);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.pc(), 0xbfc0_0180);
+}
+
+#[test]
+fn exception_in_delay_slot() {
+    let mut cpu = cpu(&[BRANCH, SYSCALL]);
+    cpu.exec_next_instruction().unwrap();
+    cpu.exec_next_instruction().unwrap();
+    // EPC points to the branch, BD says so
+    assert_eq!(epc(&cpu), CODE);
+    assert_ne!(cause(&cpu) & CAUSE_BRANCH_DELAY, 0);
+}
+
+#[test]
+fn branch_delay_bit_clears() {
+    let mut cpu = cpu(&[BRANCH, SYSCALL, NOP, SYSCALL]);
+    cpu.exec_next_instruction().unwrap();
+    cpu.exec_next_instruction().unwrap();
+    cpu.set_pc(CODE + 12);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(epc(&cpu), CODE + 12);
+    assert_eq!(cause(&cpu) & CAUSE_BRANCH_DELAY, 0);
+}
+
+#[test]
+fn exception_keeps_pending_interrupts() {
+    let mut cpu = cpu(&[SYSCALL]);
+    cpu.set_cop0_register(13, 0x100);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cause(&cpu), 0x100 | 8 << 2);
+}
+
+#[test]
+fn interrupt_in_delay_slot() {
+    let mut cpu = cpu(&[BRANCH, NOP]);
+    cpu.exec_next_instruction().unwrap();
+
+    // The delay slot is known before the interrupt is taken
+    cpu.set_cop0_register(13, 0x100);
+    cpu.set_cop0_register(12, 0x101);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.pc(), 0x8000_0080);
+    assert_eq!(epc(&cpu), CODE);
+    assert_eq!(cause(&cpu), CAUSE_BRANCH_DELAY | 0x100);
+}
+
+#[test]
+fn cached_fetch_hits() {
+    let mut cpu = cpu(&[SET_T0_1]);
+    cpu.cache_control = ICACHE_ENABLED;
+    cpu.exec_next_instruction().unwrap();
+
+    // The cache still has the old instruction
+    poke_code(&mut cpu, CODE, &[SET_T0_2]);
+    cpu.set_pc(CODE);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.registers()[T0], 1);
+}
+
+#[test]
+fn kseg1_fetch_bypasses_the_cache() {
+    let mut cpu = cpu(&[SET_T0_1]);
+    cpu.cache_control = ICACHE_ENABLED;
+    cpu.exec_next_instruction().unwrap();
+
+    poke_code(&mut cpu, CODE, &[SET_T0_2]);
+    cpu.set_pc(CODE | 0x2000_0000);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.registers()[T0], 2);
+}
+
+#[test]
+fn disabled_cache_fetches_from_the_bus() {
+    let mut cpu = cpu(&[SET_T0_1]);
+    cpu.exec_next_instruction().unwrap();
+
+    poke_code(&mut cpu, CODE, &[SET_T0_2]);
+    cpu.set_pc(CODE);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.registers()[T0], 2);
+}
+
+#[test]
+fn isolated_store_invalidates_the_line() {
+    let mut cpu = cpu(&[STORE_ZERO]);
+    cpu.cache_control = TAG_TEST;
+    cpu.icache
+        .refill(0x8000_2000, |_| Ok::<u32, ()>(0))
+        .unwrap();
+    cpu.bus_mut().poke(0x2000, 0xff);
+
+    cpu.set_gpr(T1, 0x2000);
+    cpu.set_cop0_register(12, SR_ISOLATE_CACHE);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.icache.lookup(0x8000_2000), None);
+    // The store doesn't reach RAM
+    assert_eq!(cpu.bus().peek(0x2000), Some(0xff));
+}
+
+#[test]
+fn unmapped_load_is_a_bus_error() {
+    let mut cpu = cpu(&[LOAD_T0]);
+    cpu.set_gpr(T0, 0x1234);
+    cpu.set_gpr(T1, UNMAPPED);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0080);
+    assert_eq!(epc(&cpu), CODE);
+    assert_eq!(exception_code(&cpu), 7);
+    // The load never happens
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.registers()[T0], 0x1234);
+}
+
+#[test]
+fn unmapped_store_is_a_bus_error() {
+    let mut cpu = cpu(&[STORE_ZERO]);
+    cpu.set_gpr(T1, UNMAPPED);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0080);
+    assert_eq!(epc(&cpu), CODE);
+    assert_eq!(exception_code(&cpu), 7);
+}
+
+#[test]
+fn unmapped_fetch_is_a_bus_error() {
+    let mut cpu = cpu(&[]);
+    cpu.set_pc(UNMAPPED);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0080);
+    assert_eq!(epc(&cpu), UNMAPPED);
+    assert_eq!(exception_code(&cpu), 6);
+}
+
+#[test]
+fn unaligned_load_is_an_address_error() {
+    let mut cpu = cpu(&[LOAD_T0]);
+    cpu.set_gpr(T1, 0x2002);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0080);
+    assert_eq!(epc(&cpu), CODE);
+    assert_eq!(exception_code(&cpu), 4);
+}
+
+#[test]
+fn unaligned_store_is_an_address_error() {
+    let mut cpu = cpu(&[STORE_ZERO]);
+    cpu.set_gpr(T1, 0x2001);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0080);
+    assert_eq!(exception_code(&cpu), 5);
+}
+
+#[test]
+fn prid() {
+    let mut cpu = cpu(&[READ_PRID, NOP]);
+    cpu.exec_next_instruction().unwrap();
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.registers()[T0], 2);
+}
+
+#[test]
+fn unaligned_load_sets_badvaddr() {
+    let mut cpu = cpu(&[LOAD_T0]);
+    cpu.set_gpr(T1, 0x2002);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(badvaddr(&cpu), 0x2002);
+}
+
+#[test]
+fn unaligned_store_sets_badvaddr() {
+    let mut cpu = cpu(&[STORE_ZERO]);
+    cpu.set_gpr(T1, 0x2001);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(badvaddr(&cpu), 0x2001);
+}
+
+#[test]
+fn unaligned_fetch_sets_badvaddr() {
+    let mut cpu = cpu(&[]);
+    cpu.set_pc(CODE + 2);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(exception_code(&cpu), 4);
+    assert_eq!(badvaddr(&cpu), CODE + 2);
+}
+
+#[test]
+fn execution_breakpoint() {
+    let mut cpu = cpu(&[SET_T0_1]);
+    cpu.set_gpr(T0, 0);
+    cpu.set_cop0_register(3, CODE);
+    cpu.set_cop0_register(11, 0xffff_ffff);
+    cpu.set_cop0_register(7, DCIC_EXECUTION);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0040);
+    assert_eq!(epc(&cpu), CODE);
+    assert_eq!(exception_code(&cpu), 9);
+    assert_eq!(dcic(&cpu), DCIC_EXECUTION | HIT_EXECUTION);
+    // The instruction didn't run
+    assert_eq!(cpu.registers()[T0], 0);
+}
+
+#[test]
+fn execution_breakpoint_boot_vector() {
+    let mut cpu = cpu(&[NOP]);
+    cpu.set_cop0_register(12, SR_BOOT_VECTORS);
+    cpu.set_cop0_register(3, CODE);
+    cpu.set_cop0_register(11, 0xffff_ffff);
+    cpu.set_cop0_register(7, DCIC_EXECUTION);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.pc(), 0xbfc0_0140);
+}
+
+#[test]
+fn execution_breakpoint_needs_the_master_enables() {
+    let mut cpu = cpu(&[NOP]);
+    cpu.set_cop0_register(3, CODE);
+    cpu.set_cop0_register(11, 0xffff_ffff);
+    cpu.set_cop0_register(7, 1 << 24);
+    cpu.exec_next_instruction().unwrap();
+    assert_eq!(cpu.pc(), CODE + 4);
+    assert_eq!(dcic(&cpu), 1 << 24);
+}
+
+#[test]
+fn data_read_breakpoint() {
+    let mut cpu = cpu(&[LOAD_T0]);
+    cpu.set_gpr(T1, 0x2000);
+    cpu.set_cop0_register(5, 0x2000);
+    cpu.set_cop0_register(9, 0xffff_fff0);
+    cpu.set_cop0_register(7, DCIC_DATA_READ);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0040);
+    assert_eq!(epc(&cpu), CODE);
+    assert_eq!(exception_code(&cpu), 9);
+    assert_eq!(dcic(&cpu), DCIC_DATA_READ | HIT_DATA_READ);
+}
+
+#[test]
+fn data_write_breakpoint() {
+    let mut cpu = cpu(&[STORE_ZERO]);
+    cpu.set_gpr(T1, 0x2004);
+    cpu.bus_mut().poke(0x2004, 0xff);
+    cpu.set_cop0_register(5, 0x2000);
+    cpu.set_cop0_register(9, 0xffff_fff0);
+    cpu.set_cop0_register(7, DCIC_DATA_WRITE);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), 0x8000_0040);
+    assert_eq!(exception_code(&cpu), 9);
+    assert_eq!(dcic(&cpu), DCIC_DATA_WRITE | HIT_DATA_WRITE);
+    // The store didn't happen
+    assert_eq!(cpu.bus().peek(0x2004), Some(0xff));
+}
+
+#[test]
+fn data_read_breakpoint_ignores_writes() {
+    let mut cpu = cpu(&[STORE_ZERO]);
+    cpu.set_gpr(T1, 0x2000);
+    cpu.set_cop0_register(5, 0x2000);
+    cpu.set_cop0_register(9, 0xffff_ffff);
+    cpu.set_cop0_register(7, DCIC_DATA_READ);
+    cpu.exec_next_instruction().unwrap();
+
+    assert_eq!(cpu.pc(), CODE + 4);
+    assert_eq!(dcic(&cpu), DCIC_DATA_READ);
+}
+
+#[test]
+fn fetches_dont_hit_read_watchpoints() {
+    // lw $t0, 0x1000($zero), from the code itself
+    let mut cpu = cpu(&[0x8c08_1000]);
+    cpu.bus_mut().add_watchpoint(Watchpoint {
+        addr: CODE,
+        len: 4,
+        kind: WatchKind::Read,
+    });
+
+    // Only the first hit is kept, so the fetch would have shown up instead
+    cpu.exec_next_instruction().unwrap();
+    let hit = cpu.bus_mut().take_watch_hit().unwrap();
+    assert_eq!(hit.addr, 0x1000);
+}
@@ -0,0 +1,617 @@
+/**
+ * Interactive debugger: PC breakpoints, bus watchpoints, stepping and memory and
+ * register inspection, driven by commands read from stdin.
+ */
+use crate::cpu::{Instruction, Symbols, CPU, REGISTER_NAMES};
+use crate::memory::{WatchHit, WatchKind, Watchpoint};
+use crate::renderer::Renderer;
+use crate::search::{Filter, Kind, Search, WatchList};
+use crate::session::Session;
+use crate::sio::PadState;
+use crate::system::System;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+const COP0_NAMES: [(&str, u32); 11] = [
+    ("bpc", 3),
+    ("bda", 5),
+    ("jumpdest", 6),
+    ("dcic", 7),
+    ("badvaddr", 8),
+    ("bdam", 9),
+    ("bpcm", 11),
+    ("sr", 12),
+    ("cause", 13),
+    ("epc", 14),
+    ("prid", 15),
+];
+
+/// Addresses in RAM, in any of its mirrors.
+const RAM_MASK: u32 = 0x1f_ffff;
+
+const HELP: &str = "\
+c, continue              resume emulation
+s, step [N]              execute N instructions (default 1)
+n, next                  step over calls (jal, jalr, bltzal, bgezal)
+b, break ADDR            set a breakpoint
+d, delete ADDR           remove a breakpoint
+breakpoints              list breakpoints
+watch ADDR [LEN] [r|w|rw] stop on accesses to ADDR..ADDR+LEN (default 4 bytes, rw)
+unwatch ADDR             remove watchpoints at ADDR
+watchpoints              list watchpoints
+r, regs                  show CPU registers
+set REG VALUE            set a register (gpr name, rN, pc, hi, lo or a COP0 name)
+dis [ADDR] [N]           disassemble N instructions (default: around PC)
+x ADDR [LEN]             dump LEN bytes of memory (default 64)
+poke ADDR VALUE [1|2|4]  write to memory
+cheats                   list cheats
+cheat N [on|off]         enable, disable or toggle cheat N
+search new [TYPE]        start a RAM search for TYPE values: u8, s8, u16, s16, u32
+                         or s32 (default u32)
+search eq VALUE          keep the candidates equal to VALUE
+search same|changed|up|down
+                         keep the candidates unchanged, changed, increased or
+                         decreased since the last search
+search list [N]          show N candidates (default 20)
+display ADDR [TYPE]      print the value at ADDR in RAM after every frame
+undisplay ADDR           stop printing ADDR
+reset                    reset the console
+q, quit                  exit the emulator
+Addresses and values are hexadecimal, counts are decimal, search values are
+decimal unless they start with 0x. An empty line repeats the
+last command.";
+
+/// What the frontend should do after leaving the REPL.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Resume,
+    Quit,
+}
+
+/// Why the emulation stopped in the middle of a frame.
+enum Stop {
+    Breakpoint(u32),
+    StepOver,
+    Watchpoint(WatchHit),
+    Error,
+}
+
+pub struct Debugger {
+    breakpoints: Vec<u32>,
+    paused: bool,
+    /// Address `next` runs until.
+    temporary: Option<u32>,
+    /// Don't stop on the breakpoint we're resuming from.
+    resuming: bool,
+    last_command: String,
+    symbols: Box<dyn Symbols>,
+    search: Option<Search>,
+    /// Printed after every frame.
+    display: WatchList,
+    /// Where the frame `run_frame` is in ends, kept when it stops midway.
+    frame_end: Option<u64>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            paused: false,
+            temporary: None,
+            resuming: false,
+            last_command: String::new(),
+            symbols: Box::new(HashMap::new()),
+            search: None,
+            display: WatchList::new(),
+            frame_end: None,
+        }
+    }
+
+    /// Where disassembly gets names for branch and jump targets.
+    pub fn set_symbols(&mut self, symbols: Box<dyn Symbols>) {
+        self.symbols = symbols;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops before the next instruction. The frontend then calls `repl`.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// `Session::run_frame`, but stopping at breakpoints and watchpoints. Errors
+    /// pause the emulation instead of ending it.
+    pub fn run_frame<R: Renderer>(
+        &mut self,
+        system: &mut System<R>,
+        session: &mut Session,
+        input: &PadState,
+    ) {
+        if self.paused {
+            return;
+        }
+
+        // A frame interrupted by a stop is finished when we resume, so it still
+        // gets its vblank, cheats and the session's end of frame
+        let end = match self.frame_end {
+            Some(end) => end,
+            None => {
+                if let Err(e) = session.begin_frame(system, input) {
+                    println!("Emulation error: {}", e);
+                    self.stop(system.cpu(), Stop::Error);
+                    return;
+                }
+                *self
+                    .frame_end
+                    .insert(system.cpu().cycles() + system.cycles_per_frame())
+            }
+        };
+        while system.cpu().cycles() < end {
+            if let Some(stop) = self.step(system) {
+                self.stop(system.cpu(), stop);
+                return;
+            }
+        }
+        self.frame_end = None;
+        system.end_frame();
+        if let Err(e) = session.end_frame(system) {
+            println!("Emulation error: {}", e);
+            self.stop(system.cpu(), Stop::Error);
+            return;
+        }
+
+        if !self.display.is_empty() {
+            println!("{}", self.display.report(system.cpu().bus().ram()));
+        }
+    }
+
+    fn step<R: Renderer>(&mut self, system: &mut System<R>) -> Option<Stop> {
+        let pc = system.cpu().pc();
+        let resuming = std::mem::take(&mut self.resuming);
+
+        if self.temporary == Some(pc) {
+            self.temporary = None;
+            return Some(Stop::StepOver);
+        }
+
+        if !resuming && self.breakpoints.contains(&pc) {
+            return Some(Stop::Breakpoint(pc));
+        }
+
+        if let Err(e) = system.step() {
+            println!("Emulation error: {}", e);
+            return Some(Stop::Error);
+        }
+
+        system
+            .cpu_mut()
+            .bus_mut()
+            .take_watch_hit()
+            .map(Stop::Watchpoint)
+    }
+
+    fn stop<R: Renderer>(&mut self, cpu: &CPU<R>, stop: Stop) {
+        match stop {
+            Stop::Breakpoint(addr) => println!("Breakpoint at 0x{:08X}", addr),
+            Stop::Watchpoint(hit) => print_watch_hit(&hit),
+            Stop::StepOver | Stop::Error => (),
+        }
+        self.paused = true;
+        self.temporary = None;
+        print_location(cpu, &*self.symbols);
+    }
+
+    /// Reads and runs commands until one resumes the emulation or quits.
+    pub fn repl<R: Renderer>(
+        &mut self,
+        system: &mut System<R>,
+        input: &mut impl BufRead,
+    ) -> Action {
+        loop {
+            print!("(rstationx) ");
+            std::io::stdout().flush().ok();
+
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Action::Quit,
+                Ok(_) => (),
+            }
+
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some((&command, args)) = words.split_first() else {
+                continue;
+            };
+
+            match self.command(system, command, args) {
+                Ok(Some(action)) => return action,
+                Ok(None) => (),
+                Err(message) => println!("{}", message),
+            }
+        }
+    }
+
+    fn command<R: Renderer>(
+        &mut self,
+        system: &mut System<R>,
+        command: &str,
+        args: &[&str],
+    ) -> Result<Option<Action>, String> {
+        match command {
+            "c" | "continue" => {
+                self.paused = false;
+                self.resuming = true;
+                return Ok(Some(Action::Resume));
+            }
+            "s" | "step" => {
+                let count = args.first().map_or(Ok(1), |a| parse_count(a))?;
+                self.resuming = true;
+                for _ in 0..count {
+                    if let Some(stop) = self.step(system) {
+                        self.stop(system.cpu(), stop);
+                        return Ok(None);
+                    }
+                }
+                print_location(system.cpu(), &*self.symbols);
+            }
+            "n" | "next" => {
+                let pc = system.cpu().pc();
+                let call = system
+                    .cpu()
+                    .bus()
+                    .peek_word(pc)
+                    .map(|value| is_call(Instruction { value }));
+                if call == Some(true) {
+                    // Run until the instruction after the delay slot
+                    self.temporary = Some(pc.wrapping_add(8));
+                    self.paused = false;
+                    self.resuming = true;
+                    return Ok(Some(Action::Resume));
+                }
+                return self.command(system, "step", &[]);
+            }
+            "b" | "break" => {
+                let addr = parse_hex(args.first())?;
+                if !self.breakpoints.contains(&addr) {
+                    self.breakpoints.push(addr);
+                }
+                println!("Breakpoint at 0x{:08X}", addr);
+            }
+            "d" | "delete" => {
+                let addr = parse_hex(args.first())?;
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|&b| b != addr);
+                if self.breakpoints.len() == count {
+                    return Err(format!("No breakpoint at 0x{:08X}", addr));
+                }
+            }
+            "breakpoints" => {
+                for addr in &self.breakpoints {
+                    println!("0x{:08X}", addr);
+                }
+            }
+            "watch" => {
+                let addr = parse_hex(args.first())?;
+                let len = args.get(1).map_or(Ok(4), |a| parse_count(a))?;
+                let kind = match args.get(2).copied() {
+                    Some("r") => WatchKind::Read,
+                    Some("w") => WatchKind::Write,
+                    Some("rw") | None => WatchKind::Access,
+                    Some(kind) => return Err(format!("Invalid watchpoint kind '{}'", kind)),
+                };
+                system
+                    .cpu_mut()
+                    .bus_mut()
+                    .add_watchpoint(Watchpoint { addr, len, kind });
+            }
+            "unwatch" => {
+                let addr = parse_hex(args.first())?;
+                if !system.cpu_mut().bus_mut().remove_watchpoint(addr) {
+                    return Err(format!("No watchpoint at 0x{:08X}", addr));
+                }
+            }
+            "watchpoints" => {
+                for w in system.cpu().bus().watchpoints() {
+                    println!("0x{:08X} {:>4} bytes {:?}", w.addr, w.len, w.kind);
+                }
+            }
+            "r" | "regs" => print_registers(system.cpu()),
+            "set" => {
+                let (Some(name), Some(value)) = (args.first(), args.get(1)) else {
+                    return Err("Usage: set REG VALUE".into());
+                };
+                let value = parse_hex(Some(value))?;
+                set_register(system.cpu_mut(), name, value)?;
+            }
+            "dis" => {
+                let pc = system.cpu().pc();
+                let addr = match args.first() {
+                    Some(_) => parse_hex(args.first())? & !3,
+                    None => pc.wrapping_sub(16),
+                };
+                let count = args.get(1).map_or(Ok(10), |a| parse_count(a))?;
+                for i in 0..count {
+                    let addr = addr.wrapping_add(i * 4);
+                    let marker = match (addr == pc, self.breakpoints.contains(&addr)) {
+                        (true, _) => "=>",
+                        (false, true) => " *",
+                        (false, false) => "  ",
+                    };
+                    match system.cpu().bus().peek_word(addr) {
+                        Some(value) => {
+                            let text = Instruction { value }.disassemble(addr, &*self.symbols);
+                            println!("{} 0x{:08X}: {:08X}  {}", marker, addr, value, text)
+                        }
+                        None => println!("{} 0x{:08X}: ????????", marker, addr),
+                    }
+                }
+            }
+            "x" => {
+                let addr = parse_hex(args.first())?;
+                let len = args.get(1).map_or(Ok(64), |a| parse_count(a))?;
+                dump_memory(system.cpu(), addr, len);
+            }
+            "poke" => {
+                let addr = parse_hex(args.first())?;
+                let value = parse_hex(args.get(1))?;
+                let width = args.get(2).map_or(Ok(4), |a| parse_count(a))?;
+                if ![1, 2, 4].contains(&width) {
+                    return Err(format!("Invalid width {}", width));
+                }
+                let bus = system.cpu_mut().bus_mut();
+                for i in 0..width {
+                    let byte = (value >> (i * 8)) as u8;
+                    if !bus.poke(addr.wrapping_add(i), byte) {
+                        return Err(format!("0x{:08X} isn't memory", addr.wrapping_add(i)));
+                    }
+                }
+            }
+            "cheats" => {
+                for (i, cheat) in system.cheats().cheats().iter().enumerate() {
+                    let enabled = if cheat.enabled() { 'x' } else { ' ' };
+                    println!("{:>3} [{}] {}", i, enabled, cheat.name());
+                }
+            }
+            "cheat" => {
+                let index = args
+                    .first()
+                    .map_or(Err("Usage: cheat N [on|off]".into()), |a| parse_count(a))?;
+                let cheat = system
+                    .cheats_mut()
+                    .get_mut(index as usize)
+                    .ok_or_else(|| format!("No cheat {}", index))?;
+                let enabled = match args.get(1).copied() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    None => !cheat.enabled(),
+                    Some(arg) => return Err(format!("Expected on or off, not '{}'", arg)),
+                };
+                cheat.set_enabled(enabled);
+                println!(
+                    "{} {}",
+                    cheat.name(),
+                    if enabled { "enabled" } else { "disabled" }
+                );
+            }
+            "search" => self.search(system.cpu().bus().ram(), args)?,
+            "display" => {
+                let addr = parse_hex(args.first())? & RAM_MASK;
+                let kind = args
+                    .get(1)
+                    .map_or(Ok(Kind::parse("u32").unwrap()), |a| parse_kind(a))?;
+                self.display.add(addr, kind);
+            }
+            "undisplay" => {
+                let addr = parse_hex(args.first())? & RAM_MASK;
+                if !self.display.remove(addr) {
+                    return Err(format!("0x{:06X} isn't displayed", addr));
+                }
+            }
+            "reset" => {
+                system.reset().map_err(|e| e.to_string())?;
+                print_location(system.cpu(), &*self.symbols);
+            }
+            "q" | "quit" => return Ok(Some(Action::Quit)),
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(format!("Unknown command '{}', try 'help'", command)),
+        }
+        Ok(None)
+    }
+
+    fn search(&mut self, ram: &[u8], args: &[&str]) -> Result<(), String> {
+        let filter = match args.first().copied() {
+            Some("new") => {
+                let kind = args
+                    .get(1)
+                    .map_or(Ok(Kind::parse("u32").unwrap()), |a| parse_kind(a))?;
+                let search = self.search.insert(Search::new(ram, kind));
+                println!("{} {} candidates", search.len(), kind);
+                return Ok(());
+            }
+            Some("list") => {
+                let search = self
+                    .search
+                    .as_ref()
+                    .ok_or("No search, start one with 'search new'")?;
+                let count = args.get(1).map_or(Ok(20), |a| parse_count(a))?;
+                for (addr, value) in search.results().take(count as usize) {
+                    println!("0x{:06X} {}", addr, value);
+                }
+                if search.len() > count as usize {
+                    println!("... {} more", search.len() - count as usize);
+                }
+                return Ok(());
+            }
+            Some("eq") => Filter::Equal(parse_value(args.get(1))?),
+            Some("same") => Filter::Unchanged,
+            Some("changed") => Filter::Changed,
+            Some("up") => Filter::Increased,
+            Some("down") => Filter::Decreased,
+            _ => return Err("Usage: search new|eq|same|changed|up|down|list".into()),
+        };
+
+        let search = self
+            .search
+            .as_mut()
+            .ok_or("No search, start one with 'search new'")?;
+        println!("{} candidates left", search.filter(ram, filter));
+        Ok(())
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+fn print_watch_hit(hit: &WatchHit) {
+    let access = match hit.value {
+        Some(value) => format!("write of 0x{:X}", value),
+        None => "read".to_string(),
+    };
+    println!(
+        "Watchpoint 0x{:08X}: {} at 0x{:08X}",
+        hit.watchpoint.addr, access, hit.addr
+    );
+}
+
+fn print_location<R: Renderer>(cpu: &CPU<R>, symbols: &dyn Symbols) {
+    let pc = cpu.pc();
+    let slot = match cpu.in_delay_slot() {
+        true => " (delay slot)",
+        false => "",
+    };
+    match cpu.bus().peek_word(pc) {
+        Some(value) => {
+            let text = Instruction { value }.disassemble(pc, symbols);
+            println!("=> 0x{:08X}: {:08X}  {}{}", pc, value, text, slot)
+        }
+        None => println!("=> 0x{:08X}: ????????{}", pc, slot),
+    }
+}
+
+fn print_registers<R: Renderer>(cpu: &CPU<R>) {
+    let registers = cpu.registers();
+    for row in 0..8 {
+        let line: Vec<String> = (0..4)
+            .map(|column| {
+                let index = row * 4 + column;
+                format!("{:>4} {:08X}", REGISTER_NAMES[index], registers[index])
+            })
+            .collect();
+        println!("{}", line.join("  "));
+    }
+    println!(
+        "  pc {:08X}    hi {:08X}    lo {:08X}",
+        cpu.pc(),
+        cpu.hi(),
+        cpu.lo()
+    );
+
+    let cop0: Vec<String> = COP0_NAMES
+        .iter()
+        .filter_map(|&(name, index)| {
+            cpu.cop0_register(index)
+                .map(|value| format!("{} {:08X}", name, value))
+        })
+        .collect();
+    for line in cop0.chunks(4) {
+        println!("{}", line.join("  "));
+    }
+}
+
+fn set_register<R: Renderer>(cpu: &mut CPU<R>, name: &str, value: u32) -> Result<(), String> {
+    let name = name.trim_start_matches('$').to_ascii_lowercase();
+
+    let gpr = REGISTER_NAMES.iter().position(|&n| n == name).or_else(|| {
+        name.strip_prefix('r')
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|&n| n < 32)
+    });
+    let cop0 = COP0_NAMES.iter().find(|&&(n, _)| n == name);
+
+    match (name.as_str(), gpr, cop0) {
+        ("pc", _, _) => cpu.set_pc(value),
+        ("hi", _, _) => cpu.set_hi(value),
+        ("lo", _, _) => cpu.set_lo(value),
+        (_, Some(index), _) => cpu.set_gpr(index, value),
+        (_, None, Some(&(_, index))) => {
+            cpu.set_cop0_register(index, value);
+        }
+        _ => return Err(format!("Unknown register '{}'", name)),
+    }
+    Ok(())
+}
+
+fn dump_memory<R: Renderer>(cpu: &CPU<R>, addr: u32, len: u32) {
+    for line in (0..len).step_by(16) {
+        let start = addr.wrapping_add(line);
+        let bytes: Vec<Option<u8>> = (0..16.min(len - line))
+            .map(|i| cpu.bus().peek(start.wrapping_add(i)))
+            .collect();
+
+        let hex: Vec<String> = bytes
+            .iter()
+            .map(|b| b.map_or("??".to_string(), |b| format!("{:02X}", b)))
+            .collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|b| match b {
+                Some(b @ 0x20..=0x7e) => *b as char,
+                _ => '.',
+            })
+            .collect();
+        println!("0x{:08X}: {:<47}  {}", start, hex.join(" "), ascii);
+    }
+}
+
+/// jal, jalr and the linking BCONDZ branches: the ones that come back.
+fn is_call(instruction: Instruction) -> bool {
+    match instruction.opcode() {
+        0x00 => instruction.secondary_opcode() == 0x09,
+        0x01 => instruction.rt().0 & 0x1e == 0x10,
+        0x03 => true,
+        _ => false,
+    }
+}
+
+fn parse_hex(arg: Option<&&str>) -> Result<u32, String> {
+    let arg = arg.ok_or("Missing address")?;
+    let digits = arg.trim_start_matches("0x").trim_start_matches("0X");
+    u32::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex number '{}'", arg))
+}
+
+fn parse_value(arg: Option<&&str>) -> Result<i64, String> {
+    let arg = arg.ok_or("Missing value")?;
+    let (negative, digits) = match arg.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, *arg),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| format!("Invalid value '{}'", arg))?;
+    Ok(if negative { -value } else { value })
+}
+
+fn parse_kind(arg: &str) -> Result<Kind, String> {
+    Kind::parse(arg).ok_or_else(|| {
+        format!(
+            "Invalid type '{}', expected u8, s8, u16, s16, u32 or s32",
+            arg
+        )
+    })
+}
+
+fn parse_count(arg: &str) -> Result<u32, String> {
+    arg.parse().map_err(|_| format!("Invalid count '{}'", arg))
+}
@@ -0,0 +1,219 @@
+/**
+ * CD images. Only the first data track is read: raw .bin dumps (2352-byte
+ * sectors), .iso/.img images (2048-byte sectors), or a .cue sheet pointing to one
+ * of those.
+ */
+use crate::bios::Region;
+use crate::error::{DiscError, Error};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub const SECTOR_SIZE: usize = 2352;
+pub const DATA_SIZE: usize = 2048;
+/// Sectors `hash` reads.
+const IDENTITY_SECTORS: u32 = 32;
+
+pub struct Disc {
+    file: File,
+    path: PathBuf,
+    sector_size: usize,
+    sectors: u32,
+}
+
+impl Disc {
+    pub fn open(path: &Path) -> Result<Disc, Error> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        if extension.as_deref() == Some("cue") {
+            let image = cue_image(path)?;
+            return Disc::open(&image);
+        }
+
+        let io_error = |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file = File::open(path).map_err(io_error)?;
+        let size = file.metadata().map_err(io_error)?.len();
+
+        let sector_size = if size % SECTOR_SIZE as u64 == 0 {
+            SECTOR_SIZE
+        } else if size % DATA_SIZE as u64 == 0 {
+            DATA_SIZE
+        } else {
+            return Err(Error::InvalidDisc(DiscError::PartialSector(
+                path.to_path_buf(),
+            )));
+        };
+
+        info!("Opened disc image {}", path.display());
+        Ok(Disc {
+            file,
+            path: path.to_path_buf(),
+            sector_size,
+            sectors: (size / sector_size as u64) as u32,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of sectors in the data track.
+    pub fn sectors(&self) -> u32 {
+        self.sectors
+    }
+
+    /// The 2048 bytes of user data of the sector at `lba`, counted from the start of
+    /// the track (so without the 2 second pregap).
+    pub fn read_data(&mut self, lba: u32) -> Result<Vec<u8>, Error> {
+        if lba >= self.sectors {
+            return Err(Error::InvalidDisc(DiscError::ReadPastEnd { lba }));
+        }
+
+        let mut sector = vec![0; self.sector_size];
+        let offset = lba as u64 * self.sector_size as u64;
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.read_exact(&mut sector))
+            .map_err(|source| Error::Io {
+                path: self.path.clone(),
+                source,
+            })?;
+
+        if self.sector_size == DATA_SIZE {
+            return Ok(sector);
+        }
+
+        // Raw sectors: 12 bytes sync, 4 bytes header, then mode 1 data, or an 8
+        // byte subheader and the data for mode 2 (which all PlayStation discs use)
+        let start = match sector[15] {
+            1 => 16,
+            _ => 24,
+        };
+        Ok(sector[start..start + DATA_SIZE].to_vec())
+    }
+
+    /// The region from the licence text in sector 4. Real drives check the SCEx
+    /// string in the wobble of the disc, which images don't keep.
+    pub fn region(&mut self) -> Option<Region> {
+        let licence = self.read_data(4).ok()?;
+        let marker = b"Sony Computer Entertainment ";
+        let start = licence.windows(marker.len()).position(|w| w == marker)? + marker.len();
+        match licence.get(start..start + 4)? {
+            b"Inc." => Some(Region::Japan),
+            b"Amer" => Some(Region::NorthAmerica),
+            b"Euro" => Some(Region::Europe),
+            _ => None,
+        }
+    }
+
+    /// MD5 of the first sectors: the licence, the volume descriptors and usually
+    /// the root directory. Tells discs apart without reading the whole image.
+    pub fn hash(&mut self) -> Result<[u8; 16], Error> {
+        let mut context = md5::Context::new();
+        for lba in 0..IDENTITY_SECTORS.min(self.sectors) {
+            context.consume(self.read_data(lba)?);
+        }
+        Ok(context.compute().0)
+    }
+
+    /// Looks up a file in the ISO9660 filesystem, like `\DATA\MOVIE.STR;1`. The
+    /// version suffix is optional. Returns the first sector and the size.
+    pub fn find_file(&mut self, path: &str) -> Result<Option<(u32, u32)>, Error> {
+        // The root directory record is in the primary volume descriptor
+        let descriptor = self.read_data(16)?;
+        if &descriptor[1..6] != b"CD001" {
+            return Err(Error::InvalidDisc(DiscError::NoFilesystem));
+        }
+        let mut entry = (
+            read_u32(&descriptor, 156 + 2),
+            read_u32(&descriptor, 156 + 10),
+        );
+
+        for name in path.split(['\\', '/']).filter(|n| !n.is_empty()) {
+            match self.find_in_directory(entry, name)? {
+                Some(found) => entry = found,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(entry))
+    }
+
+    fn find_in_directory(
+        &mut self,
+        (lba, size): (u32, u32),
+        name: &str,
+    ) -> Result<Option<(u32, u32)>, Error> {
+        let name = name.to_ascii_uppercase();
+        let sectors = (size as usize).div_ceil(DATA_SIZE) as u32;
+
+        for sector in lba..lba + sectors {
+            let data = self.read_data(sector)?;
+            let mut offset = 0;
+            // Records don't cross sector boundaries, a zero length pads the rest
+            while offset < DATA_SIZE && data[offset] != 0 {
+                let record = &data[offset..];
+                let length = record[32] as usize;
+                let record_name =
+                    String::from_utf8_lossy(&record[33..33 + length]).to_ascii_uppercase();
+
+                let matches = record_name == name
+                    || (!name.contains(';')
+                        && record_name.split(';').next() == Some(name.as_str()));
+                if matches {
+                    return Ok(Some((read_u32(record, 2), read_u32(record, 10))));
+                }
+                offset += record[0] as usize;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads a whole file found with `find_file`.
+    pub fn read_file(&mut self, (lba, size): (u32, u32)) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::with_capacity(size as usize);
+        let mut sector = lba;
+        while data.len() < size as usize {
+            data.extend(self.read_data(sector)?);
+            sector += 1;
+        }
+        data.truncate(size as usize);
+        Ok(data)
+    }
+}
+
+/// ISO9660 stores most numbers in both byte orders, the little endian one first.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Path of the image the first FILE line of a cue sheet points to.
+fn cue_image(path: &Path) -> Result<PathBuf, Error> {
+    let cue = std::fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    for line in cue.lines() {
+        let line = line.trim();
+        if !line.starts_with("FILE") {
+            continue;
+        }
+
+        let name = match (line.find('"'), line.rfind('"')) {
+            (Some(start), Some(end)) if end > start => &line[start + 1..end],
+            _ => line.split_whitespace().nth(1).unwrap_or(""),
+        };
+        let directory = path.parent().unwrap_or(Path::new("."));
+        return Ok(directory.join(name));
+    }
+
+    Err(Error::InvalidDisc(DiscError::NoImageInCue(
+        path.to_path_buf(),
+    )))
+}
@@ -0,0 +1,415 @@
+// Errors the emulator can run into. Frontends match on these to decide whether a
+// failure is fatal.
+use crate::memcard::Format;
+use crate::memory::{MemoryRegion, Port};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Nothing answers at this address. The CPU turns these into bus error exceptions.
+    Unmapped {
+        addr: u32,
+        width: usize,
+    },
+    /// Access that isn't aligned to its width.
+    Unaligned {
+        addr: u32,
+        width: usize,
+    },
+    /// A device register the emulator doesn't handle.
+    UnhandledRegister {
+        region: MemoryRegion,
+        offset: u32,
+        write: bool,
+    },
+    /// A hardware setting the emulator can't emulate, like an unusual display mode.
+    UnsupportedSetting {
+        setting: &'static str,
+        value: u32,
+    },
+    UnimplementedInstruction {
+        word: u32,
+    },
+    /// Exception the HLE kernel has no handler for, like a bus error. `pc` is
+    /// where it happened.
+    UnhandledException {
+        code: u32,
+        pc: u32,
+    },
+    UnimplementedGp0Command {
+        opcode: u8,
+        word: u32,
+    },
+    UnimplementedGp1Command {
+        opcode: u8,
+        word: u32,
+    },
+    /// DMA channel set up in a way we don't handle.
+    InvalidDma(&'static str),
+    /// DMA transfer to or from a device that isn't emulated.
+    UnsupportedDmaPort(Port),
+    BiosLoad(io::Error),
+    /// Disc image we can't make sense of.
+    InvalidDisc(DiscError),
+    InvalidExe(ExeError),
+    /// Reading or writing a file on the host, like a memory card image.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Memory card image or save the library or the card tool refuses to work with.
+    MemoryCard(MemoryCardError),
+    /// Save state that's corrupt or from an incompatible version.
+    InvalidSaveState(StateError),
+    /// Input movie that's corrupt, or made with another BIOS or disc.
+    InvalidMovie(MovieError),
+    /// Cheat file or GameShark code that doesn't parse.
+    InvalidCheat(CheatError),
+    /// Script that doesn't compile, or failed while running.
+    Script(Box<rhai::EvalAltResult>),
+    /// Movie playback went differently than the recording: RAM didn't match after
+    /// this frame.
+    Desync {
+        frame: u64,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl Error {
+    /// Whether this is the guest using hardware the emulator doesn't implement, as
+    /// opposed to bad input or a host failure. Those are worth carrying on after,
+    /// see `System::set_error_policy`.
+    pub fn is_unimplemented(&self) -> bool {
+        matches!(
+            self,
+            Error::UnhandledRegister { .. }
+                | Error::UnsupportedSetting { .. }
+                | Error::UnimplementedInstruction { .. }
+                | Error::UnimplementedGp0Command { .. }
+                | Error::UnimplementedGp1Command { .. }
+                | Error::InvalidDma(_)
+                | Error::UnsupportedDmaPort(_)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum DiscError {
+    /// The image size isn't a multiple of either sector size.
+    PartialSector(PathBuf),
+    /// Cue sheet without a FILE line.
+    NoImageInCue(PathBuf),
+    ReadPastEnd {
+        lba: u32,
+    },
+    NoFilesystem,
+    /// The boot file SYSTEM.CNF names isn't on the disc.
+    BootFileNotFound(String),
+    /// Disc boot without a disc.
+    NoDisc,
+}
+
+#[derive(Debug)]
+pub enum ExeError {
+    /// No PS-X EXE header.
+    NotAnExe,
+    /// Shorter than the code size in the header.
+    Truncated { text_size: usize },
+}
+
+/// Blocks are counted from 0, like in the library, and shown from 1, like in the
+/// BIOS memory card manager.
+#[derive(Debug)]
+pub enum MemoryCardError {
+    UnknownFormat(PathBuf),
+    UnknownSaveFormat(PathBuf),
+    TruncatedCard {
+        format: Format,
+        len: usize,
+    },
+    TruncatedSave {
+        len: usize,
+    },
+    InvalidCardSize {
+        len: usize,
+    },
+    InvalidSaveSize {
+        len: usize,
+    },
+    MissingHeader,
+    BrokenChain {
+        block: usize,
+    },
+    NotFirstBlock {
+        block: usize,
+    },
+    NameTaken(String),
+    NotEnoughBlocks {
+        needed: usize,
+        free: usize,
+    },
+    AlreadyDeleted(String),
+    NotDeleted(String),
+    /// Some of the blocks of a deleted save have been reused.
+    Overwritten(String),
+    /// Block number given on the command line.
+    InvalidBlockNumber(String),
+    InvalidCommand,
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    NotAState,
+    /// Written by another version, layouts differ between versions.
+    Version(u32),
+    Decompression(io::Error),
+    Truncated,
+    LengthMismatch {
+        len: usize,
+        expected: usize,
+    },
+    /// A value out of range, like an enum discriminant that doesn't exist.
+    InvalidValue(&'static str),
+    /// The state and the components disagree on the layout.
+    TrailingBytes(usize),
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    NotAMovie,
+    Version(u32),
+    /// The inputs or the starting state don't read back.
+    Corrupt(StateError),
+    OtherBios,
+    OtherDisc,
+    OtherExe,
+    /// Starts from power on, and the system has already run.
+    AlreadyRunning,
+}
+
+#[derive(Debug)]
+pub enum CheatError {
+    /// Not 8 and 4 hex digits.
+    NotACode(String),
+    UnsupportedCodeType(u8),
+    NotAName(String),
+    CodeBeforeName,
+    /// In this cheat, a conditional is the last code.
+    MissingConditionalTarget(String),
+    /// In this cheat, a repeater isn't followed by a write.
+    MissingRepeatedWrite(String),
+    /// Where it went wrong in a cheat file.
+    Line {
+        line: usize,
+        error: Box<CheatError>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Unmapped { addr, width } => {
+                write!(f, "unmapped {}-byte access at 0x{:08X}", width, addr)
+            }
+            Error::Unaligned { addr, width } => {
+                write!(f, "unaligned {}-byte access at 0x{:08X}", width, addr)
+            }
+            Error::UnhandledRegister {
+                region,
+                offset,
+                write,
+            } => write!(
+                f,
+                "unhandled {} of {:?} register at offset 0x{:02X}",
+                if *write { "write" } else { "read" },
+                region,
+                offset
+            ),
+            Error::UnsupportedSetting { setting, value } => {
+                write!(f, "unsupported {}: 0x{:08X}", setting, value)
+            }
+            Error::UnimplementedInstruction { word } => {
+                write!(f, "unimplemented instruction 0x{:08X}", word)
+            }
+            Error::UnhandledException { code, pc } => {
+                write!(f, "unhandled exception {} at 0x{:08X}", code, pc)
+            }
+            Error::UnimplementedGp0Command { opcode, word } => {
+                write!(f, "unhandled GP0 command 0x{:02X} (0x{:08X})", opcode, word)
+            }
+            Error::UnimplementedGp1Command { opcode, word } => {
+                write!(f, "unhandled GP1 command 0x{:02X} (0x{:08X})", opcode, word)
+            }
+            Error::InvalidDma(reason) => write!(f, "invalid DMA: {}", reason),
+            Error::UnsupportedDmaPort(port) => write!(f, "unsupported DMA port {:?}", port),
+            Error::BiosLoad(e) => write!(f, "failed to load the BIOS: {}", e),
+            Error::InvalidDisc(e) => write!(f, "{}", e),
+            Error::InvalidExe(e) => write!(f, "{}", e),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::MemoryCard(e) => write!(f, "{}", e),
+            Error::InvalidSaveState(e) => write!(f, "invalid save state: {}", e),
+            Error::InvalidMovie(message) => write!(f, "invalid movie: {}", message),
+            Error::InvalidCheat(message) => write!(f, "invalid cheat: {}", message),
+            Error::Script(e) => write!(f, "script error: {}", e),
+            Error::Desync {
+                frame,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "movie desynced at frame {}: RAM checksum 0x{:08x}, recorded 0x{:08x}",
+                frame, actual, expected
+            ),
+        }
+    }
+}
+
+impl fmt::Display for DiscError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscError::PartialSector(path) => {
+                write!(f, "{} isn't a whole number of sectors", path.display())
+            }
+            DiscError::NoImageInCue(path) => write!(f, "no FILE entry in {}", path.display()),
+            DiscError::ReadPastEnd { lba } => {
+                write!(f, "read past the end of the disc: sector {}", lba)
+            }
+            DiscError::NoFilesystem => write!(f, "no ISO9660 filesystem"),
+            DiscError::BootFileNotFound(path) => write!(f, "boot file {} not found", path),
+            DiscError::NoDisc => write!(f, "no disc to boot"),
+        }
+    }
+}
+
+impl fmt::Display for ExeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExeError::NotAnExe => write!(f, "not a PS-X EXE"),
+            ExeError::Truncated { text_size } => {
+                write!(f, "EXE truncated: {} bytes of code expected", text_size)
+            }
+        }
+    }
+}
+
+impl fmt::Display for MemoryCardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryCardError::UnknownFormat(path) => {
+                write!(f, "unknown memory card format for {}", path.display())
+            }
+            MemoryCardError::UnknownSaveFormat(path) => {
+                write!(f, "unknown save format for {}", path.display())
+            }
+            MemoryCardError::TruncatedCard { format, len } => {
+                write!(f, "truncated {:?} memory card: {} bytes", format, len)
+            }
+            MemoryCardError::TruncatedSave { len } => {
+                write!(f, "truncated PSV save: {} bytes", len)
+            }
+            MemoryCardError::InvalidCardSize { len } => {
+                write!(f, "invalid memory card size: {} bytes", len)
+            }
+            MemoryCardError::InvalidSaveSize { len } => {
+                write!(f, "invalid single save size: {} bytes", len)
+            }
+            MemoryCardError::MissingHeader => write!(f, "missing memory card header"),
+            MemoryCardError::BrokenChain { block } => {
+                write!(f, "broken block chain for save in block {}", block + 1)
+            }
+            MemoryCardError::NotFirstBlock { block } => {
+                write!(f, "block {} is not the start of a save", block + 1)
+            }
+            MemoryCardError::NameTaken(name) => write!(f, "a save named {} already exists", name),
+            MemoryCardError::NotEnoughBlocks { needed, free } => {
+                write!(f, "not enough free blocks: need {}, have {}", needed, free)
+            }
+            MemoryCardError::AlreadyDeleted(name) => write!(f, "save {} is already deleted", name),
+            MemoryCardError::NotDeleted(name) => write!(f, "save {} is not deleted", name),
+            MemoryCardError::Overwritten(name) => {
+                write!(f, "save {} was partially overwritten", name)
+            }
+            MemoryCardError::InvalidBlockNumber(block) => {
+                write!(f, "invalid block number: {}", block)
+            }
+            MemoryCardError::InvalidCommand => write!(f, "invalid memcard command"),
+        }
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::Version(version) => write!(
+                f,
+                "format version {}, this build reads version {}",
+                version,
+                crate::savestate::VERSION
+            ),
+            StateError::Decompression(e) => write!(f, "{}", e),
+            StateError::Truncated => write!(f, "truncated"),
+            StateError::LengthMismatch { len, expected } => {
+                write!(f, "{} bytes where {} were expected", len, expected)
+            }
+            StateError::InvalidValue(what) => write!(f, "invalid {}", what),
+            StateError::TrailingBytes(count) => write!(f, "{} trailing bytes", count),
+        }
+    }
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::Version(version) => write!(
+                f,
+                "format version {}, this build reads version {}",
+                version,
+                crate::movie::VERSION
+            ),
+            MovieError::Corrupt(e) => write!(f, "{}", e),
+            MovieError::OtherBios => write!(f, "recorded with another BIOS"),
+            MovieError::OtherDisc => write!(f, "recorded with another disc"),
+            MovieError::OtherExe => write!(f, "recorded booting another EXE"),
+            MovieError::AlreadyRunning => {
+                write!(f, "starts from power on, and the system has already run")
+            }
+        }
+    }
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::NotACode(line) => write!(f, "'{}' isn't a code", line),
+            CheatError::UnsupportedCodeType(kind) => {
+                write!(f, "unsupported code type {:02X}", kind)
+            }
+            CheatError::NotAName(line) => write!(f, "'{}' isn't a cheat name", line),
+            CheatError::CodeBeforeName => write!(f, "code before the first cheat name"),
+            CheatError::MissingConditionalTarget(name) => {
+                write!(f, "{}: conditional code with nothing after it", name)
+            }
+            CheatError::MissingRepeatedWrite(name) => {
+                write!(f, "{}: repeater code without a write after it", name)
+            }
+            CheatError::Line { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::BiosLoad(e)
+            | Error::Io { source: e, .. }
+            | Error::InvalidSaveState(StateError::Decompression(e)) => Some(e),
+            Error::Script(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
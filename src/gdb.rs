@@ -0,0 +1,397 @@
+/**
+ * GDB remote serial protocol stub, so `gdb-multiarch` (`set architecture mips:3000`,
+ * `target remote :PORT`) or an IDE can debug the guest.
+ *
+ * Registers use GDB's MIPS layout: r0-r31, sr, lo, hi, bad, cause, pc, then the FPU
+ * registers, which always read as 0. Breakpoints of both kinds are PC checks rather
+ * than patched `break` instructions, watchpoints go to the bus.
+ */
+use crate::cpu::CPU;
+use crate::memory::{WatchHit, WatchKind, Watchpoint};
+use crate::renderer::Renderer;
+use crate::session::Session;
+use crate::sio::PadState;
+use crate::system::System;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// What GDB's MIPS target description expects in a `g` packet.
+const REGISTER_COUNT: usize = 72;
+const PC_REGISTER: usize = 37;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbStub {
+    stream: TcpStream,
+    breakpoints: Vec<u32>,
+    running: bool,
+    /// Don't stop on the breakpoint we're resuming from.
+    resuming: bool,
+    /// Where the frame `run_frame` is in ends, kept when it stops midway.
+    frame_end: Option<u64>,
+}
+
+/// Why the guest stopped, turned into a stop reply packet.
+enum Stop {
+    Signal(u8),
+    Watchpoint(WatchHit),
+}
+
+impl GdbStub {
+    /// Blocks until GDB connects. The guest starts halted.
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        info!("Waiting for GDB on port {}", port);
+
+        let (stream, addr) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        info!("GDB connected from {}", addr);
+
+        Ok(GdbStub {
+            stream,
+            breakpoints: Vec::new(),
+            running: false,
+            resuming: false,
+            frame_end: None,
+        })
+    }
+
+    /// Serves GDB while the guest is halted, then runs until the end of the frame
+    /// or the next stop. Returns false once GDB detached.
+    pub fn run_frame<R: Renderer>(
+        &mut self,
+        system: &mut System<R>,
+        session: &mut Session,
+        input: &PadState,
+    ) -> io::Result<bool> {
+        if !self.running && !self.serve(system)? {
+            return Ok(false);
+        }
+
+        // Like the debugger, a frame interrupted by a stop is finished when GDB
+        // continues
+        let end = match self.frame_end {
+            Some(end) => end,
+            None => {
+                if let Err(e) = session.begin_frame(system, input) {
+                    error!("Emulation error: {}", e);
+                    self.stop(system.cpu(), Stop::Signal(SIGILL))?;
+                    return Ok(true);
+                }
+                *self
+                    .frame_end
+                    .insert(system.cpu().cycles() + system.cycles_per_frame())
+            }
+        };
+        while self.running && system.cpu().cycles() < end {
+            if let Some(stop) = self.step(system) {
+                self.stop(system.cpu(), stop)?;
+            }
+        }
+        if system.cpu().cycles() >= end {
+            self.frame_end = None;
+            system.end_frame();
+            if let Err(e) = session.end_frame(system) {
+                error!("Emulation error: {}", e);
+                self.stop(system.cpu(), Stop::Signal(SIGILL))?;
+            }
+        }
+
+        if self.running && self.interrupted()? {
+            self.stop(system.cpu(), Stop::Signal(SIGINT))?;
+        }
+        Ok(true)
+    }
+
+    fn step<R: Renderer>(&mut self, system: &mut System<R>) -> Option<Stop> {
+        let pc = system.cpu().pc();
+        let resuming = std::mem::take(&mut self.resuming);
+        if !resuming && self.breakpoints.contains(&pc) {
+            return Some(Stop::Signal(SIGTRAP));
+        }
+
+        if let Err(e) = system.step() {
+            error!("Emulation error: {}", e);
+            return Some(Stop::Signal(SIGILL));
+        }
+
+        system
+            .cpu_mut()
+            .bus_mut()
+            .take_watch_hit()
+            .map(Stop::Watchpoint)
+    }
+
+    fn stop<R: Renderer>(&mut self, cpu: &CPU<R>, stop: Stop) -> io::Result<()> {
+        self.running = false;
+
+        let reply = match stop {
+            Stop::Signal(signal) => format!("S{:02x}", signal),
+            Stop::Watchpoint(hit) => {
+                let kind = match hit.watchpoint.kind {
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Write => "watch",
+                    WatchKind::Access => "awatch",
+                };
+                format!(
+                    "T{:02x}{:02x}:{};{}:{:x};",
+                    SIGTRAP,
+                    PC_REGISTER,
+                    hex_word(cpu.pc()),
+                    kind,
+                    hit.addr
+                )
+            }
+        };
+        self.send(&reply)
+    }
+
+    /// Checks for the Ctrl-C byte GDB sends to interrupt a running target.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Handles packets until one resumes the guest. Returns false on detach.
+    fn serve<R: Renderer>(&mut self, system: &mut System<R>) -> io::Result<bool> {
+        loop {
+            let packet = self.receive()?;
+            let (command, args) = packet.split_at(packet.len().min(1));
+            let cpu = system.cpu_mut();
+
+            let reply = match command {
+                "?" => format!("S{:02x}", SIGTRAP),
+                "g" => (0..REGISTER_COUNT)
+                    .map(|r| hex_word(read_register(cpu, r)))
+                    .collect(),
+                "G" => {
+                    for (r, word) in args.as_bytes().chunks(8).enumerate() {
+                        let value = std::str::from_utf8(word).ok().and_then(parse_word);
+                        if let Some(value) = value {
+                            write_register(cpu, r, value);
+                        }
+                    }
+                    "OK".into()
+                }
+                "p" => match usize::from_str_radix(args, 16) {
+                    Ok(r) => hex_word(read_register(cpu, r)),
+                    Err(_) => "E01".into(),
+                },
+                "P" => {
+                    let register = args.split_once('=').and_then(|(r, v)| {
+                        Some((usize::from_str_radix(r, 16).ok()?, parse_word(v)?))
+                    });
+                    match register {
+                        Some((r, value)) => {
+                            write_register(cpu, r, value);
+                            "OK".into()
+                        }
+                        None => "E01".into(),
+                    }
+                }
+                "m" => read_memory(cpu, args).unwrap_or_else(|| "E01".into()),
+                "M" => match write_memory(cpu, args) {
+                    Some(()) => "OK".into(),
+                    None => "E01".into(),
+                },
+                "c" | "s" => {
+                    if let Ok(addr) = u32::from_str_radix(args, 16) {
+                        cpu.set_pc(addr);
+                    }
+                    self.resuming = true;
+                    if command == "c" {
+                        self.running = true;
+                        return Ok(true);
+                    }
+
+                    let stop = self.step(system).unwrap_or(Stop::Signal(SIGTRAP));
+                    self.stop(system.cpu(), stop)?;
+                    continue;
+                }
+                "Z" | "z" => match self.breakpoint(cpu, command == "Z", args) {
+                    Some(true) => "OK".into(),
+                    // Unsupported kind
+                    Some(false) => String::new(),
+                    None => "E01".into(),
+                },
+                "D" => {
+                    self.send("OK")?;
+                    return Ok(false);
+                }
+                "k" => return Ok(false),
+                "H" => "OK".into(),
+                _ if packet.starts_with("qSupported") => "PacketSize=1000".into(),
+                _ if packet == "qAttached" => "1".into(),
+                // Anything else is unsupported, GDB falls back to what it knows
+                _ => String::new(),
+            };
+            self.send(&reply)?;
+        }
+    }
+
+    /// `Z`/`z` packets: `TYPE,ADDR,KIND`. Returns None if malformed, Some(false) for
+    /// unsupported types.
+    fn breakpoint<R: Renderer>(
+        &mut self,
+        cpu: &mut CPU<R>,
+        insert: bool,
+        args: &str,
+    ) -> Option<bool> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = u32::from_str_radix(fields.next()?, 16).ok()?;
+        let len = u32::from_str_radix(fields.next()?, 16).ok()?;
+
+        let watch = match kind {
+            // Software and hardware breakpoints
+            "0" | "1" => {
+                self.breakpoints.retain(|&b| b != addr);
+                if insert {
+                    self.breakpoints.push(addr);
+                }
+                return Some(true);
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(false),
+        };
+
+        let bus = cpu.bus_mut();
+        match insert {
+            true => bus.add_watchpoint(Watchpoint {
+                addr,
+                len,
+                kind: watch,
+            }),
+            false => {
+                bus.remove_watchpoint(addr);
+            }
+        }
+        Some(true)
+    }
+
+    /// Reads a `$packet#checksum`, acknowledging it.
+    fn receive(&mut self) -> io::Result<String> {
+        let mut byte = [0];
+
+        // Skip acks and stray interrupt bytes
+        loop {
+            self.stream.read_exact(&mut byte)?;
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut packet = Vec::new();
+        loop {
+            self.stream.read_exact(&mut byte)?;
+            if byte[0] == b'#' {
+                break;
+            }
+            packet.push(byte[0]);
+        }
+
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum)?;
+        self.stream.write_all(b"+")?;
+
+        let packet = String::from_utf8_lossy(&packet).into_owned();
+        trace!("GDB <- {}", packet);
+        Ok(packet)
+    }
+
+    fn send(&mut self, packet: &str) -> io::Result<()> {
+        trace!("GDB -> {}", packet);
+        let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", packet, checksum)?;
+        self.stream.flush()
+    }
+}
+
+fn read_register<R: Renderer>(cpu: &CPU<R>, index: usize) -> u32 {
+    match index {
+        0..=31 => cpu.registers()[index],
+        32 => cpu.cop0_register(12).unwrap_or(0),
+        33 => cpu.lo(),
+        34 => cpu.hi(),
+        35 => cpu.cop0_register(8).unwrap_or(0),
+        36 => cpu.cop0_register(13).unwrap_or(0),
+        PC_REGISTER => cpu.pc(),
+        // No FPU
+        _ => 0,
+    }
+}
+
+fn write_register<R: Renderer>(cpu: &mut CPU<R>, index: usize, value: u32) {
+    match index {
+        0..=31 => cpu.set_gpr(index, value),
+        32 => {
+            cpu.set_cop0_register(12, value);
+        }
+        33 => cpu.set_lo(value),
+        34 => cpu.set_hi(value),
+        36 => {
+            cpu.set_cop0_register(13, value);
+        }
+        // Writing back the same PC shouldn't drop a branch in progress
+        PC_REGISTER if value != cpu.pc() => cpu.set_pc(value),
+        // BadVaddr is read-only and there's no FPU
+        _ => (),
+    }
+}
+
+/// `m` packets: `ADDR,LEN`.
+fn read_memory<R: Renderer>(cpu: &CPU<R>, args: &str) -> Option<String> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let len = u32::from_str_radix(len, 16).ok()?;
+
+    (0..len)
+        .map(|i| {
+            cpu.bus()
+                .peek(addr.wrapping_add(i))
+                .map(|b| format!("{:02x}", b))
+        })
+        .collect()
+}
+
+/// `M` packets: `ADDR,LEN:BYTES`.
+fn write_memory<R: Renderer>(cpu: &mut CPU<R>, args: &str) -> Option<()> {
+    let (header, data) = args.split_once(':')?;
+    let (addr, _) = header.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+
+    for (i, byte) in data.as_bytes().chunks(2).enumerate() {
+        let byte = u8::from_str_radix(std::str::from_utf8(byte).ok()?, 16).ok()?;
+        if !cpu.bus_mut().poke(addr.wrapping_add(i as u32), byte) {
+            return None;
+        }
+    }
+    Some(())
+}
+
+/// Registers go over the wire in target (little endian) byte order.
+fn hex_word(value: u32) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn parse_word(hex: &str) -> Option<u32> {
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some(value.swap_bytes())
+}
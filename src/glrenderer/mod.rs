@@ -6,9 +6,9 @@ use sdl2;
 use std::ffi::{c_void, CString};
 use std::ptr;
 
-use crate::gpu::{Color, Position};
-use crate::renderer::Renderer;
 use buffer::{Buffer, VERTEX_BUFFER_LEN};
+use rstationx::gpu::{Color, Position, VRAM_HEIGHT, VRAM_WIDTH};
+use rstationx::renderer::Renderer;
 
 // FIXME: Remove this eventually
 #[allow(dead_code)]
@@ -221,6 +221,46 @@ impl Renderer for GLRenderer {
             );
         }
     }
+
+    /// Reads the front buffer, where the window shows VRAM scaled up, and keeps
+    /// one pixel per VRAM pixel.
+    fn read_display(&self, x: u16, y: u16, width: u16, height: u16) -> Option<Vec<u8>> {
+        let (x, y, width, height) = (x as u32, y as u32, width as u32, height as u32);
+        if x + width > VRAM_WIDTH as u32 || y + height > VRAM_HEIGHT as u32 {
+            return None;
+        }
+
+        let (window_width, window_height) = self.window.drawable_size();
+        let scale_x = window_width / VRAM_WIDTH as u32;
+        let scale_y = window_height / VRAM_HEIGHT as u32;
+        let (read_width, read_height) = (width * scale_x, height * scale_y);
+
+        let mut pixels = vec![0u8; (read_width * read_height * 3) as usize];
+        unsafe {
+            gl::ReadBuffer(gl::FRONT);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            // GL counts rows from the bottom
+            gl::ReadPixels(
+                (x * scale_x) as GLint,
+                (window_height - (y + height) * scale_y) as GLint,
+                read_width as GLsizei,
+                read_height as GLsizei,
+                gl::RGB,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut c_void,
+            );
+        }
+
+        let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+        for row in (0..height).rev() {
+            let line = (row * scale_y * read_width) as usize;
+            for column in 0..width {
+                let i = (line + (column * scale_x) as usize) * 3;
+                rgb.extend_from_slice(&pixels[i..i + 3]);
+            }
+        }
+        Some(rgb)
+    }
 }
 
 fn compile_shader(kind: gl::types::GLenum, source: &str) -> GLuint {
@@ -1,9 +1,12 @@
+use crate::error::Error;
 use crate::renderer::Renderer;
+use crate::savestate::{invalid, StateReader, StateWriter};
 use crate::utils;
-use crate::utils::Error;
-use std::string::String;
 
-type Handler<R> = fn(&mut GPU<R>) -> Result<(), String>;
+type Handler<R> = fn(&mut GPU<R>) -> Result<(), Error>;
+
+pub const VRAM_WIDTH: usize = 1024;
+pub const VRAM_HEIGHT: usize = 512;
 
 #[derive(Clone, Copy, Debug)]
 enum TextureDepth {
@@ -74,6 +77,76 @@ enum GP0Mode {
     Imageload,
 }
 
+/// The rectangle of VRAM a transfer to or from the CPU goes through.
+#[derive(Clone, Copy)]
+struct ImageTransfer {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    // Pixels transferred so far
+    index: u32,
+}
+
+impl ImageTransfer {
+    const NONE: ImageTransfer = ImageTransfer {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+        index: 0,
+    };
+
+    /// From the position and size words of GP0 0xA0 and 0xC0.
+    fn from_gp0(position: u32, size: u32) -> ImageTransfer {
+        // 0 means the whole width or height of VRAM
+        let width = ((size & 0xffff).wrapping_sub(1) & 0x3ff) + 1;
+        let height = ((size >> 16).wrapping_sub(1) & 0x1ff) + 1;
+
+        ImageTransfer {
+            x: (position & 0x3ff) as u16,
+            y: ((position >> 16) & 0x1ff) as u16,
+            width: width as u16,
+            height: height as u16,
+            index: 0,
+        }
+    }
+
+    fn pixels(&self) -> u32 {
+        self.width as u32 * self.height as u32
+    }
+
+    /// Index in VRAM of the next pixel, None once they've all been transferred.
+    fn next(&mut self) -> Option<usize> {
+        if self.index >= self.pixels() {
+            return None;
+        }
+
+        let width = self.width as u32;
+        let x = (self.x as usize + (self.index % width) as usize) % VRAM_WIDTH;
+        let y = (self.y as usize + (self.index / width) as usize) % VRAM_HEIGHT;
+        self.index += 1;
+        Some(y * VRAM_WIDTH + x)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16s(&[self.x, self.y, self.width, self.height]);
+        w.u32(self.index);
+    }
+
+    fn load_state(r: &mut StateReader) -> Result<ImageTransfer, Error> {
+        let mut rect = [0; 4];
+        r.u16s(&mut rect)?;
+        Ok(ImageTransfer {
+            x: rect[0],
+            y: rect[1],
+            width: rect[2],
+            height: rect[3],
+            index: r.u32()?,
+        })
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Position {
     pub x: i16,
@@ -127,6 +200,9 @@ pub struct GPU<R: Renderer> {
     vres: VerticalRes,
     field: Field,
     vmode: VMode,
+    /// What `vmode` is at power on and after a GP1 reset, PAL on European
+    /// consoles.
+    default_vmode: VMode,
     display_depth: DisplayDepth,
     dma_direction: DMADirection,
 
@@ -142,6 +218,15 @@ pub struct GPU<R: Renderer> {
     gp0_command_method: Handler<R>,
 
     gp0_mode: GP0Mode,
+    image_load: ImageTransfer,
+    image_store: ImageTransfer,
+    /// The last word read from GPUREAD, which it keeps returning after an image
+    /// store.
+    read_latch: u32,
+    drawing_offset: Position,
+
+    /// Only image loads write here, drawing commands go to the renderer.
+    vram: Vec<u16>,
 }
 
 impl<R: Renderer> GPU<R> {
@@ -167,6 +252,7 @@ impl<R: Renderer> GPU<R> {
             vres: VerticalRes::Y240,
             field: Field::Top,
             vmode: VMode::NTSC,
+            default_vmode: VMode::NTSC,
             display_depth: DisplayDepth::D15,
             dma_direction: DMADirection::Off,
 
@@ -187,9 +273,24 @@ impl<R: Renderer> GPU<R> {
             gp0_command_method: GPU::gp0_nop,
 
             gp0_mode: GP0Mode::Command,
+            image_load: ImageTransfer::NONE,
+            image_store: ImageTransfer::NONE,
+            read_latch: 0,
+            drawing_offset: Position { x: 0, y: 0 },
+
+            vram: vec![0; VRAM_WIDTH * VRAM_HEIGHT],
         }
     }
 
+    /// Whether the console is a PAL one, which the video mode starts out as.
+    pub fn set_pal(&mut self, pal: bool) {
+        self.default_vmode = match pal {
+            true => VMode::PAL,
+            false => VMode::NTSC,
+        };
+        self.vmode = self.default_vmode;
+    }
+
     pub fn status(&self) -> u32 {
         let r = 0
             | (self.texture_base.0 as u32) << 0
@@ -225,7 +326,7 @@ impl<R: Renderer> GPU<R> {
         r | dma_request << 25
     }
 
-    pub fn load<T: TryFrom<u32>>(&self, offset: u32) -> T {
+    pub fn load<T: TryFrom<u32>>(&mut self, offset: u32) -> T {
         let value: u32 = match offset {
             0 => self.read(),
             4 => self.status(),
@@ -234,26 +335,37 @@ impl<R: Renderer> GPU<R> {
         utils::to_t(value)
     }
 
-    pub fn gp0(&mut self, val: u32) -> Result<(), String> {
+    /// Length in words and handler of a GP0 command.
+    fn gp0_handler(opcode: u32) -> Option<(u32, Handler<R>)> {
+        let handler: (u32, Handler<R>) = match opcode {
+            0x00 => (1, GPU::gp0_nop),
+            0x01 => (1, GPU::gp0_clear_cache),
+            0x28 => (5, GPU::gp0_quad_mono_opaque),
+            0x2c => (9, GPU::gp0_quad_texture_blend_opaque),
+            0x30 => (6, GPU::gp0_triangle_shaded_opaque),
+            0x38 => (8, GPU::gp0_quad_shaded_opaque),
+            0xa0 => (3, GPU::gp0_image_load),
+            0xc0 => (3, GPU::gp0_image_store),
+            0xe1 => (1, GPU::gp0_draw_mode),
+            0xe2 => (1, GPU::gp0_texture_window),
+            0xe3 => (1, GPU::gp0_drawing_area_top_left),
+            0xe4 => (1, GPU::gp0_drawing_area_bottom_right),
+            0xe5 => (1, GPU::gp0_drawing_offset),
+            0xe6 => (1, GPU::gp0_mask_bit_setting),
+            _ => return None,
+        };
+        Some(handler)
+    }
+
+    pub fn gp0(&mut self, val: u32) -> Result<(), Error> {
         if self.gp0_command_remaining == 0 {
             let opcode = (val >> 24) & 0xff;
 
-            let (len, method): (u32, Handler<R>) = match opcode {
-                0x00 => (1, GPU::gp0_nop),
-                0x01 => (1, GPU::gp0_clear_cache),
-                0x28 => (5, GPU::gp0_quad_mono_opaque),
-                0x2c => (9, GPU::gp0_quad_texture_blend_opaque),
-                0x30 => (6, GPU::gp0_triangle_shaded_opaque),
-                0x38 => (8, GPU::gp0_quad_shaded_opaque),
-                0xa0 => (3, GPU::gp0_image_load),
-                0xc0 => (3, GPU::gp0_image_store),
-                0xe1 => (1, GPU::gp0_draw_mode),
-                0xe2 => (1, GPU::gp0_texture_window),
-                0xe3 => (1, GPU::gp0_drawing_area_top_left),
-                0xe4 => (1, GPU::gp0_drawing_area_bottom_right),
-                0xe5 => (1, GPU::gp0_drawing_offset),
-                0xe6 => (1, GPU::gp0_mask_bit_setting),
-                _ => return Error!("Unhandled GP0 command 0x{:08x}", val),
+            let Some((len, method)) = GPU::gp0_handler(opcode) else {
+                return Err(Error::UnimplementedGp0Command {
+                    opcode: opcode as u8,
+                    word: val,
+                });
             };
             self.gp0_command_remaining = len;
             self.gp0_command_method = method;
@@ -272,7 +384,8 @@ impl<R: Renderer> GPU<R> {
                 }
             }
             GP0Mode::Imageload => {
-                // TODO: copy pixel data to video ram
+                self.load_pixel(val as u16);
+                self.load_pixel((val >> 16) as u16);
 
                 if self.gp0_command_remaining == 0 {
                     self.gp0_mode = GP0Mode::Command;
@@ -282,16 +395,16 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    pub fn gp0_nop(&mut self) -> Result<(), String> {
+    pub fn gp0_nop(&mut self) -> Result<(), Error> {
         Ok(())
     }
 
-    pub fn gp0_clear_cache(&mut self) -> Result<(), String> {
+    pub fn gp0_clear_cache(&mut self) -> Result<(), Error> {
         debug!("Unimplemented gp0 clear cache command");
         Ok(())
     }
 
-    pub fn gp0_quad_mono_opaque(&mut self) -> Result<(), String> {
+    pub fn gp0_quad_mono_opaque(&mut self) -> Result<(), Error> {
         let positions = [
             Position::from_gp0(self.gp0_command[1]),
             Position::from_gp0(self.gp0_command[2]),
@@ -306,7 +419,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    pub fn gp0_quad_texture_blend_opaque(&mut self) -> Result<(), String> {
+    pub fn gp0_quad_texture_blend_opaque(&mut self) -> Result<(), Error> {
         let positions = [
             Position::from_gp0(self.gp0_command[1]),
             Position::from_gp0(self.gp0_command[3]),
@@ -325,7 +438,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    pub fn gp0_triangle_shaded_opaque(&mut self) -> Result<(), String> {
+    pub fn gp0_triangle_shaded_opaque(&mut self) -> Result<(), Error> {
         let positions = [
             Position::from_gp0(self.gp0_command[1]),
             Position::from_gp0(self.gp0_command[3]),
@@ -343,7 +456,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    pub fn gp0_quad_shaded_opaque(&mut self) -> Result<(), String> {
+    pub fn gp0_quad_shaded_opaque(&mut self) -> Result<(), Error> {
         let positions = [
             Position::from_gp0(self.gp0_command[1]),
             Position::from_gp0(self.gp0_command[3]),
@@ -362,32 +475,30 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    pub fn gp0_image_load(&mut self) -> Result<(), String> {
-        let resolution = self.gp0_command[2];
-
-        let width = resolution & 0xffff;
-        let height = resolution >> 16;
-
-        let image_size = width * height;
-        let image_size = (image_size + 1) & !1;
+    pub fn gp0_image_load(&mut self) -> Result<(), Error> {
+        self.image_load = ImageTransfer::from_gp0(self.gp0_command[1], self.gp0_command[2]);
 
+        // Odd sized images are padded to a whole word
+        let image_size = (self.image_load.pixels() + 1) & !1;
         self.gp0_command_remaining = image_size / 2;
         self.gp0_mode = GP0Mode::Imageload;
 
         Ok(())
     }
 
-    pub fn gp0_image_store(&mut self) -> Result<(), String> {
-        let resolution = self.gp0_command[2];
-
-        let width = resolution & 0xffff;
-        let height = resolution >> 16;
+    fn load_pixel(&mut self, pixel: u16) {
+        if let Some(index) = self.image_load.next() {
+            self.vram[index] = pixel;
+        }
+    }
 
-        debug!("Unhandled image store {}x{}", width, height);
+    /// The pixels are then read from GPUREAD, two at a time.
+    pub fn gp0_image_store(&mut self) -> Result<(), Error> {
+        self.image_store = ImageTransfer::from_gp0(self.gp0_command[1], self.gp0_command[2]);
         Ok(())
     }
 
-    fn gp0_draw_mode(&mut self) -> Result<(), String> {
+    fn gp0_draw_mode(&mut self) -> Result<(), Error> {
         let val = self.gp0_command[0];
 
         self.texture_base.0 = (val & 0xf) as u8;
@@ -398,7 +509,12 @@ impl<R: Renderer> GPU<R> {
             0 => TextureDepth::T4,
             1 => TextureDepth::T8,
             2 => TextureDepth::T15,
-            n => return Error!("Unhandled texture depth {:?}", n),
+            n => {
+                return Err(Error::UnsupportedSetting {
+                    setting: "texture depth",
+                    value: n,
+                })
+            }
         };
 
         self.dithering = ((val >> 9) & 1) != 0;
@@ -410,7 +526,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp0_texture_window(&mut self) -> Result<(), String> {
+    fn gp0_texture_window(&mut self) -> Result<(), Error> {
         let val = self.gp0_command[0];
 
         let x_mask = (val & 0x1f) as u8;
@@ -423,7 +539,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp0_drawing_area_top_left(&mut self) -> Result<(), String> {
+    fn gp0_drawing_area_top_left(&mut self) -> Result<(), Error> {
         let val = self.gp0_command[0];
 
         self.drawing_area.top = ((val >> 10) & 0x3ff) as u16;
@@ -431,15 +547,15 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp0_drawing_area_bottom_right(&mut self) -> Result<(), String> {
+    fn gp0_drawing_area_bottom_right(&mut self) -> Result<(), Error> {
         let val = self.gp0_command[0];
 
-        self.drawing_area.top = ((val >> 10) & 0x3ff) as u16;
-        self.drawing_area.left = (val & 0x3ff) as u16;
+        self.drawing_area.bottom = ((val >> 10) & 0x3ff) as u16;
+        self.drawing_area.right = (val & 0x3ff) as u16;
         Ok(())
     }
 
-    fn gp0_drawing_offset(&mut self) -> Result<(), String> {
+    fn gp0_drawing_offset(&mut self) -> Result<(), Error> {
         let val = self.gp0_command[0];
 
         let x = (val & 0x7ff) as u16;
@@ -448,7 +564,8 @@ impl<R: Renderer> GPU<R> {
         let x = ((x << 5) as i16) >> 5; // what the fuck
         let y = ((y << 5) as i16) >> 5;
 
-        self.renderer.set_draw_offset(Position { x, y });
+        self.drawing_offset = Position { x, y };
+        self.renderer.set_draw_offset(self.drawing_offset);
 
         // FIXME: This is a hack till we have better timings
         self.renderer.display();
@@ -456,7 +573,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp0_mask_bit_setting(&mut self) -> Result<(), String> {
+    fn gp0_mask_bit_setting(&mut self) -> Result<(), Error> {
         let val = self.gp0_command[0];
 
         self.force_set_mask_bit = (val & 1) != 0;
@@ -464,7 +581,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    pub fn gp1(&mut self, val: u32) -> Result<(), String> {
+    pub fn gp1(&mut self, val: u32) -> Result<(), Error> {
         let opcode = (val >> 24) & 0xff;
 
         match opcode {
@@ -477,11 +594,14 @@ impl<R: Renderer> GPU<R> {
             0x06 => self.gp1_display_horizontal_range(val),
             0x07 => self.gp1_display_vertical_range(val),
             0x08 => self.gp1_display_mode(val),
-            _ => Error!("Unhandled GP1 command 0x{:08x}", val),
+            _ => Err(Error::UnimplementedGp1Command {
+                opcode: opcode as u8,
+                word: val,
+            }),
         }
     }
 
-    fn gp1_reset(&mut self, val: u32) -> Result<(), String> {
+    fn gp1_reset(&mut self, val: u32) -> Result<(), Error> {
         self.gp1_reset_command_buf(val)?;
 
         self.interrupt = false;
@@ -511,7 +631,7 @@ impl<R: Renderer> GPU<R> {
         self.hres = HorizontalRes::from_fields(0, 0);
         self.vres = VerticalRes::Y240;
 
-        self.vmode = VMode::NTSC;
+        self.vmode = self.default_vmode;
         self.interlacing = true;
         self.display_horiz_range = (0x200, 0xc00);
         self.display_line_range = (0x10, 0x100);
@@ -519,7 +639,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp1_reset_command_buf(&mut self, _: u32) -> Result<(), String> {
+    fn gp1_reset_command_buf(&mut self, _: u32) -> Result<(), Error> {
         self.gp0_command.clear();
         self.gp0_command_remaining = 0;
         self.gp0_mode = GP0Mode::Command;
@@ -527,17 +647,17 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp1_ack_irq(&mut self, _: u32) -> Result<(), String> {
+    fn gp1_ack_irq(&mut self, _: u32) -> Result<(), Error> {
         self.interrupt = false;
         Ok(())
     }
 
-    fn gp1_display_enable(&mut self, val: u32) -> Result<(), String> {
+    fn gp1_display_enable(&mut self, val: u32) -> Result<(), Error> {
         self.display_disabled = val & 1 != 0;
         Ok(())
     }
 
-    fn gp1_dma_direction(&mut self, val: u32) -> Result<(), String> {
+    fn gp1_dma_direction(&mut self, val: u32) -> Result<(), Error> {
         self.dma_direction = match val & 3 {
             0 => DMADirection::Off,
             1 => DMADirection::FIFO,
@@ -548,7 +668,7 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp1_display_vram_start(&mut self, val: u32) -> Result<(), String> {
+    fn gp1_display_vram_start(&mut self, val: u32) -> Result<(), Error> {
         let x = (val & 0x3fe) as u16;
         let y = ((val >> 10) & 0x1ff) as u16;
 
@@ -556,21 +676,21 @@ impl<R: Renderer> GPU<R> {
         Ok(())
     }
 
-    fn gp1_display_horizontal_range(&mut self, val: u32) -> Result<(), String> {
+    fn gp1_display_horizontal_range(&mut self, val: u32) -> Result<(), Error> {
         let start = (val & 0xfff) as u16;
         let end = ((val >> 12) & 0xfff) as u16;
         self.display_horiz_range = (start, end);
         Ok(())
     }
 
-    fn gp1_display_vertical_range(&mut self, val: u32) -> Result<(), String> {
+    fn gp1_display_vertical_range(&mut self, val: u32) -> Result<(), Error> {
         let start = (val & 0x3ff) as u16;
         let end = ((val >> 10) & 0x3ff) as u16;
         self.display_line_range = (start, end);
         Ok(())
     }
 
-    fn gp1_display_mode(&mut self, val: u32) -> Result<(), String> {
+    fn gp1_display_mode(&mut self, val: u32) -> Result<(), Error> {
         let hr1 = (val & 3) as u8;
         let hr2 = ((val >> 6) & 1) as u8;
 
@@ -594,16 +714,261 @@ impl<R: Renderer> GPU<R> {
         self.interlacing = val & 0x20 != 0;
 
         if val & 0x80 != 0 {
-            return Error!("Unsupported display mode 0x{:08x}", val);
+            return Err(Error::UnsupportedSetting {
+                setting: "display mode",
+                value: val,
+            });
         }
         Ok(())
     }
 
-    pub fn read(&self) -> u32 {
-        0
+    /// GPUREAD: the next two pixels of an image store, then the last word read
+    /// again once they've all been read.
+    pub fn read(&mut self) -> u32 {
+        if self.image_store.index < self.image_store.pixels() {
+            let mut pixel = || {
+                let index = self.image_store.next();
+                index.map_or(0, |index| self.vram[index]) as u32
+            };
+            self.read_latch = pixel() | pixel() << 16;
+        }
+        self.read_latch
+    }
+
+    /// VRAM as the GPU sees it, 1024x512 pixels in 15-bit BGR.
+    pub fn vram(&self) -> &[u16] {
+        &self.vram
+    }
+
+    /// All of VRAM as RGB888, 1024x512 read as 15-bit pixels: texture pages and
+    /// CLUTs show up as they're stored.
+    pub fn vram_rgb(&self) -> Vec<u8> {
+        self.vram
+            .iter()
+            .flat_map(|&pixel| bgr15_to_rgb(pixel))
+            .collect()
+    }
+
+    /// Size of the displayed picture, from the display mode and the vertical range.
+    pub fn display_size(&self) -> (u32, u32) {
+        let width = match self.hres.0 {
+            r if r & 1 != 0 => 368,
+            r => [256, 320, 512, 640][(r >> 1) as usize],
+        };
+
+        let lines = self
+            .display_line_range
+            .1
+            .saturating_sub(self.display_line_range.0) as u32;
+        let lines = match (lines, self.vmode) {
+            (0, VMode::NTSC) => 240,
+            (0, VMode::PAL) => 256,
+            (lines, _) => lines,
+        };
+        let height = match self.vres {
+            VerticalRes::Y480 if self.interlacing => lines * 2,
+            _ => lines,
+        };
+        (width, height.min(VRAM_HEIGHT as u32))
+    }
+
+    /// The displayed area of VRAM as RGB888. In 15-bit mode that's what the
+    /// renderer shows, when it can read it back. In 24-bit mode, and without a
+    /// renderer, it's VRAM as image loads left it, in 15 or 24-bit depending on
+    /// the display mode: 24-bit pictures are movies, uploaded that way.
+    pub fn display_rgb(&self) -> (u32, u32, Vec<u8>) {
+        let (width, height) = self.display_size();
+        if let DisplayDepth::D15 = self.display_depth {
+            let (x, y) = self.display_vram_start;
+            if let Some(rgb) = self
+                .renderer
+                .read_display(x, y, width as u16, height as u16)
+            {
+                return (width, height, rgb);
+            }
+        }
+
+        let (start_x, start_y) = (
+            self.display_vram_start.0 as usize,
+            self.display_vram_start.1 as usize,
+        );
+
+        let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height as usize {
+            let row = ((start_y + y) % VRAM_HEIGHT) * VRAM_WIDTH;
+            let halfword = |x: usize| self.vram[row + (start_x + x) % VRAM_WIDTH];
+
+            for x in 0..width as usize {
+                match self.display_depth {
+                    DisplayDepth::D15 => rgb.extend_from_slice(&bgr15_to_rgb(halfword(x))),
+                    DisplayDepth::D24 => {
+                        // Three bytes per pixel, straddling the 16-bit halfwords
+                        let byte = |i: usize| (halfword(i / 2) >> ((i % 2) * 8)) as u8;
+                        rgb.extend_from_slice(&[byte(x * 3), byte(x * 3 + 1), byte(x * 3 + 2)]);
+                    }
+                }
+            }
+        }
+        (width, height, rgb)
+    }
+
+    /// Registers, command progress and VRAM. What the renderer already drew isn't
+    /// part of it, it's redrawn by the next frame.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.semi_transparency);
+        w.u8(self.texture_base.0);
+        w.u8(self.texture_base.1);
+        w.u8(self.texture_depth as u8);
+
+        w.bool(self.texture_disable);
+        w.bool(self.draw_to_display);
+        w.bool(self.force_set_mask_bit);
+        w.bool(self.preserve_masked_pixels);
+        w.bool(self.interlacing);
+        w.bool(self.display_disabled);
+        w.bool(self.dithering);
+        w.bool(self.interrupt);
+        w.bool(self.texture_flip.0);
+        w.bool(self.texture_flip.1);
+
+        w.u8(self.hres.0);
+        w.u8(self.vres as u8);
+        w.u8(self.field as u8);
+        w.u8(self.vmode as u8);
+        w.u8(self.display_depth as u8);
+        w.u8(self.dma_direction as u8);
+
+        w.u8(self.texture_window_mask.0);
+        w.u8(self.texture_window_mask.1);
+        w.u8(self.texture_window_offset.0);
+        w.u8(self.texture_window_offset.1);
+        w.u16s(&[
+            self.drawing_area.left,
+            self.drawing_area.right,
+            self.drawing_area.top,
+            self.drawing_area.bottom,
+            self.display_vram_start.0,
+            self.display_vram_start.1,
+            self.display_horiz_range.0,
+            self.display_horiz_range.1,
+            self.display_line_range.0,
+            self.display_line_range.1,
+        ]);
+
+        // The handler is found again from the command's opcode
+        w.u8(self.gp0_command.len);
+        w.u32s(&self.gp0_command.data);
+        w.u32(self.gp0_command_remaining);
+        w.bool(matches!(self.gp0_mode, GP0Mode::Imageload));
+        self.image_load.save_state(w);
+        self.image_store.save_state(w);
+        w.u32(self.read_latch);
+        w.u16(self.drawing_offset.x as u16);
+        w.u16(self.drawing_offset.y as u16);
+
+        w.u16s(&self.vram);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.semi_transparency = r.u8()?;
+        self.texture_base = (r.u8()?, r.u8()?);
+        self.texture_depth = match r.u8()? {
+            0 => TextureDepth::T4,
+            1 => TextureDepth::T8,
+            2 => TextureDepth::T15,
+            _ => return Err(invalid("GPU texture depth")),
+        };
+
+        self.texture_disable = r.bool()?;
+        self.draw_to_display = r.bool()?;
+        self.force_set_mask_bit = r.bool()?;
+        self.preserve_masked_pixels = r.bool()?;
+        self.interlacing = r.bool()?;
+        self.display_disabled = r.bool()?;
+        self.dithering = r.bool()?;
+        self.interrupt = r.bool()?;
+        self.texture_flip = (r.bool()?, r.bool()?);
+
+        self.hres = HorizontalRes(r.u8()? & 7);
+        self.vres = match r.bool()? {
+            false => VerticalRes::Y240,
+            true => VerticalRes::Y480,
+        };
+        self.field = match r.bool()? {
+            false => Field::Bottom,
+            true => Field::Top,
+        };
+        self.vmode = match r.bool()? {
+            false => VMode::NTSC,
+            true => VMode::PAL,
+        };
+        self.display_depth = match r.bool()? {
+            false => DisplayDepth::D15,
+            true => DisplayDepth::D24,
+        };
+        self.dma_direction = match r.u8()? {
+            0 => DMADirection::Off,
+            1 => DMADirection::FIFO,
+            2 => DMADirection::CPU2GP0,
+            3 => DMADirection::VRAM2CPU,
+            _ => return Err(invalid("GPU DMA direction")),
+        };
+
+        self.texture_window_mask = (r.u8()?, r.u8()?);
+        self.texture_window_offset = (r.u8()?, r.u8()?);
+        let mut areas = [0; 10];
+        r.u16s(&mut areas)?;
+        self.drawing_area = DrawingArea {
+            left: areas[0],
+            right: areas[1],
+            top: areas[2],
+            bottom: areas[3],
+        };
+        self.display_vram_start = (areas[4], areas[5]);
+        self.display_horiz_range = (areas[6], areas[7]);
+        self.display_line_range = (areas[8], areas[9]);
+
+        let len = r.u8()?;
+        if len as usize > self.gp0_command.data.len() {
+            return Err(invalid("GP0 command length"));
+        }
+        self.gp0_command.len = len;
+        r.u32s(&mut self.gp0_command.data)?;
+        self.gp0_command_remaining = r.u32()?;
+        self.gp0_mode = match r.bool()? {
+            false => GP0Mode::Command,
+            true => GP0Mode::Imageload,
+        };
+        self.gp0_command_method = match len {
+            0 => GPU::gp0_nop,
+            _ => match GPU::gp0_handler(self.gp0_command[0] >> 24) {
+                Some((_, method)) => method,
+                None => return Err(invalid("GP0 command")),
+            },
+        };
+
+        self.image_load = ImageTransfer::load_state(r)?;
+        self.image_store = ImageTransfer::load_state(r)?;
+        self.read_latch = r.u32()?;
+        self.drawing_offset = Position {
+            x: r.u16()? as i16,
+            y: r.u16()? as i16,
+        };
+        self.renderer.set_draw_offset(self.drawing_offset);
+
+        r.u16s(&mut self.vram)
     }
 }
 
+/// 5 bits per component, red in the low bits.
+fn bgr15_to_rgb(pixel: u16) -> [u8; 3] {
+    let component = |shift: u16| {
+        let c = ((pixel >> shift) & 0x1f) as u8;
+        (c << 3) | (c >> 2)
+    };
+    [component(0), component(5), component(10)]
+}
+
 struct CommandBuffer {
     data: [u32; 12],
     len: u8,
@@ -643,3 +1008,39 @@ impl ::std::ops::Index<usize> for CommandBuffer {
         &self.data[index]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::NullRenderer;
+
+    #[test]
+    fn drawing_area() {
+        let mut gpu = GPU::new(NullRenderer);
+        gpu.gp0(0xe300_0000 | 20 << 10 | 10).unwrap();
+        gpu.gp0(0xe400_0000 | 239 << 10 | 319).unwrap();
+
+        let area = &gpu.drawing_area;
+        assert_eq!((area.left, area.top), (10, 20));
+        assert_eq!((area.right, area.bottom), (319, 239));
+    }
+
+    #[test]
+    fn image_store() {
+        let mut gpu = GPU::new(NullRenderer);
+        // A 3x1 image at (10, 20), padded to a whole word, and one pixel under it
+        for word in [0xa000_0000, 20 << 16 | 10, 0x0001_0003, 0x2222_1111, 0x3333] {
+            gpu.gp0(word).unwrap();
+        }
+        for word in [0xa000_0000, 21 << 16 | 11, 0x0001_0001, 0x4444] {
+            gpu.gp0(word).unwrap();
+        }
+
+        for word in [0xc000_0000, 20 << 16 | 10, 0x0002_0002] {
+            gpu.gp0(word).unwrap();
+        }
+        assert_eq!([gpu.read(), gpu.read()], [0x2222_1111, 0x4444_0000]);
+        // The last word again once it's done
+        assert_eq!(gpu.read(), 0x4444_0000);
+    }
+}
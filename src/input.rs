@@ -0,0 +1,123 @@
+// Maps the host keyboard and SDL game controllers onto the emulated DualShock.
+use sdl2::controller::{self, GameController};
+use sdl2::keyboard::{KeyboardState, Scancode};
+use sdl2::GameControllerSubsystem;
+
+use rstationx::sio::{Axis, Button, PadState};
+
+const KEYBOARD_MAP: [(Scancode, Button); 16] = [
+    (Scancode::Up, Button::Up),
+    (Scancode::Down, Button::Down),
+    (Scancode::Left, Button::Left),
+    (Scancode::Right, Button::Right),
+    (Scancode::Return, Button::Start),
+    (Scancode::Backspace, Button::Select),
+    (Scancode::Z, Button::Cross),
+    (Scancode::X, Button::Circle),
+    (Scancode::A, Button::Square),
+    (Scancode::S, Button::Triangle),
+    (Scancode::Q, Button::L1),
+    (Scancode::W, Button::R1),
+    (Scancode::E, Button::L2),
+    (Scancode::R, Button::R2),
+    (Scancode::D, Button::L3),
+    (Scancode::F, Button::R3),
+];
+
+const CONTROLLER_MAP: [(controller::Button, Button); 14] = [
+    (controller::Button::DPadUp, Button::Up),
+    (controller::Button::DPadDown, Button::Down),
+    (controller::Button::DPadLeft, Button::Left),
+    (controller::Button::DPadRight, Button::Right),
+    (controller::Button::Start, Button::Start),
+    (controller::Button::Back, Button::Select),
+    (controller::Button::A, Button::Cross),
+    (controller::Button::B, Button::Circle),
+    (controller::Button::X, Button::Square),
+    (controller::Button::Y, Button::Triangle),
+    (controller::Button::LeftShoulder, Button::L1),
+    (controller::Button::RightShoulder, Button::R1),
+    (controller::Button::LeftStick, Button::L3),
+    (controller::Button::RightStick, Button::R3),
+];
+
+const AXIS_MAP: [(controller::Axis, Axis); 4] = [
+    (controller::Axis::LeftX, Axis::LeftX),
+    (controller::Axis::LeftY, Axis::LeftY),
+    (controller::Axis::RightX, Axis::RightX),
+    (controller::Axis::RightY, Axis::RightY),
+];
+
+// Triggers are analog on most host controllers but digital on the DualShock.
+const TRIGGER_THRESHOLD: i16 = 0x4000;
+
+// Refreshed every poll, so this only needs to outlive the polling interval.
+const RUMBLE_DURATION_MS: u32 = 100;
+
+pub struct Input {
+    subsystem: GameControllerSubsystem,
+    controller: Option<GameController>,
+}
+
+impl Input {
+    pub fn new(subsystem: GameControllerSubsystem) -> Input {
+        let mut input = Input {
+            subsystem,
+            controller: None,
+        };
+        input.open_controller();
+        input
+    }
+
+    /// Opens the first available game controller, if we don't have one already.
+    pub fn open_controller(&mut self) {
+        if self.controller.as_ref().is_some_and(|c| c.attached()) {
+            return;
+        }
+
+        let count = self.subsystem.num_joysticks().unwrap_or(0);
+        self.controller = (0..count)
+            .filter(|&i| self.subsystem.is_game_controller(i))
+            .find_map(|i| self.subsystem.open(i).ok());
+
+        if let Some(controller) = &self.controller {
+            info!("Using game controller \"{}\"", controller.name());
+        }
+    }
+
+    /// Reads the host devices. `motors` is the (small, large) rumble the game asked for.
+    pub fn update(&mut self, keyboard: &KeyboardState, motors: (u8, u8)) -> PadState {
+        let mut pad = PadState::new();
+
+        for (scancode, button) in KEYBOARD_MAP {
+            pad.set_button(button, keyboard.is_scancode_pressed(scancode));
+        }
+
+        let controller = match self.controller.as_mut() {
+            Some(controller) if controller.attached() => controller,
+            _ => return pad,
+        };
+
+        for (host, button) in CONTROLLER_MAP {
+            if controller.button(host) {
+                pad.set_button(button, true);
+            }
+        }
+
+        if controller.axis(controller::Axis::TriggerLeft) > TRIGGER_THRESHOLD {
+            pad.set_button(Button::L2, true);
+        }
+        if controller.axis(controller::Axis::TriggerRight) > TRIGGER_THRESHOLD {
+            pad.set_button(Button::R2, true);
+        }
+
+        for (host, axis) in AXIS_MAP {
+            let value = (controller.axis(host) >> 8) as i32 + 0x80;
+            pad.set_axis(axis, value as u8);
+        }
+
+        let (small, large) = motors;
+        let _ = controller.set_rumble((large as u16) << 8, (small as u16) << 8, RUMBLE_DURATION_MS);
+        pad
+    }
+}
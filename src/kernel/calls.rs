@@ -0,0 +1,238 @@
+// Names and arguments of the BIOS kernel functions, by table and number. Argument
+// kinds: s is a string pointer, x a hex value and d a decimal one. Only the first
+// four arguments are listed, the rest are on the stack.
+
+pub const A0: &[(u32, &str, &str)] = &[
+    (0x00, "open", "sx"),
+    (0x01, "lseek", "dxd"),
+    (0x02, "read", "dxx"),
+    (0x03, "write", "dxx"),
+    (0x04, "close", "d"),
+    (0x05, "ioctl", "dxx"),
+    (0x06, "exit", "d"),
+    (0x07, "isatty", "d"),
+    (0x08, "getc", "d"),
+    (0x09, "putc", "xd"),
+    (0x0a, "todigit", "x"),
+    (0x0b, "atof", "s"),
+    (0x0c, "strtoul", "sxd"),
+    (0x0d, "strtol", "sxd"),
+    (0x0e, "abs", "d"),
+    (0x0f, "labs", "d"),
+    (0x10, "atoi", "s"),
+    (0x11, "atol", "s"),
+    (0x12, "atob", "sx"),
+    (0x13, "SaveState", "x"),
+    (0x14, "RestoreState", "xx"),
+    (0x15, "strcat", "xs"),
+    (0x16, "strncat", "xsd"),
+    (0x17, "strcmp", "ss"),
+    (0x18, "strncmp", "ssd"),
+    (0x19, "strcpy", "xs"),
+    (0x1a, "strncpy", "xsd"),
+    (0x1b, "strlen", "s"),
+    (0x1c, "index", "sx"),
+    (0x1d, "rindex", "sx"),
+    (0x1e, "strchr", "sx"),
+    (0x1f, "strrchr", "sx"),
+    (0x20, "strpbrk", "ss"),
+    (0x21, "strspn", "ss"),
+    (0x22, "strcspn", "ss"),
+    (0x23, "strtok", "xs"),
+    (0x24, "strstr", "ss"),
+    (0x25, "toupper", "x"),
+    (0x26, "tolower", "x"),
+    (0x27, "bcopy", "xxx"),
+    (0x28, "bzero", "xx"),
+    (0x29, "bcmp", "xxx"),
+    (0x2a, "memcpy", "xxx"),
+    (0x2b, "memset", "xxx"),
+    (0x2c, "memmove", "xxx"),
+    (0x2d, "memcmp", "xxx"),
+    (0x2e, "memchr", "xxx"),
+    (0x2f, "rand", ""),
+    (0x30, "srand", "x"),
+    (0x31, "qsort", "xxxx"),
+    (0x32, "strtod", "sx"),
+    (0x33, "malloc", "x"),
+    (0x34, "free", "x"),
+    (0x35, "lsearch", "xxxx"),
+    (0x36, "bsearch", "xxxx"),
+    (0x37, "calloc", "xx"),
+    (0x38, "realloc", "xx"),
+    (0x39, "InitHeap", "xx"),
+    (0x3a, "_exit", "d"),
+    (0x3b, "getchar", ""),
+    (0x3c, "putchar", "x"),
+    (0x3d, "gets", "x"),
+    (0x3e, "puts", "s"),
+    (0x3f, "printf", "sxxx"),
+    (0x40, "SystemErrorUnresolvedException", ""),
+    (0x41, "LoadTest", "sx"),
+    (0x42, "Load", "sx"),
+    (0x43, "Exec", "xxx"),
+    (0x44, "FlushCache", ""),
+    (0x45, "init_a0_b0_c0_vectors", ""),
+    (0x46, "GPU_dw", "xxxx"),
+    (0x47, "gpu_send_dma", "xxxx"),
+    (0x48, "SendGP1Command", "x"),
+    (0x49, "GPU_cw", "x"),
+    (0x4a, "GPU_cwp", "xx"),
+    (0x4b, "send_gpu_linked_list", "x"),
+    (0x4c, "gpu_abort_dma", ""),
+    (0x4d, "GetGPUStatus", ""),
+    (0x4e, "gpu_sync", ""),
+    (0x51, "LoadExec", "sxx"),
+    (0x54, "CdInit", ""),
+    (0x55, "_bu_init", ""),
+    (0x56, "CdRemove", ""),
+    (0x5b, "dev_tty_init", ""),
+    (0x5c, "dev_tty_open", "xsx"),
+    (0x5e, "dev_tty_ioctl", "xxx"),
+    (0x5f, "dev_cd_open", "xsx"),
+    (0x60, "dev_cd_read", "xxx"),
+    (0x61, "dev_cd_close", "x"),
+    (0x62, "dev_cd_firstfile", "xsx"),
+    (0x63, "dev_cd_nextfile", "xx"),
+    (0x64, "dev_cd_chdir", "xs"),
+    (0x65, "dev_card_open", "xsx"),
+    (0x66, "dev_card_read", "xxx"),
+    (0x67, "dev_card_write", "xxx"),
+    (0x68, "dev_card_close", "x"),
+    (0x69, "dev_card_firstfile", "xsx"),
+    (0x6a, "dev_card_nextfile", "xx"),
+    (0x6b, "dev_card_erase", "xs"),
+    (0x6c, "dev_card_undelete", "xs"),
+    (0x6d, "dev_card_format", "x"),
+    (0x6e, "dev_card_rename", "xsxs"),
+    (0x70, "_bu_init", ""),
+    (0x71, "CdInit", ""),
+    (0x72, "CdRemove", ""),
+    (0x78, "CdAsyncSeekL", "x"),
+    (0x7c, "CdAsyncGetStatus", "x"),
+    (0x7e, "CdAsyncReadSector", "xxx"),
+    (0x81, "CdAsyncSetMode", "x"),
+    (0x90, "CdromIoIrqFunc1", ""),
+    (0x91, "CdromDmaIrqFunc1", ""),
+    (0x92, "CdromIoIrqFunc2", ""),
+    (0x93, "CdromDmaIrqFunc2", ""),
+    (0x94, "CdromGetInt5errCode", "xx"),
+    (0x95, "CdInitSubFunc", ""),
+    (0x96, "AddCDROMDevice", ""),
+    (0x97, "AddMemCardDevice", ""),
+    (0x98, "AddDuartTtyDevice", ""),
+    (0x99, "AddDummyTtyDevice", ""),
+    (0x9c, "SetConf", "xxx"),
+    (0x9d, "GetConf", "xxx"),
+    (0x9e, "SetCdromIrqAutoAbort", "xx"),
+    (0x9f, "SetMemSize", "d"),
+    (0xa0, "WarmBoot", ""),
+    (0xa1, "SystemErrorBootOrDiskFailure", "xx"),
+    (0xa2, "EnqueueCdIntr", ""),
+    (0xa3, "DequeueCdIntr", ""),
+    (0xa4, "CdGetLbn", "s"),
+    (0xa5, "CdReadSector", "xxx"),
+    (0xa6, "CdGetStatus", ""),
+    (0xab, "_card_info", "d"),
+    (0xac, "_card_load", "d"),
+    (0xad, "_card_auto", "d"),
+    (0xb2, "do_a_long_jmp", ""),
+    (0xb4, "GetSystemInfo", "x"),
+];
+
+pub const B0: &[(u32, &str, &str)] = &[
+    (0x00, "alloc_kernel_memory", "x"),
+    (0x01, "free_kernel_memory", "x"),
+    (0x02, "init_timer", "xxx"),
+    (0x03, "get_timer", "x"),
+    (0x04, "enable_timer_irq", "x"),
+    (0x05, "disable_timer_irq", "x"),
+    (0x06, "restart_timer", "x"),
+    (0x07, "DeliverEvent", "xx"),
+    (0x08, "OpenEvent", "xxxx"),
+    (0x09, "CloseEvent", "x"),
+    (0x0a, "WaitEvent", "x"),
+    (0x0b, "TestEvent", "x"),
+    (0x0c, "EnableEvent", "x"),
+    (0x0d, "DisableEvent", "x"),
+    (0x0e, "OpenThread", "xxx"),
+    (0x0f, "CloseThread", "x"),
+    (0x10, "ChangeThread", "x"),
+    (0x11, "jump_to_00000000", ""),
+    (0x12, "InitPad", "xxxx"),
+    (0x13, "StartPad", ""),
+    (0x14, "StopPad", ""),
+    (0x15, "OutdatedPadInitAndStart", "xxxx"),
+    (0x16, "OutdatedPadGetButtons", ""),
+    (0x17, "ReturnFromException", ""),
+    (0x18, "SetDefaultExitFromException", ""),
+    (0x19, "SetCustomExitFromException", "x"),
+    (0x20, "UnDeliverEvent", "xx"),
+    (0x32, "open", "sx"),
+    (0x33, "lseek", "dxd"),
+    (0x34, "read", "dxx"),
+    (0x35, "write", "dxx"),
+    (0x36, "close", "d"),
+    (0x37, "ioctl", "dxx"),
+    (0x38, "exit", "d"),
+    (0x39, "isatty", "d"),
+    (0x3a, "getc", "d"),
+    (0x3b, "putc", "xd"),
+    (0x3c, "getchar", ""),
+    (0x3d, "putchar", "x"),
+    (0x3e, "gets", "x"),
+    (0x3f, "puts", "s"),
+    (0x40, "cd", "s"),
+    (0x41, "format", "s"),
+    (0x42, "firstfile", "sx"),
+    (0x43, "nextfile", "x"),
+    (0x44, "rename", "ss"),
+    (0x45, "erase", "s"),
+    (0x46, "undelete", "s"),
+    (0x47, "AddDrv", "x"),
+    (0x48, "DelDrv", "s"),
+    (0x49, "PrintInstalledDevices", ""),
+    (0x4a, "InitCard", "d"),
+    (0x4b, "StartCard", ""),
+    (0x4c, "StopCard", ""),
+    (0x4e, "write_card_sector", "xxx"),
+    (0x4f, "read_card_sector", "xxx"),
+    (0x50, "allow_new_card", ""),
+    (0x51, "Krom2RawAdd", "x"),
+    (0x54, "get_errno", ""),
+    (0x55, "GetLastFileError", "d"),
+    (0x56, "GetC0Table", ""),
+    (0x57, "GetB0Table", ""),
+    (0x58, "get_bu_callback_port", ""),
+    (0x59, "testdevice", "s"),
+    (0x5b, "ChangeClearPad", "d"),
+    (0x5c, "get_card_status", "d"),
+    (0x5d, "wait_card_status", "d"),
+];
+
+pub const C0: &[(u32, &str, &str)] = &[
+    (0x00, "EnqueueTimerAndVblankIrqs", "x"),
+    (0x01, "EnqueueSyscallHandler", "x"),
+    (0x02, "SysEnqIntRP", "xx"),
+    (0x03, "SysDeqIntRP", "xx"),
+    (0x04, "get_free_EvCB_slot", ""),
+    (0x05, "get_free_TCB_slot", ""),
+    (0x06, "ExceptionHandler", ""),
+    (0x07, "InstallExceptionHandlers", ""),
+    (0x08, "SysInitMemory", "xx"),
+    (0x09, "SysInitKernelVariables", ""),
+    (0x0a, "ChangeClearRCnt", "xx"),
+    (0x0c, "InitDefInt", "x"),
+    (0x0d, "SetIrqAutoAck", "xx"),
+    (0x12, "InstallDevices", "x"),
+    (0x13, "FlushStdInOutPut", ""),
+    (0x15, "tty_cdevinput", "xx"),
+    (0x16, "tty_cdevscan", ""),
+    (0x17, "tty_circgetc", "x"),
+    (0x18, "tty_circputc", "xx"),
+    (0x19, "ioabort", "ss"),
+    (0x1a, "set_card_find_mode", "x"),
+    (0x1b, "KernelRedirect", "x"),
+    (0x1c, "AdjustA0Table", ""),
+    (0x1d, "get_card_find_mode", ""),
+];
@@ -0,0 +1,71 @@
+// PS-X EXE executables: a 2048 byte header followed by the code and data to copy
+// to RAM.
+use crate::cpu::CPU;
+use crate::error::{Error, ExeError};
+use crate::renderer::Renderer;
+
+const HEADER_SIZE: usize = 0x800;
+/// Where the stack goes when the header doesn't say.
+const DEFAULT_STACK: u32 = 0x801f_fff0;
+
+pub struct Exe {
+    pc: u32,
+    gp: u32,
+    text_addr: u32,
+    bss_addr: u32,
+    bss_size: u32,
+    stack: u32,
+    text: Vec<u8>,
+}
+
+impl Exe {
+    pub fn parse(data: &[u8]) -> Result<Exe, Error> {
+        if data.len() < HEADER_SIZE || &data[0..8] != b"PS-X EXE" {
+            return Err(Error::InvalidExe(ExeError::NotAnExe));
+        }
+
+        let word = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let text_size = word(0x1c) as usize;
+        let Some(text) = data.get(HEADER_SIZE..HEADER_SIZE + text_size) else {
+            return Err(Error::InvalidExe(ExeError::Truncated { text_size }));
+        };
+
+        let stack = match word(0x30) {
+            0 => DEFAULT_STACK,
+            addr => addr.wrapping_add(word(0x34)),
+        };
+
+        Ok(Exe {
+            pc: word(0x10),
+            gp: word(0x14),
+            text_addr: word(0x18),
+            bss_addr: word(0x28),
+            bss_size: word(0x2c),
+            stack,
+            text: text.to_vec(),
+        })
+    }
+
+    /// Copies the EXE to RAM and sets up the registers to run it.
+    pub fn load<R: Renderer>(&self, cpu: &mut CPU<R>) {
+        let bus = cpu.bus_mut();
+        for (i, &byte) in self.text.iter().enumerate() {
+            bus.poke(self.text_addr.wrapping_add(i as u32), byte);
+        }
+        for i in 0..self.bss_size {
+            bus.poke(self.bss_addr.wrapping_add(i), 0);
+        }
+
+        info!(
+            "Loaded EXE at 0x{:08x} ({} bytes), entry point 0x{:08x}",
+            self.text_addr,
+            self.text.len(),
+            self.pc
+        );
+
+        cpu.set_gpr(28, self.gp);
+        cpu.set_gpr(29, self.stack);
+        cpu.set_gpr(30, self.stack);
+        cpu.set_pc(self.pc);
+    }
+}
@@ -0,0 +1,1090 @@
+// High level emulation of the BIOS kernel, to run games and homebrew without a BIOS
+// image. Calls through the A0/B0/C0 vectors and exceptions are handled here instead
+// of by guest code. Only the commonly used functions are there, the rest log a
+// warning and return 0.
+use super::exe::Exe;
+use super::Call;
+use crate::cpu::{Instruction, CPU};
+use crate::disc::{Disc, DATA_SIZE};
+use crate::error::{DiscError, Error};
+use crate::memory::Bus;
+use crate::renderer::Renderer;
+use crate::savestate::{StateReader, StateWriter};
+use std::path::PathBuf;
+
+/// Where the CPU goes on exceptions once the kernel is up.
+pub const EXCEPTION_VECTOR: u32 = 0x8000_0080;
+
+/// Handles returned by OpenEvent, the low bits are an index.
+const EVENT_HANDLE: u32 = 0xf100_0000;
+/// Event mode where delivering only marks the event as ready.
+const EVENT_MODE_READY: u32 = 0x2000;
+
+/// File descriptors 0 and 1 are the TTY.
+const FIRST_FD: usize = 2;
+
+/// Where GetB0Table and GetC0Table say the tables are, like in the real kernel.
+const B0_TABLE: u32 = 0x874;
+const C0_TABLE: u32 = 0x674;
+
+const ERROR: u32 = 0xffff_ffff;
+
+/// Longest string the string functions will walk.
+const MAX_STRING: u32 = 0x10000;
+
+/// What to do after a call.
+pub enum Reply {
+    /// Return to $ra with this in $v0.
+    Return(u32),
+    /// The call set the PC itself.
+    Jump,
+    Unimplemented,
+}
+
+/// What the kernel boots.
+#[derive(Clone)]
+pub enum Boot {
+    /// SYSTEM.CNF's BOOT line, or PSX.EXE, from the disc.
+    Disc,
+    Exe(PathBuf),
+}
+
+struct Event {
+    class: u32,
+    spec: u32,
+    mode: u32,
+    enabled: bool,
+    delivered: bool,
+}
+
+struct OpenFile {
+    lba: u32,
+    size: u32,
+    position: u32,
+}
+
+struct Block {
+    addr: u32,
+    size: u32,
+    free: bool,
+}
+
+pub struct Hle {
+    pub boot: Boot,
+    events: Vec<Option<Event>>,
+    files: Vec<Option<OpenFile>>,
+    heap: Vec<Block>,
+    /// The two buffers from InitPad.
+    pad_buffers: Option<[u32; 2]>,
+    pads_started: bool,
+    seed: u32,
+    /// TTY output of the last call.
+    pub tty: Vec<u8>,
+}
+
+impl Hle {
+    pub fn new(boot: Boot) -> Hle {
+        Hle {
+            boot,
+            events: Vec::new(),
+            files: Vec::new(),
+            heap: Vec::new(),
+            pad_buffers: None,
+            pads_started: false,
+            seed: 0,
+            tty: Vec::new(),
+        }
+    }
+
+    /// Loads the EXE named by the disc's SYSTEM.CNF and sets the CPU up to run it.
+    pub fn boot_disc<R: Renderer>(
+        &mut self,
+        cpu: &mut CPU<R>,
+        disc: &mut Disc,
+    ) -> Result<(), Error> {
+        let path = match disc.find_file("SYSTEM.CNF;1")? {
+            Some(file) => {
+                let config = disc.read_file(file)?;
+                boot_path(&String::from_utf8_lossy(&config))
+            }
+            None => None,
+        };
+        let path = path.unwrap_or_else(|| "PSX.EXE;1".into());
+
+        let Some(file) = disc.find_file(&path)? else {
+            return Err(Error::InvalidDisc(DiscError::BootFileNotFound(path)));
+        };
+        info!("Booting {}", path);
+        Exe::parse(&disc.read_file(file)?)?.load(cpu);
+        Ok(())
+    }
+
+    pub fn call<R: Renderer>(
+        &mut self,
+        call: &Call,
+        cpu: &mut CPU<R>,
+        disc: Option<&mut Disc>,
+    ) -> Reply {
+        let [a0, a1, a2, a3] = call.args;
+        let bus = cpu.bus_mut();
+
+        let value = match (call.table, call.function) {
+            // Files
+            (0xa0, 0x00) | (0xb0, 0x32) => self.open(bus, disc, a0),
+            (0xa0, 0x01) | (0xb0, 0x33) => self.seek(a0, a1, a2),
+            (0xa0, 0x02) | (0xb0, 0x34) => self.read(bus, disc, a0, a1, a2),
+            (0xa0, 0x03) | (0xb0, 0x35) => self.write(bus, a0, a1, a2),
+            (0xa0, 0x04) | (0xb0, 0x36) => self.close(a0),
+            (0xa0, 0x06) | (0xa0, 0x3a) | (0xb0, 0x38) => {
+                info!("Program exited with code {}", a0 as i32);
+                0
+            }
+
+            // Numbers
+            (0xa0, 0x0e) | (0xa0, 0x0f) => (a0 as i32).unsigned_abs(),
+            (0xa0, 0x10) | (0xa0, 0x11) => parse_int(&read_string(bus, a0), 10) as u32,
+            (0xa0, 0x0c) | (0xa0, 0x0d) => {
+                let number = parse_int(&read_string(bus, a0), a2) as u32;
+                if a1 != 0 {
+                    // Not tracking how much was parsed, point past the end
+                    write32(bus, a1, a0 + read_string(bus, a0).len() as u32);
+                }
+                number
+            }
+            (0xa0, 0x2f) => {
+                self.seed = self.seed.wrapping_mul(0x41c6_4e6d).wrapping_add(0x3039);
+                (self.seed >> 16) & 0x7fff
+            }
+            (0xa0, 0x30) => {
+                self.seed = a0;
+                0
+            }
+
+            // Strings
+            (0xa0, 0x15) => {
+                let end = a0 + read_string(bus, a0).len() as u32;
+                copy_string(bus, end, a1, MAX_STRING);
+                a0
+            }
+            (0xa0, 0x16) => {
+                let end = a0 + read_string(bus, a0).len() as u32;
+                let length = copy_string(bus, end, a1, a2);
+                write8(bus, end + length, 0);
+                a0
+            }
+            (0xa0, 0x17) => compare(&read_string(bus, a0), &read_string(bus, a1)),
+            (0xa0, 0x18) => {
+                let (a, b) = (read_string(bus, a0), read_string(bus, a1));
+                let n = a2 as usize;
+                compare(&a[..a.len().min(n)], &b[..b.len().min(n)])
+            }
+            (0xa0, 0x19) => {
+                copy_string(bus, a0, a1, MAX_STRING);
+                a0
+            }
+            (0xa0, 0x1a) => {
+                let length = copy_string(bus, a0, a1, a2);
+                for i in length..a2 {
+                    write8(bus, a0 + i, 0);
+                }
+                a0
+            }
+            (0xa0, 0x1b) => read_string(bus, a0).len() as u32,
+            (0xa0, 0x1c) | (0xa0, 0x1e) => {
+                let s = read_string(bus, a0);
+                s.iter()
+                    .position(|&c| c == a1 as u8)
+                    .map_or(0, |i| a0 + i as u32)
+            }
+            (0xa0, 0x1d) | (0xa0, 0x1f) => {
+                let s = read_string(bus, a0);
+                s.iter()
+                    .rposition(|&c| c == a1 as u8)
+                    .map_or(0, |i| a0 + i as u32)
+            }
+            (0xa0, 0x24) => {
+                let (s, needle) = (read_string(bus, a0), read_string(bus, a1));
+                match needle.is_empty() {
+                    true => a0,
+                    false => s
+                        .windows(needle.len())
+                        .position(|w| w == needle.as_slice())
+                        .map_or(0, |i| a0 + i as u32),
+                }
+            }
+            (0xa0, 0x25) => (a0 as u8).to_ascii_uppercase() as u32,
+            (0xa0, 0x26) => (a0 as u8).to_ascii_lowercase() as u32,
+
+            // Memory
+            (0xa0, 0x27) => {
+                copy(bus, a1, a0, a2);
+                a1
+            }
+            (0xa0, 0x28) => {
+                fill(bus, a0, 0, a1);
+                a0
+            }
+            (0xa0, 0x29) | (0xa0, 0x2d) => compare(&read(bus, a0, a2), &read(bus, a1, a2)),
+            (0xa0, 0x2a) | (0xa0, 0x2c) => {
+                copy(bus, a0, a1, a2);
+                a0
+            }
+            (0xa0, 0x2b) => {
+                fill(bus, a0, a1 as u8, a2);
+                a0
+            }
+            (0xa0, 0x2e) => read(bus, a0, a2)
+                .iter()
+                .position(|&c| c == a1 as u8)
+                .map_or(0, |i| a0 + i as u32),
+
+            // Heap
+            (0xa0, 0x33) | (0xb0, 0x00) => self.malloc(a0),
+            (0xa0, 0x34) | (0xb0, 0x01) => {
+                self.free(a0);
+                0
+            }
+            (0xa0, 0x37) => {
+                let size = a0.wrapping_mul(a1);
+                let addr = self.malloc(size);
+                fill(bus, addr, 0, size);
+                addr
+            }
+            (0xa0, 0x38) => {
+                let old = self
+                    .heap
+                    .iter()
+                    .find(|b| b.addr == a0 && !b.free)
+                    .map(|b| b.size);
+                let addr = self.malloc(a1);
+                if let (Some(size), true) = (old, addr != 0) {
+                    copy(bus, addr, a0, size.min(a1));
+                    self.free(a0);
+                }
+                addr
+            }
+            (0xa0, 0x39) => {
+                self.heap = vec![Block {
+                    addr: a0,
+                    size: a1,
+                    free: true,
+                }];
+                0
+            }
+
+            // TTY
+            (0xa0, 0x3c) | (0xb0, 0x3d) => {
+                self.tty.push(a0 as u8);
+                a0
+            }
+            (0xa0, 0x3e) | (0xb0, 0x3f) => {
+                self.tty.extend(read_string(bus, a0));
+                self.tty.push(b'\n');
+                0
+            }
+            (0xa0, 0x3f) => {
+                let mut index = 0;
+                let sp = cpu.registers()[29];
+                let format_string = read_string(cpu.bus(), a0);
+                let mut next_arg = || {
+                    index += 1;
+                    match index {
+                        1 => a1,
+                        2 => a2,
+                        3 => a3,
+                        _ => read32(cpu.bus(), sp + 4 * index),
+                    }
+                };
+                let output = printf(cpu.bus(), &format_string, &mut next_arg);
+                self.tty.extend(&output);
+                output.len() as u32
+            }
+
+            // Executables
+            (0xa0, 0x51) => {
+                let Some(disc) = disc else {
+                    return Reply::Return(ERROR);
+                };
+                let path = String::from_utf8_lossy(&read_string(bus, a0)).into_owned();
+                let exe = disc
+                    .find_file(strip_device(&path))
+                    .ok()
+                    .flatten()
+                    .and_then(|file| disc.read_file(file).ok())
+                    .and_then(|data| Exe::parse(&data).ok());
+                match exe {
+                    Some(exe) => {
+                        exe.load(cpu);
+                        if a1 != 0 {
+                            cpu.set_gpr(29, a1 + a2);
+                            cpu.set_gpr(30, a1 + a2);
+                        }
+                        return Reply::Jump;
+                    }
+                    None => {
+                        warn!("LoadExec: can't load {}", path);
+                        ERROR
+                    }
+                }
+            }
+
+            // Events
+            (0xb0, 0x07) => {
+                self.deliver_event(a0, a1);
+                0
+            }
+            (0xb0, 0x08) => self.open_event(a0, a1, a2),
+            (0xb0, 0x09) => self.with_event(a0, |_| true, |slot| *slot = None),
+            (0xb0, 0x0a) => self.wait_event(a0),
+            (0xb0, 0x0b) => self.with_event(
+                a0,
+                |e| e.delivered,
+                |slot| slot.as_mut().unwrap().delivered = false,
+            ),
+            (0xb0, 0x0c) => {
+                self.with_event(a0, |_| true, |slot| slot.as_mut().unwrap().enabled = true)
+            }
+            (0xb0, 0x0d) => {
+                self.with_event(a0, |_| true, |slot| slot.as_mut().unwrap().enabled = false)
+            }
+            (0xb0, 0x20) => {
+                for event in self.events.iter_mut().flatten() {
+                    if event.class == a0 && event.spec == a1 {
+                        event.delivered = false;
+                    }
+                }
+                0
+            }
+
+            // Pads and memory cards
+            (0xb0, 0x12) => {
+                self.pad_buffers = Some([a0, a2]);
+                2
+            }
+            (0xb0, 0x13) => {
+                self.pads_started = true;
+                1
+            }
+            (0xb0, 0x14) => {
+                self.pads_started = false;
+                1
+            }
+            (0xb0, 0x4b) | (0xb0, 0x4c) => 1,
+
+            (0xb0, 0x56) => C0_TABLE,
+            (0xb0, 0x57) => B0_TABLE,
+
+            // Setup functions with nothing to set up
+            (0xa0, 0x44)
+            | (0xa0, 0x96..=0x99)
+            | (0xa0, 0x9f)
+            | (0xb0, 0x02..=0x06)
+            | (0xb0, 0x18)
+            | (0xb0, 0x19)
+            | (0xb0, 0x4a)
+            | (0xb0, 0x50)
+            | (0xb0, 0x5b)
+            | (0xc0, 0x00..=0x03)
+            | (0xc0, 0x07..=0x0d)
+            | (0xc0, 0x12)
+            | (0xc0, 0x1c) => 0,
+            (0xa0, 0x54..=0x56) | (0xa0, 0x70..=0x72) => 1,
+
+            _ => return Reply::Unimplemented,
+        };
+        Reply::Return(value)
+    }
+
+    /// Syscalls and interrupts. There are no interrupt handlers, so interrupts are
+    /// just acknowledged. Other exceptions would crash the real kernel, so they're
+    /// errors.
+    pub fn exception<R: Renderer>(&mut self, cpu: &mut CPU<R>) -> Result<(), Error> {
+        let cause = cpu.cop0_register(13).unwrap_or(0);
+        let epc = cpu.cop0_register(14).unwrap_or(0);
+        let mut sr = cpu.cop0_register(12).unwrap_or(0);
+        let delay_slot = cause & (1 << 31) != 0;
+
+        let resume = match (cause >> 2) & 0x1f {
+            0 => {
+                // Software and hardware interrupts would fire again right away
+                cpu.set_cop0_register(13, 0);
+                cpu.bus_mut().interrupt_controller_mut().acknowledge(0);
+                epc
+            }
+            8 => {
+                // The interrupt enable bits as they were before the exception
+                let v0 = match cpu.registers()[4] {
+                    // EnterCriticalSection
+                    1 => {
+                        let enabled = sr & 0x404 == 0x404;
+                        sr &= !0x404;
+                        enabled as u32
+                    }
+                    // ExitCriticalSection
+                    2 => {
+                        sr |= 0x404;
+                        0
+                    }
+                    _ => 0,
+                };
+                cpu.set_gpr(2, v0);
+                // After the syscall, which is after the branch if it's in its
+                // delay slot
+                match delay_slot {
+                    true => branch_destination(cpu, epc),
+                    false => epc.wrapping_add(4),
+                }
+            }
+            code => {
+                return Err(Error::UnhandledException {
+                    code,
+                    pc: if delay_slot { epc.wrapping_add(4) } else { epc },
+                })
+            }
+        };
+
+        // Same as RFE
+        let mode = sr & 0x3f;
+        sr = (sr & !0xf) | (mode >> 2);
+        cpu.set_cop0_register(12, sr);
+        cpu.set_pc(resume);
+        Ok(())
+    }
+
+    /// Fills the InitPad buffers, which the real kernel does from the vblank IRQ.
+    /// `buttons` are active low, None for empty ports.
+    pub fn vblank<R: Renderer>(&self, bus: &mut Bus<R>, buttons: [Option<u16>; 2]) {
+        let (Some(buffers), true) = (self.pad_buffers, self.pads_started) else {
+            return;
+        };
+
+        for (addr, buttons) in buffers.into_iter().zip(buttons) {
+            let data = match buttons {
+                Some(b) => [0x00, 0x41, b as u8, (b >> 8) as u8],
+                None => [0xff, 0x00, 0xff, 0xff],
+            };
+            for (i, byte) in data.into_iter().enumerate() {
+                write8(bus, addr + i as u32, byte);
+            }
+        }
+    }
+
+    fn open<R: Renderer>(&mut self, bus: &Bus<R>, disc: Option<&mut Disc>, name: u32) -> u32 {
+        let name = String::from_utf8_lossy(&read_string(bus, name)).into_owned();
+        if !name.to_ascii_lowercase().starts_with("cdrom:") {
+            warn!(
+                "HLE kernel: can't open {}, only CD files are supported",
+                name
+            );
+            return ERROR;
+        }
+
+        let file = disc.and_then(|disc| disc.find_file(strip_device(&name)).ok().flatten());
+        let Some((lba, size)) = file else {
+            return ERROR;
+        };
+
+        let file = OpenFile {
+            lba,
+            size,
+            position: 0,
+        };
+        let fd = match self.files.iter().position(|f| f.is_none()) {
+            Some(index) => {
+                self.files[index] = Some(file);
+                index
+            }
+            None => {
+                self.files.push(Some(file));
+                self.files.len() - 1
+            }
+        };
+        (fd + FIRST_FD) as u32
+    }
+
+    fn file(&mut self, fd: u32) -> Option<&mut OpenFile> {
+        let index = (fd as usize).checked_sub(FIRST_FD)?;
+        self.files.get_mut(index)?.as_mut()
+    }
+
+    fn seek(&mut self, fd: u32, offset: u32, whence: u32) -> u32 {
+        let Some(file) = self.file(fd) else {
+            return ERROR;
+        };
+        file.position = match whence {
+            0 => offset,
+            1 => file.position.wrapping_add(offset),
+            _ => file.size.wrapping_add(offset),
+        };
+        file.position
+    }
+
+    fn read<R: Renderer>(
+        &mut self,
+        bus: &mut Bus<R>,
+        disc: Option<&mut Disc>,
+        fd: u32,
+        dst: u32,
+        len: u32,
+    ) -> u32 {
+        let (Some(file), Some(disc)) = (self.file(fd), disc) else {
+            return ERROR;
+        };
+
+        let len = len.min(file.size.saturating_sub(file.position));
+        let mut done = 0;
+        while done < len {
+            let position = file.position + done;
+            let sector = file.lba + position / DATA_SIZE as u32;
+            let Ok(data) = disc.read_data(sector) else {
+                return ERROR;
+            };
+
+            let start = position as usize % DATA_SIZE;
+            let count = (DATA_SIZE - start).min((len - done) as usize);
+            for (i, &byte) in data[start..start + count].iter().enumerate() {
+                write8(bus, dst + done + i as u32, byte);
+            }
+            done += count as u32;
+        }
+
+        file.position += len;
+        len
+    }
+
+    fn write<R: Renderer>(&mut self, bus: &Bus<R>, fd: u32, src: u32, len: u32) -> u32 {
+        if fd as usize >= FIRST_FD {
+            return ERROR;
+        }
+        self.tty.extend(read(bus, src, len));
+        len
+    }
+
+    fn close(&mut self, fd: u32) -> u32 {
+        match self.file(fd) {
+            Some(_) => {
+                self.files[fd as usize - FIRST_FD] = None;
+                fd
+            }
+            None => ERROR,
+        }
+    }
+
+    /// First fit, splitting the block found.
+    fn malloc(&mut self, size: u32) -> u32 {
+        let size = (size + 3) & !3;
+        let Some(index) = self.heap.iter().position(|b| b.free && b.size >= size) else {
+            return 0;
+        };
+
+        let block = &mut self.heap[index];
+        let addr = block.addr;
+        if block.size > size {
+            let rest = Block {
+                addr: addr + size,
+                size: block.size - size,
+                free: true,
+            };
+            block.size = size;
+            self.heap.insert(index + 1, rest);
+        }
+        self.heap[index].free = false;
+        addr
+    }
+
+    fn free(&mut self, addr: u32) {
+        if let Some(block) = self.heap.iter_mut().find(|b| b.addr == addr) {
+            block.free = true;
+        }
+
+        // Merge neighbouring free blocks
+        let mut i = 0;
+        while i + 1 < self.heap.len() {
+            if self.heap[i].free && self.heap[i + 1].free {
+                self.heap[i].size += self.heap[i + 1].size;
+                self.heap.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Only events that get marked as ready work, there's no calling back into
+    /// the game. Opening one that would call a handler fails.
+    fn open_event(&mut self, class: u32, spec: u32, mode: u32) -> u32 {
+        if mode != EVENT_MODE_READY {
+            warn!(
+                "HLE kernel: OpenEvent(0x{:08X}, 0x{:04X}) with mode 0x{:04X}, only ready events are supported",
+                class, spec, mode
+            );
+            return ERROR;
+        }
+
+        let event = Event {
+            class,
+            spec,
+            mode,
+            enabled: false,
+            delivered: false,
+        };
+        let index = match self.events.iter().position(|e| e.is_none()) {
+            Some(index) => {
+                self.events[index] = Some(event);
+                index
+            }
+            None => {
+                self.events.push(Some(event));
+                self.events.len() - 1
+            }
+        };
+        EVENT_HANDLE | index as u32
+    }
+
+    /// Runs `update` on the event slot if `handle` is open and `test` passes.
+    /// Returns 1 if it did, 0 otherwise.
+    fn with_event<T, U>(&mut self, handle: u32, test: T, update: U) -> u32
+    where
+        T: FnOnce(&Event) -> bool,
+        U: FnOnce(&mut Option<Event>),
+    {
+        let index = (handle & 0xffff) as usize;
+        match self.events.get_mut(index) {
+            Some(slot) if slot.as_ref().is_some_and(test) => {
+                update(slot);
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Nothing raises events on its own yet, so waiting for one that hasn't been
+    /// delivered would block forever. That fails instead of hanging.
+    fn wait_event(&mut self, handle: u32) -> u32 {
+        match self.with_event(
+            handle,
+            |e| e.delivered,
+            |slot| slot.as_mut().unwrap().delivered = false,
+        ) {
+            0 => {
+                warn!(
+                    "HLE kernel: WaitEvent(0x{:08X}) on an event that hasn't been delivered",
+                    handle
+                );
+                0
+            }
+            ready => ready,
+        }
+    }
+
+    fn deliver_event(&mut self, class: u32, spec: u32) {
+        for event in self.events.iter_mut().flatten() {
+            if event.enabled && event.class == class && event.spec == spec {
+                event.delivered = true;
+            }
+        }
+    }
+
+    /// Everything but the TTY output, which is passed on after every call.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        match &self.boot {
+            Boot::Disc => w.string(""),
+            Boot::Exe(path) => w.string(&path.to_string_lossy()),
+        }
+
+        w.u32(self.events.len() as u32);
+        for event in &self.events {
+            w.bool(event.is_some());
+            if let Some(event) = event {
+                w.u32s(&[event.class, event.spec, event.mode]);
+                w.bool(event.enabled);
+                w.bool(event.delivered);
+            }
+        }
+
+        w.u32(self.files.len() as u32);
+        for file in &self.files {
+            w.bool(file.is_some());
+            if let Some(file) = file {
+                w.u32s(&[file.lba, file.size, file.position]);
+            }
+        }
+
+        w.u32(self.heap.len() as u32);
+        for block in &self.heap {
+            w.u32s(&[block.addr, block.size]);
+            w.bool(block.free);
+        }
+
+        w.bool(self.pad_buffers.is_some());
+        w.u32s(&self.pad_buffers.unwrap_or([0; 2]));
+        w.bool(self.pads_started);
+        w.u32(self.seed);
+    }
+
+    pub fn load_state(r: &mut StateReader) -> Result<Hle, Error> {
+        let boot = match r.string()? {
+            path if path.is_empty() => Boot::Disc,
+            path => Boot::Exe(path.into()),
+        };
+        let mut hle = Hle::new(boot);
+
+        for _ in 0..r.u32()? {
+            let event = match r.bool()? {
+                true => Some(Event {
+                    class: r.u32()?,
+                    spec: r.u32()?,
+                    mode: r.u32()?,
+                    enabled: r.bool()?,
+                    delivered: r.bool()?,
+                }),
+                false => None,
+            };
+            hle.events.push(event);
+        }
+
+        for _ in 0..r.u32()? {
+            let file = match r.bool()? {
+                true => Some(OpenFile {
+                    lba: r.u32()?,
+                    size: r.u32()?,
+                    position: r.u32()?,
+                }),
+                false => None,
+            };
+            hle.files.push(file);
+        }
+
+        for _ in 0..r.u32()? {
+            hle.heap.push(Block {
+                addr: r.u32()?,
+                size: r.u32()?,
+                free: r.bool()?,
+            });
+        }
+
+        let has_pad_buffers = r.bool()?;
+        let pad_buffers = [r.u32()?, r.u32()?];
+        hle.pad_buffers = has_pad_buffers.then_some(pad_buffers);
+        hle.pads_started = r.bool()?;
+        hle.seed = r.u32()?;
+        Ok(hle)
+    }
+}
+
+/// The file named by SYSTEM.CNF's BOOT line.
+fn boot_path(config: &str) -> Option<String> {
+    config.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim().eq_ignore_ascii_case("BOOT")).then(|| strip_device(value.trim()).to_string())
+    })
+}
+
+/// `cdrom:\FOO.EXE;1` to `\FOO.EXE;1`.
+fn strip_device(path: &str) -> &str {
+    path.split_once(':').map_or(path, |(_, rest)| rest)
+}
+
+fn read8<R: Renderer>(bus: &Bus<R>, addr: u32) -> u8 {
+    bus.peek(addr).unwrap_or(0)
+}
+
+fn read32<R: Renderer>(bus: &Bus<R>, addr: u32) -> u32 {
+    bus.peek_word(addr).unwrap_or(0)
+}
+
+fn write8<R: Renderer>(bus: &mut Bus<R>, addr: u32, value: u8) {
+    bus.poke(addr, value);
+}
+
+fn write32<R: Renderer>(bus: &mut Bus<R>, addr: u32, value: u32) {
+    for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+        bus.poke(addr + i as u32, byte);
+    }
+}
+
+fn read<R: Renderer>(bus: &Bus<R>, addr: u32, len: u32) -> Vec<u8> {
+    (0..len).map(|i| read8(bus, addr.wrapping_add(i))).collect()
+}
+
+fn read_string<R: Renderer>(bus: &Bus<R>, addr: u32) -> Vec<u8> {
+    (0..MAX_STRING)
+        .map(|i| read8(bus, addr.wrapping_add(i)))
+        .take_while(|&c| c != 0)
+        .collect()
+}
+
+/// Copies at most `max` characters, with the NUL if it fits. Returns the length
+/// copied without it.
+fn copy_string<R: Renderer>(bus: &mut Bus<R>, dst: u32, src: u32, max: u32) -> u32 {
+    let s = read_string(bus, src);
+    let length = (s.len() as u32).min(max);
+    for (i, &c) in s[..length as usize].iter().enumerate() {
+        write8(bus, dst + i as u32, c);
+    }
+    if length < max {
+        write8(bus, dst + length, 0);
+    }
+    length
+}
+
+/// Handles overlapping ranges like memmove.
+fn copy<R: Renderer>(bus: &mut Bus<R>, dst: u32, src: u32, len: u32) {
+    for (i, byte) in read(bus, src, len).into_iter().enumerate() {
+        write8(bus, dst + i as u32, byte);
+    }
+}
+
+fn fill<R: Renderer>(bus: &mut Bus<R>, dst: u32, value: u8, len: u32) {
+    for i in 0..len {
+        write8(bus, dst + i, value);
+    }
+}
+
+/// strcmp style result.
+fn compare(a: &[u8], b: &[u8]) -> u32 {
+    a.cmp(b) as i32 as u32
+}
+
+/// strtol without the end pointer. Base 0 picks it from the prefix.
+fn parse_int(s: &[u8], base: u32) -> i32 {
+    let s = String::from_utf8_lossy(s);
+    let s = s.trim_start();
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let (base, s) = match (base, s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))) {
+        (0 | 16, Some(rest)) => (16, rest),
+        (0, None) if s.starts_with('0') && s.len() > 1 => (8, &s[1..]),
+        (0, None) => (10, s),
+        (base, _) => (base, s),
+    };
+
+    let digits: String = s.chars().take_while(|c| c.is_digit(base)).collect();
+    let value = u32::from_str_radix(&digits, base).unwrap_or(0) as i32;
+    if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    }
+}
+
+/// The kernel's printf: flags, width, precision and the d i u x X o c s p
+/// conversions.
+fn printf<R: Renderer>(bus: &Bus<R>, format: &[u8], next_arg: &mut dyn FnMut() -> u32) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut chars = format.iter().copied().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != b'%' {
+            output.push(c);
+            continue;
+        }
+
+        let (mut left, mut zero) = (false, false);
+        while let Some(&flag @ (b'-' | b'0' | b'+' | b' ' | b'#')) = chars.peek() {
+            left |= flag == b'-';
+            zero |= flag == b'0';
+            chars.next();
+        }
+
+        let number = |chars: &mut std::iter::Peekable<_>| {
+            let mut value = None;
+            while let Some(&digit @ b'0'..=b'9') = chars.peek() {
+                value = Some(value.unwrap_or(0) * 10 + (digit - b'0') as usize);
+                chars.next();
+            }
+            value
+        };
+        let width = number(&mut chars).unwrap_or(0);
+        let precision = match chars.peek() {
+            Some(b'.') => {
+                chars.next();
+                number(&mut chars)
+            }
+            _ => None,
+        };
+        while let Some(b'l' | b'h') = chars.peek() {
+            chars.next();
+        }
+
+        let text = match chars.next() {
+            Some(b'd' | b'i') => (next_arg() as i32).to_string(),
+            Some(b'u') => next_arg().to_string(),
+            Some(b'x') => format!("{:x}", next_arg()),
+            Some(b'X') => format!("{:X}", next_arg()),
+            Some(b'o') => format!("{:o}", next_arg()),
+            Some(b'p') => format!("{:08x}", next_arg()),
+            Some(b'c') => (next_arg() as u8 as char).to_string(),
+            Some(b's') => {
+                let mut s = read_string(bus, next_arg());
+                if let Some(precision) = precision {
+                    s.truncate(precision);
+                }
+                String::from_utf8_lossy(&s).into_owned()
+            }
+            Some(b'%') => "%".into(),
+            Some(other) => format!("%{}", other as char),
+            None => break,
+        };
+
+        let padding = width.saturating_sub(text.len());
+        let mut text = text.as_bytes();
+        if zero && !left {
+            // The sign goes before the zeroes
+            if let Some(rest) = text.strip_prefix(b"-") {
+                output.push(b'-');
+                text = rest;
+            }
+            output.extend(std::iter::repeat_n(b'0', padding));
+        } else if !left {
+            output.extend(std::iter::repeat_n(b' ', padding));
+        }
+        output.extend(text);
+        if left {
+            output.extend(std::iter::repeat_n(b' ', padding));
+        }
+    }
+    output
+}
+
+/// Where the branch or jump at `pc` goes, going by the registers as they are now.
+/// Links have already been written, so this works as long as the delay slot
+/// didn't change the registers the branch looks at.
+fn branch_destination<R: Renderer>(cpu: &CPU<R>, pc: u32) -> u32 {
+    let i = Instruction {
+        value: cpu.bus().peek_word(pc).unwrap_or(0),
+    };
+    let registers = cpu.registers();
+    let (rs, rt) = (
+        registers[i.rs().0 as usize] as i32,
+        registers[i.rt().0 as usize] as i32,
+    );
+    let branch = pc.wrapping_add(4).wrapping_add(i.imm16_se() << 2);
+    let fallthrough = pc.wrapping_add(8);
+
+    let taken = match i.opcode() {
+        0x00 if matches!(i.secondary_opcode(), 0x08 | 0x09) => return rs as u32,
+        0x02 | 0x03 => return (pc.wrapping_add(4) & 0xf000_0000) | (i.imm_jump() << 2),
+        0x01 => (rs < 0) != (i.rt().0 & 1 != 0),
+        0x04 => rs == rt,
+        0x05 => rs != rt,
+        0x06 => rs <= 0,
+        0x07 => rs > 0,
+        _ => {
+            warn!(
+                "HLE kernel: exception in the delay slot of 0x{:08x}",
+                i.value
+            );
+            return fallthrough;
+        }
+    };
+    if taken {
+        branch
+    } else {
+        fallthrough
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bios::BIOS;
+    use crate::gpu::GPU;
+    use crate::kernel::Boot;
+    use crate::memory::RAM;
+    use crate::renderer::NullRenderer;
+
+    const CODE: u32 = 0x8000_1000;
+    const SYSCALL: u32 = 0x0000_000c;
+
+    /// Runs `code` from RAM up to the first exception, then lets the HLE kernel
+    /// handle it.
+    fn run(code: &[u32], registers: &[(usize, u32)]) -> (CPU<NullRenderer>, Result<(), Error>) {
+        let bus = Bus::new(BIOS::empty(), RAM::new(), GPU::new(NullRenderer));
+        let mut cpu = CPU::new(bus);
+        for (i, word) in code.iter().enumerate() {
+            for (j, byte) in word.to_le_bytes().into_iter().enumerate() {
+                cpu.bus_mut().poke(CODE + (i * 4 + j) as u32, byte);
+            }
+        }
+        for &(index, value) in registers {
+            cpu.set_gpr(index, value);
+        }
+        cpu.set_pc(CODE);
+
+        while cpu.pc() != EXCEPTION_VECTOR {
+            cpu.exec_next_instruction().unwrap();
+        }
+        let result = Hle::new(Boot::Disc).exception(&mut cpu);
+        (cpu, result)
+    }
+
+    #[test]
+    fn syscall() {
+        let (cpu, result) = run(&[SYSCALL], &[(4, 2)]);
+        result.unwrap();
+        assert_eq!(cpu.pc(), CODE + 4);
+    }
+
+    #[test]
+    fn syscall_in_taken_branch_delay_slot() {
+        // beq $zero, $zero, +12
+        let (cpu, result) = run(&[0x1000_0002, SYSCALL], &[(4, 2)]);
+        result.unwrap();
+        assert_eq!(cpu.pc(), CODE + 12);
+    }
+
+    #[test]
+    fn syscall_in_untaken_branch_delay_slot() {
+        // bne $zero, $zero, +12
+        let (cpu, result) = run(&[0x1400_0002, SYSCALL], &[(4, 2)]);
+        result.unwrap();
+        assert_eq!(cpu.pc(), CODE + 8);
+    }
+
+    #[test]
+    fn syscall_in_jump_delay_slot() {
+        // jr $t0
+        let (cpu, result) = run(&[0x0100_0008, SYSCALL], &[(4, 2), (8, 0x8000_2000)]);
+        result.unwrap();
+        assert_eq!(cpu.pc(), 0x8000_2000);
+    }
+
+    #[test]
+    fn events() {
+        let mut hle = Hle::new(Boot::Disc);
+        let event = hle.open_event(0xf000_0003, 0x0020, EVENT_MODE_READY);
+        assert_eq!(event, EVENT_HANDLE);
+        assert_eq!(hle.wait_event(event), 0);
+
+        // Delivered once enabled
+        hle.deliver_event(0xf000_0003, 0x0020);
+        assert_eq!(hle.wait_event(event), 0);
+        hle.with_event(
+            event,
+            |_| true,
+            |slot| slot.as_mut().unwrap().enabled = true,
+        );
+        hle.deliver_event(0xf000_0003, 0x0020);
+        assert_eq!(hle.wait_event(event), 1);
+        assert_eq!(hle.wait_event(event), 0);
+    }
+
+    #[test]
+    fn event_handlers_are_refused() {
+        let mut hle = Hle::new(Boot::Disc);
+        assert_eq!(hle.open_event(0xf000_0003, 0x0020, 0x1000), ERROR);
+        assert!(hle.events.is_empty());
+    }
+
+    #[test]
+    fn reserved_instruction() {
+        let (_, result) = run(&[0xfc00_0000], &[]);
+        assert!(matches!(
+            result,
+            Err(Error::UnhandledException { code: 10, pc: CODE })
+        ));
+    }
+}
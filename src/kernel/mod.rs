@@ -0,0 +1,285 @@
+/**
+ * Hooks on the BIOS kernel's A0/B0/C0 call vectors: programs jump to one of them
+ * with the function number in $t1. Calls are logged at debug level with their
+ * decoded arguments, and the TTY output is collected.
+ *
+ * Without a BIOS the calls are handled by the HLE kernel instead.
+ */
+mod calls;
+mod exe;
+mod hle;
+
+use crate::cpu::CPU;
+use crate::disc::Disc;
+use crate::error::{DiscError, Error};
+use crate::memory::Bus;
+use crate::renderer::Renderer;
+use crate::savestate::{StateReader, StateWriter};
+use std::io::Write;
+
+use self::exe::Exe;
+use self::hle::{Hle, Reply, EXCEPTION_VECTOR};
+
+pub use self::hle::Boot;
+
+/// Longest string argument shown in call logs.
+const MAX_STRING: usize = 64;
+
+/// A kernel function call, caught on its way through a vector.
+pub struct Call {
+    /// Address of the vector.
+    pub pc: u32,
+    /// 0xa0, 0xb0 or 0xc0.
+    pub table: u32,
+    pub function: u32,
+    pub args: [u32; 4],
+}
+
+impl Call {
+    /// The call the CPU is about to make, if it's at a vector.
+    pub fn at<R: Renderer>(cpu: &CPU<R>) -> Option<Call> {
+        let pc = cpu.pc();
+        let table = pc & 0x1fff_ffff;
+        if !matches!(table, 0xa0 | 0xb0 | 0xc0) {
+            return None;
+        }
+
+        let r = cpu.registers();
+        Some(Call {
+            pc,
+            table,
+            function: r[9],
+            args: [r[4], r[5], r[6], r[7]],
+        })
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.entry().map(|&(_, name, _)| name)
+    }
+
+    fn entry(&self) -> Option<&'static (u32, &'static str, &'static str)> {
+        let table = match self.table {
+            0xa0 => calls::A0,
+            0xb0 => calls::B0,
+            _ => calls::C0,
+        };
+        table.iter().find(|&&(n, _, _)| n == self.function)
+    }
+
+    fn is_putchar(&self) -> bool {
+        matches!((self.table, self.function), (0xa0, 0x3c) | (0xb0, 0x3d))
+    }
+
+    /// `printf("Hello %d\n", 0x5)`, or `A0:FF(...)` for unknown functions.
+    fn describe<R: Renderer>(&self, bus: &Bus<R>) -> String {
+        let Some(&(_, name, signature)) = self.entry() else {
+            return format!("{:X}:{:02X}(...)", self.table, self.function);
+        };
+
+        let args: Vec<String> = signature
+            .chars()
+            .zip(self.args)
+            .map(|(kind, arg)| match kind {
+                's' => match read_string(bus, arg) {
+                    Some(s) => format!("{:?}", s),
+                    None => format!("0x{:08x}", arg),
+                },
+                'd' => (arg as i32).to_string(),
+                _ => format!("0x{:x}", arg),
+            })
+            .collect();
+        format!("{}({})", name, args.join(", "))
+    }
+}
+
+pub struct Kernel {
+    /// Where TTY output goes. Without one, it's logged a line at a time.
+    tty: Option<Box<dyn Write>>,
+    line: Vec<u8>,
+    hle: Option<Hle>,
+    /// MD5 of the EXE booted with `Boot::Exe`.
+    exe_hash: Option<[u8; 16]>,
+}
+
+impl Kernel {
+    pub fn new() -> Kernel {
+        Kernel {
+            tty: None,
+            line: Vec::new(),
+            hle: None,
+            exe_hash: None,
+        }
+    }
+
+    /// What the HLE kernel booted, None when running the real BIOS.
+    pub fn hle_boot(&self) -> Option<Boot> {
+        self.hle.as_ref().map(|hle| hle.boot.clone())
+    }
+
+    /// MD5 of the EXE the HLE kernel booted, None when it booted the disc or
+    /// isn't running.
+    pub fn exe_hash(&self) -> Option<[u8; 16]> {
+        self.exe_hash
+    }
+
+    /// Switches to the HLE kernel, starting over, and loads the EXE to boot.
+    pub fn boot_hle<R: Renderer>(
+        &mut self,
+        boot: Boot,
+        cpu: &mut CPU<R>,
+        disc: Option<&mut Disc>,
+    ) -> Result<(), Error> {
+        let hle = self.hle.insert(Hle::new(boot.clone()));
+        self.exe_hash = None;
+
+        // Exception vectors in RAM, where we catch them
+        cpu.set_cop0_register(12, 0);
+
+        match boot {
+            Boot::Exe(path) => {
+                let data = std::fs::read(&path).map_err(|source| Error::Io { path, source })?;
+                Exe::parse(&data)?.load(cpu);
+                self.exe_hash = Some(md5::compute(&data).0);
+                Ok(())
+            }
+            Boot::Disc => match disc {
+                Some(disc) => hle.boot_disc(cpu, disc),
+                None => Err(Error::InvalidDisc(DiscError::NoDisc)),
+            },
+        }
+    }
+
+    /// Runs kernel calls and exception handlers with the HLE kernel. Returns false
+    /// when there's nothing to do, and the CPU should carry on.
+    pub fn hle_step<R: Renderer>(
+        &mut self,
+        cpu: &mut CPU<R>,
+        disc: Option<&mut Disc>,
+    ) -> Result<bool, Error> {
+        let Some(hle) = self.hle.as_mut() else {
+            return Ok(false);
+        };
+
+        if cpu.pc() == EXCEPTION_VECTOR {
+            hle.exception(cpu)?;
+            return Ok(true);
+        }
+
+        let Some(call) = Call::at(cpu) else {
+            return Ok(false);
+        };
+        if !call.is_putchar() {
+            debug!(
+                "{:X}:{:02X} {}",
+                call.table,
+                call.function,
+                call.describe(cpu.bus())
+            );
+        }
+
+        let reply = hle.call(&call, cpu, disc);
+        for c in std::mem::take(&mut hle.tty) {
+            self.putchar(c);
+        }
+
+        let ra = cpu.registers()[31];
+        match reply {
+            Reply::Return(value) => {
+                cpu.set_gpr(2, value);
+                cpu.set_pc(ra);
+            }
+            Reply::Jump => (),
+            Reply::Unimplemented => {
+                warn!("HLE kernel: {} isn't implemented", call.describe(cpu.bus()));
+                cpu.set_gpr(2, 0);
+                cpu.set_pc(ra);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Once per frame, for what the kernel does in its vblank handler.
+    pub fn vblank<R: Renderer>(&mut self, bus: &mut Bus<R>, buttons: [Option<u16>; 2]) {
+        if let Some(hle) = self.hle.as_ref() {
+            hle.vblank(bus, buttons);
+        }
+    }
+
+    /// The HLE kernel's bookkeeping, if it's running.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.hle.is_some());
+        if let Some(hle) = self.hle.as_ref() {
+            hle.save_state(w);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.hle = match r.bool()? {
+            true => Some(Hle::load_state(r)?),
+            false => None,
+        };
+        Ok(())
+    }
+
+    pub fn set_tty_output(&mut self, output: Option<Box<dyn Write>>) {
+        self.tty = output;
+    }
+
+    /// Records a call that went through its vector. printf, puts and friends all
+    /// end up in putchar, so that's the one TTY output comes from.
+    pub fn called<R: Renderer>(&mut self, call: &Call, bus: &Bus<R>) {
+        if call.is_putchar() {
+            self.putchar(call.args[0] as u8);
+            return;
+        }
+
+        debug!(
+            "{:X}:{:02X} {}",
+            call.table,
+            call.function,
+            call.describe(bus)
+        );
+    }
+
+    pub fn putchar(&mut self, c: u8) {
+        if let Some(tty) = self.tty.as_mut() {
+            let written = tty.write_all(&[c]);
+            let flushed = match c {
+                b'\n' => tty.flush(),
+                _ => Ok(()),
+            };
+            if let Err(e) = written.and(flushed) {
+                error!("Failed to write TTY output: {}", e);
+                self.tty = None;
+            }
+            return;
+        }
+
+        match c {
+            b'\n' => {
+                info!("TTY: {}", String::from_utf8_lossy(&self.line));
+                self.line.clear();
+            }
+            b'\r' => (),
+            _ => self.line.push(c),
+        }
+    }
+}
+
+impl Default for Kernel {
+    fn default() -> Kernel {
+        Kernel::new()
+    }
+}
+
+/// The NUL terminated string at `addr`, cut short if it's too long.
+fn read_string<R: Renderer>(bus: &Bus<R>, addr: u32) -> Option<String> {
+    let mut bytes = Vec::new();
+    for i in 0..MAX_STRING as u32 {
+        match bus.peek(addr.wrapping_add(i))? {
+            0 => break,
+            b => bytes.push(b),
+        }
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
@@ -0,0 +1,34 @@
+//! rstationx, a PlayStation emulator. `System` puts the console together,
+//! frontends plug in a `Renderer` and feed it input.
+
+#[macro_use]
+extern crate log;
+
+pub mod avrecord;
+pub mod bios;
+pub mod cheats;
+pub mod cpu;
+pub mod debugger;
+pub mod disc;
+pub mod error;
+pub mod gdb;
+pub mod gpu;
+pub mod kernel;
+pub mod mdec;
+pub mod memcard;
+pub mod memory;
+pub mod movie;
+pub mod renderer;
+pub mod rewind;
+pub mod savestate;
+pub mod screenshot;
+pub mod script;
+pub mod search;
+pub mod session;
+pub mod sio;
+pub mod system;
+pub mod tracer;
+mod utils;
+
+pub use error::Error;
+pub use system::System;
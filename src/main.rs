@@ -1,11 +1,7 @@
 // #![allow(dead_code)]
-mod bios;
-mod cpu;
+mod cardtool;
 mod glrenderer;
-mod gpu;
-mod memory;
-mod renderer;
-mod utils;
+mod input;
 
 #[macro_use]
 extern crate log;
@@ -13,45 +9,404 @@ extern crate env_logger;
 extern crate gl;
 extern crate sdl2;
 
-use std::os;
-
+use rstationx::bios::BIOS;
+use rstationx::cheats::Cheats;
+use rstationx::debugger::{Action, Debugger};
+use rstationx::disc::Disc;
+use rstationx::gdb::GdbStub;
+use rstationx::kernel::Boot;
+use rstationx::renderer::{NullRenderer, Renderer};
+use rstationx::script::Script;
+use rstationx::session::{Capture, Session};
+use rstationx::sio::{MemoryCard, PadState};
+use rstationx::system::ErrorPolicy;
+use rstationx::tracer::{Tracer, Trigger};
+use rstationx::System;
+use sdl2::controller;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+const MEMCARD_PATHS: [&str; 2] = ["./memcards/memcard1.mcr", "./memcards/memcard2.mcr"];
+const CHEAT_DIR: &str = "./cheats";
+/// Default rewind buffer size, in MiB.
+const REWIND_BUFFER: usize = 64;
 
 fn main() {
     env_logger::Builder::from_default_env()
         .format_timestamp(None)
         .init();
 
-    let sdl_context = sdl2::init().unwrap();
-    async fn watch_events(mut event_pump: sdl2::EventPump) {
-        loop {
-            for e in event_pump.poll_iter() {
-                match e {
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => std::process::exit(0),
-                    Event::Quit { .. } => std::process::exit(0),
-                    _ => (),
-                }
-            }
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|s| s.as_str()) == Some("memcard") {
+        if let Err(e) = cardtool::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
+        return;
+    }
+
+    // --headless runs without a window, to play back a movie with --play FILE, run
+    // a script with --script FILE, or for --frames N frames
+    if args.iter().any(|a| a == "--headless") {
+        headless(&args);
     }
-    let _ = watch_events(sdl_context.event_pump().unwrap());
+
+    let sdl_context = sdl(sdl2::init());
+    let mut event_pump = sdl(sdl_context.event_pump());
+    let mut input = input::Input::new(sdl(sdl_context.game_controller()));
 
     let renderer = glrenderer::GLRenderer::new(sdl_context);
 
-    let bios_path = std::path::Path::new("./bios/bios");
-    let bios = bios::BIOS::new(bios_path).unwrap();
+    let mut system = setup(&args, renderer);
+
+    // With --debug the emulation starts paused in the debugger, F12 breaks into it
+    let mut debugger = args.iter().any(|a| a == "--debug").then(|| {
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        debugger
+    });
+
+    // --gdb PORT waits for a GDB connection, which then controls the emulation
+    let mut gdb = args.iter().any(|a| a == "--gdb").then(|| {
+        let port = option(&args, "--gdb").and_then(|p| p.parse().ok());
+        GdbStub::listen(port.unwrap_or(2345)).unwrap_or_else(|e| {
+            error!("Can't wait for GDB: {}", e);
+            std::process::exit(1);
+        })
+    });
 
-    let gpu = gpu::GPU::new(renderer);
-    let ram = memory::RAM::new();
-    let bus = memory::Bus::new(bios, ram, gpu);
-    let mut cpu = cpu::CPU::new(bus);
+    // F5 saves a state, F7 loads it and F6 picks the next slot. Holding F8
+    // rewinds, F9 takes a screenshot and F10 dumps VRAM
+    let mut session = session(&args, &mut system);
+    let mut pad = PadState::new();
 
     info!("Starting emulation loop...");
     loop {
-        cpu.exec_next_instruction();
+        if let Some(stub) = gdb.as_mut() {
+            match stub.run_frame(&mut system, &mut session, &pad) {
+                Ok(true) => (),
+                Ok(false) => {
+                    info!("GDB detached");
+                    gdb = None;
+                }
+                Err(e) => {
+                    error!("GDB connection lost: {}", e);
+                    gdb = None;
+                }
+            }
+        } else if let Some(debugger) = debugger.as_mut() {
+            if debugger.paused() {
+                let action = debugger.repl(&mut system, &mut std::io::stdin().lock());
+                if action == Action::Quit {
+                    quit(&mut system, 0);
+                }
+            }
+            debugger.run_frame(&mut system, &mut session, &pad);
+        } else if let Err(e) = session.run_frame(&mut system, &pad) {
+            error!("Emulation stopped: {}", e);
+            quit(&mut system, 1);
+        }
+
+        for e in event_pump.poll_iter() {
+            match e {
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                }
+                | Event::Quit { .. } => quit(&mut system, 0),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                }
+                | Event::ControllerButtonDown {
+                    button: controller::Button::Guide,
+                    ..
+                } => {
+                    if let Some(pad) = system.pad_mut(0) {
+                        pad.toggle_analog();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    if let Err(e) = session.save_state(&system) {
+                        error!("Failed to save state: {}", e);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    session.next_slot();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    if let Err(e) = session.load_state(&mut system) {
+                        error!("Can't load state: {}", e);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => session.set_rewinding(true),
+                Event::KeyUp {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => session.set_rewinding(false),
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::F9 | Keycode::F10)),
+                    ..
+                } => {
+                    let capture = match keycode {
+                        Keycode::F9 => Capture::Screen,
+                        _ => Capture::VRAM,
+                    };
+                    if let Err(e) = session.capture(&system, capture) {
+                        error!("Failed to save {:?} capture: {}", capture, e);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => debugger.get_or_insert_with(Debugger::new).pause(),
+                Event::ControllerDeviceAdded { .. } => input.open_controller(),
+                _ => (),
+            }
+        }
+
+        let motors = system.pad_mut(0).map_or((0, 0), |pad| pad.motors());
+        pad = input.update(&event_pump.keyboard_state(), motors);
+
+        if let Err(e) = system.flush_memcards(false) {
+            error!("Failed to save memory cards: {}", e);
+        }
+    }
+}
+
+/// The console as the command line describes it: BIOS or HLE kernel, disc, memory
+/// cards, TTY output and tracing.
+fn setup<R: Renderer>(args: &[String], renderer: R) -> System<R> {
+    // Without a BIOS, or with --hle, the kernel is emulated
+    let mut hle = args.iter().any(|a| a == "--hle");
+    let bios = match hle {
+        true => BIOS::empty(),
+        false => BIOS::new(Path::new("./bios/bios")).unwrap_or_else(|e| {
+            warn!("No usable BIOS ({}), using the HLE kernel", e);
+            hle = true;
+            BIOS::empty()
+        }),
+    };
+    let mut system = System::new(bios, renderer);
+
+    if let Some(path) = option(args, "--disc") {
+        match Disc::open(Path::new(path)) {
+            Ok(disc) => system.insert_disc(disc),
+            Err(e) => {
+                error!("Can't open {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if hle {
+        // --exe FILE boots an EXE directly, otherwise the disc's
+        let boot = match option(args, "--exe") {
+            Some(path) => Boot::Exe(path.into()),
+            None => Boot::Disc,
+        };
+        if let Err(e) = system.boot_hle(boot) {
+            error!("Can't boot: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Movies don't get memory cards, whatever is on them would make playback
+    // go differently
+    let movie = args.iter().any(|a| a == "--record" || a == "--play");
+    for (index, path) in MEMCARD_PATHS.iter().enumerate().filter(|_| !movie) {
+        match MemoryCard::open(Path::new(path)) {
+            Ok(memcard) => system.set_memcard(index, Some(memcard)),
+            Err(e) => error!("Failed to open memory card {}: {}", path, e),
+        }
+    }
+
+    // Cheats come from --cheats FILE, or the game's file in the cheats directory.
+    // Not for movies either, they change RAM behind the inputs' back
+    let cheats = match option(args, "--cheats") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(Path::new(CHEAT_DIR).join(format!("{}.cht", game_name(args))))
+            .filter(|path| path.exists()),
+    };
+    if let Some(path) = cheats.filter(|_| !movie) {
+        match Cheats::load(&path) {
+            Ok(cheats) => {
+                info!("{} cheats from {}", cheats.cheats().len(), path.display());
+                system.set_cheats(cheats);
+            }
+            Err(e) => error!("Failed to load cheats: {}", e),
+        }
+    }
+
+    // --keep-going logs hardware the emulator doesn't implement instead of stopping
+    if args.iter().any(|a| a == "--keep-going") {
+        system.set_error_policy(ErrorPolicy::Continue);
+    }
+
+    // Homebrew debug output
+    if args.iter().any(|a| a == "--tty") {
+        system.set_tty_output(Some(Box::new(std::io::stdout())));
+    }
+
+    if let Some(path) = option(args, "--trace") {
+        let file = File::create(path).unwrap_or_else(|e| {
+            error!("Can't trace to {}: {}", path, e);
+            std::process::exit(1);
+        });
+        let mut tracer = Tracer::new(Box::new(BufWriter::new(file)));
+        if let Some(trigger) = option(args, "--trace-start").and_then(Trigger::parse) {
+            tracer.start_at(trigger);
+        }
+        if let Some(trigger) = option(args, "--trace-stop").and_then(Trigger::parse) {
+            tracer.stop_at(trigger);
+        }
+        if let Some(size) = option(args, "--trace-ring").and_then(|n| n.parse().ok()) {
+            tracer.ring_buffer(size);
+        }
+        system.set_tracer(Some(tracer));
+    }
+
+    system
+}
+
+fn headless(args: &[String]) -> ! {
+    let mut system = setup(args, NullRenderer);
+    let frames = option(args, "--frames").and_then(|n| n.parse::<u64>().ok());
+    let mut session = session(args, &mut system);
+
+    if let Some(path) = option(args, "--script") {
+        let mut script = Script::load(Path::new(path), system).unwrap_or_else(|e| {
+            error!("Can't run {}: {}", path, e);
+            std::process::exit(1)
+        });
+
+        let mut frame = 0;
+        while frames.is_none_or(|n| frame < n) {
+            match script.run_frame() {
+                Ok(true) => {
+                    frame += 1;
+                    session.capture_frame(&script.system());
+                }
+                Ok(false) => break,
+                Err(e) => {
+                    error!("Stopped after {} frames: {}", frame, e);
+                    quit(&mut script.system(), 1);
+                }
+            }
+        }
+        let mut system = script.system();
+        quit(&mut system, 0)
+    }
+
+    let mut frame = 0;
+    while frames.is_none_or(|n| frame < n) {
+        match session.run_frame(&mut system, &PadState::new()) {
+            Ok(true) => frame += 1,
+            Ok(false) => break,
+            Err(e) => {
+                error!("Stopped after {} frames: {}", frame, e);
+                quit(&mut system, 1);
+            }
+        }
+    }
+    quit(&mut system, 0)
+}
+
+/// The frontend side of the command line: movies, rewinding, screenshots and
+/// video recording.
+fn session<R: Renderer>(args: &[String], system: &mut System<R>) -> Session {
+    let mut session = Session::new(&game_name(args));
+
+    // --record FILE records the inputs to a movie, which rules out rewinding and
+    // loading states, --play FILE plays one back
+    let movie = match (option(args, "--record"), option(args, "--play")) {
+        (Some(path), _) => session.record_movie(Path::new(path), system),
+        (None, Some(path)) => session.play_movie(Path::new(path), system),
+        (None, None) => Ok(()),
+    };
+    if let Err(e) = movie {
+        error!("Can't start the movie: {}", e);
+        quit(system, 1);
+    }
+
+    // --rewind-interval N snapshots every N frames instead of every frame,
+    // --rewind-buffer 0 turns rewinding off
+    let interval = option(args, "--rewind-interval").and_then(|n| n.parse().ok());
+    let buffer = option(args, "--rewind-buffer").and_then(|n| n.parse().ok());
+    match buffer.unwrap_or(REWIND_BUFFER) {
+        0 => (),
+        mib => session.enable_rewind(interval.unwrap_or(1), mib << 20),
+    }
+
+    // --screenshot N takes a screenshot at frame N, --vram-dump N dumps VRAM
+    for (name, capture) in [
+        ("--screenshot", Capture::Screen),
+        ("--vram-dump", Capture::VRAM),
+    ] {
+        if let Some(frame) = option(args, name).and_then(|n| n.parse().ok()) {
+            session.schedule_capture(frame, capture);
+        }
+    }
+
+    // --av FILE records video to FILE.y4m and audio to FILE.wav. There's no SPU
+    // emulation yet, so the audio is silent
+    if let Some(path) = option(args, "--av") {
+        if let Err(e) = session.record_av(Path::new(path)) {
+            error!("Can't record video: {}", e);
+        }
+    }
+
+    session
+}
+
+/// What's running, after the file it was started from: the EXE, the disc, or the
+/// BIOS.
+fn game_name(args: &[String]) -> String {
+    option(args, "--exe")
+        .or(option(args, "--disc"))
+        .and_then(|path| Path::new(path).file_stem()?.to_str())
+        .unwrap_or("bios")
+        .to_string()
+}
+
+/// Exits if SDL can't start.
+fn sdl<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        error!("Can't start SDL: {}", e);
+        std::process::exit(1)
+    })
+}
+
+/// The value following `name` on the command line.
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let index = args.iter().position(|a| a == name)?;
+    args.get(index + 1).map(|s| s.as_str())
+}
+
+/// Saves whatever needs saving before exiting.
+fn quit<R: Renderer>(system: &mut System<R>, code: i32) -> ! {
+    if let Err(e) = system.flush_memcards(true) {
+        error!("Failed to save memory cards: {}", e);
+    }
+    if let Some(mut tracer) = system.set_tracer(None) {
+        if let Err(e) = tracer.flush() {
+            error!("Failed to write trace: {}", e);
+        }
     }
+    std::process::exit(code)
 }
@@ -0,0 +1,584 @@
+/**
+ * MDEC, the macroblock decoder used for FMV playback. Takes run-length encoded
+ * DCT blocks in and hands decoded 16x16 (color) or 8x8 (monochrome) pixel
+ * blocks back.
+ */
+use crate::error::Error;
+use crate::savestate::{invalid, StateReader, StateWriter};
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputDepth {
+    D4 = 0,
+    D8 = 1,
+    D24 = 2,
+    D15 = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+    None,
+    Decode,
+    SetQuantTables { color: bool },
+    SetScaleTable,
+}
+
+// Position of each coefficient of the zig-zag ordered RLE stream in the 8x8 block.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 5, 6, 14, 15, 27, 28, //
+    2, 4, 7, 13, 16, 26, 29, 42, //
+    3, 8, 12, 17, 25, 30, 41, 43, //
+    9, 11, 18, 24, 31, 40, 44, 53, //
+    10, 19, 23, 32, 39, 45, 52, 54, //
+    20, 22, 33, 38, 46, 51, 55, 60, //
+    21, 34, 37, 47, 50, 56, 59, 61, //
+    35, 36, 48, 49, 57, 58, 62, 63, //
+];
+
+// Padding between blocks, and the end-of-block marker
+const END_OF_BLOCK: u16 = 0xfe00;
+
+type Block = [i16; 64];
+
+pub struct MDEC {
+    command: Command,
+    // Parameter words still expected for the current command
+    remaining: u32,
+    input: Vec<u32>,
+    output: VecDeque<u32>,
+
+    depth: OutputDepth,
+    signed: bool,
+    set_bit15: bool,
+    current_block: u8,
+
+    dma_in_enable: bool,
+    dma_out_enable: bool,
+
+    zagzig: [usize; 64],
+    quant_luma: [u8; 64],
+    quant_chroma: [u8; 64],
+    scale: [i16; 64],
+}
+
+impl MDEC {
+    pub fn new() -> MDEC {
+        let mut zagzig = [0; 64];
+        for (i, &z) in ZIGZAG.iter().enumerate() {
+            zagzig[z] = i;
+        }
+
+        MDEC {
+            command: Command::None,
+            remaining: 0,
+            input: Vec::new(),
+            output: VecDeque::new(),
+
+            depth: OutputDepth::D4,
+            signed: false,
+            set_bit15: false,
+            current_block: 4,
+
+            dma_in_enable: false,
+            dma_out_enable: false,
+
+            zagzig,
+            quant_luma: [0; 64],
+            quant_chroma: [0; 64],
+            scale: [0; 64],
+        }
+    }
+
+    pub fn status(&self) -> u32 {
+        let busy = self.command != Command::None;
+
+        (self.remaining.wrapping_sub(1) & 0xffff)
+            | (self.current_block as u32) << 16
+            | (self.set_bit15 as u32) << 23
+            | (self.signed as u32) << 24
+            | (self.depth as u32) << 25
+            | ((self.dma_out_enable && !self.output.is_empty()) as u32) << 27
+            | ((self.dma_in_enable && busy) as u32) << 28
+            | (busy as u32) << 29
+            | (self.output.is_empty() as u32) << 31
+    }
+
+    pub fn set_control(&mut self, value: u32) {
+        if value & (1 << 31) != 0 {
+            self.command = Command::None;
+            self.remaining = 0;
+            self.input.clear();
+            self.output.clear();
+            self.current_block = 4;
+        }
+
+        self.dma_in_enable = value & (1 << 30) != 0;
+        self.dma_out_enable = value & (1 << 29) != 0;
+    }
+
+    /// Number of decoded words waiting to be read.
+    pub fn output_len(&self) -> usize {
+        self.output.len()
+    }
+
+    pub fn read(&mut self) -> u32 {
+        self.output.pop_front().unwrap_or(0)
+    }
+
+    /// Command or parameter word written to 0x1f801820, from the CPU or DMA0.
+    pub fn write(&mut self, word: u32) {
+        if self.command == Command::None {
+            self.start_command(word);
+            return;
+        }
+
+        self.input.push(word);
+        self.remaining -= 1;
+
+        if self.remaining == 0 {
+            match self.command {
+                Command::Decode => self.decode(),
+                Command::SetQuantTables { color } => self.set_quant_tables(color),
+                Command::SetScaleTable => self.set_scale_table(),
+                Command::None => unreachable!(),
+            }
+            self.command = Command::None;
+            self.input.clear();
+        }
+    }
+
+    fn start_command(&mut self, word: u32) {
+        self.depth = match (word >> 27) & 3 {
+            0 => OutputDepth::D4,
+            1 => OutputDepth::D8,
+            2 => OutputDepth::D24,
+            3 => OutputDepth::D15,
+            _ => unreachable!(),
+        };
+        self.signed = word & (1 << 26) != 0;
+        self.set_bit15 = word & (1 << 25) != 0;
+
+        let (command, remaining) = match word >> 29 {
+            1 => (Command::Decode, word & 0xffff),
+            2 => {
+                let color = word & 1 != 0;
+                (
+                    Command::SetQuantTables { color },
+                    if color { 32 } else { 16 },
+                )
+            }
+            3 => (Command::SetScaleTable, 32),
+            // No function, but the parameter count shows up in the status
+            // register without the usual minus one
+            _ => {
+                debug!("MDEC: no-op command 0x{:08x}", word);
+                self.remaining = (word + 1) & 0xffff;
+                return;
+            }
+        };
+
+        if remaining > 0 {
+            self.command = command;
+            self.remaining = remaining;
+        }
+    }
+
+    fn set_quant_tables(&mut self, color: bool) {
+        let bytes: Vec<u8> = self.input.iter().flat_map(|w| w.to_le_bytes()).collect();
+
+        self.quant_luma.copy_from_slice(&bytes[..64]);
+        if color {
+            self.quant_chroma.copy_from_slice(&bytes[64..128]);
+        }
+    }
+
+    fn set_scale_table(&mut self) {
+        for (i, word) in self.input.iter().enumerate() {
+            self.scale[i * 2] = *word as i16;
+            self.scale[i * 2 + 1] = (*word >> 16) as i16;
+        }
+    }
+
+    fn decode(&mut self) {
+        let halfwords: Vec<u16> = self
+            .input
+            .iter()
+            .flat_map(|&w| [w as u16, (w >> 16) as u16])
+            .collect();
+        let mut stream = halfwords.iter().copied().peekable();
+
+        loop {
+            // Skip the padding between macroblocks
+            while stream.peek() == Some(&END_OF_BLOCK) {
+                stream.next();
+            }
+            if stream.peek().is_none() {
+                break;
+            }
+
+            match self.depth {
+                OutputDepth::D4 | OutputDepth::D8 => {
+                    let mut y = [0; 64];
+                    self.current_block = 0;
+                    if !self.decode_block(&mut stream, &mut y, false) {
+                        break;
+                    }
+                    self.output_mono(&y);
+                }
+                OutputDepth::D15 | OutputDepth::D24 => {
+                    if !self.decode_color_macroblock(&mut stream) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.current_block = 4;
+    }
+
+    fn decode_color_macroblock<I>(&mut self, stream: &mut I) -> bool
+    where
+        I: Iterator<Item = u16>,
+    {
+        let mut cr = [0; 64];
+        let mut cb = [0; 64];
+        let mut pixels = [[0u8; 3]; 16 * 16];
+
+        self.current_block = 4;
+        if !self.decode_block(stream, &mut cr, true) {
+            return false;
+        }
+        self.current_block = 5;
+        if !self.decode_block(stream, &mut cb, true) {
+            return false;
+        }
+
+        for (index, (xx, yy)) in [(0, 0), (8, 0), (0, 8), (8, 8)].into_iter().enumerate() {
+            let mut y = [0; 64];
+            self.current_block = index as u8;
+            if !self.decode_block(stream, &mut y, false) {
+                return false;
+            }
+            self.yuv_to_rgb(&y, &cr, &cb, xx, yy, &mut pixels);
+        }
+
+        self.output_color(&pixels);
+        true
+    }
+
+    /// Run-length decodes, dequantizes and IDCTs one 8x8 block. Returns false if
+    /// the stream ended in the middle of it.
+    fn decode_block<I>(&self, stream: &mut I, block: &mut Block, chroma: bool) -> bool
+    where
+        I: Iterator<Item = u16>,
+    {
+        let quant = match chroma {
+            true => &self.quant_chroma,
+            false => &self.quant_luma,
+        };
+
+        let mut coefficients = [0; 64];
+
+        let mut n = match stream.find(|&n| n != END_OF_BLOCK) {
+            Some(n) => n,
+            None => return false,
+        };
+
+        let q_scale = (n >> 10) as i32 & 0x3f;
+        let mut k = 0;
+        let mut value = signed10(n) * quant[0] as i32;
+
+        loop {
+            if q_scale == 0 {
+                value = signed10(n) * 2;
+            }
+            let value_clamped = value.clamp(-0x400, 0x3ff) as i16;
+
+            match q_scale {
+                0 => coefficients[k] = value_clamped,
+                _ => coefficients[self.zagzig[k]] = value_clamped,
+            }
+
+            n = match stream.next() {
+                Some(n) => n,
+                None => return false,
+            };
+
+            k += ((n >> 10) & 0x3f) as usize + 1;
+            if k > 63 {
+                break;
+            }
+            value = (signed10(n) * quant[k] as i32 * q_scale + 4) / 8;
+        }
+
+        self.idct(&coefficients, block);
+        true
+    }
+
+    /// Two 1D passes, each one transposing the block.
+    fn idct(&self, input: &Block, output: &mut Block) {
+        let mut temp = [0; 64];
+        self.idct_pass(input, &mut temp);
+        self.idct_pass(&temp, output);
+    }
+
+    fn idct_pass(&self, src: &Block, dst: &mut Block) {
+        for x in 0..8 {
+            for y in 0..8 {
+                let sum: i64 = (0..8)
+                    .map(|z| src[y + z * 8] as i64 * self.scale[x + z * 8] as i64)
+                    .sum();
+                dst[x + y * 8] = ((sum + 0x8000) >> 16) as i16;
+            }
+        }
+    }
+
+    fn yuv_to_rgb(
+        &self,
+        y: &Block,
+        cr: &Block,
+        cb: &Block,
+        xx: usize,
+        yy: usize,
+        pixels: &mut [[u8; 3]; 256],
+    ) {
+        for py in 0..8 {
+            for px in 0..8 {
+                let chroma = (px + xx) / 2 + ((py + yy) / 2) * 8;
+                let r = cr[chroma] as f32;
+                let b = cb[chroma] as f32;
+
+                let g = (-0.3437 * b) + (-0.7143 * r);
+                let r = 1.402 * r;
+                let b = 1.772 * b;
+
+                let luma = y[px + py * 8] as f32;
+                let channel = |c: f32| self.to_output((luma + c).round() as i32);
+
+                pixels[(px + xx) + (py + yy) * 16] = [channel(r), channel(g), channel(b)];
+            }
+        }
+    }
+
+    /// Clamps to the signed 8-bit range, then applies the signed/unsigned setting.
+    fn to_output(&self, value: i32) -> u8 {
+        let value = value.clamp(-128, 127) as i8 as u8;
+        match self.signed {
+            true => value,
+            false => value ^ 0x80,
+        }
+    }
+
+    fn output_color(&mut self, pixels: &[[u8; 3]; 256]) {
+        match self.depth {
+            OutputDepth::D24 => {
+                let bytes: Vec<u8> = pixels.iter().flatten().copied().collect();
+                self.push_bytes(&bytes);
+            }
+            OutputDepth::D15 => {
+                let bit15 = (self.set_bit15 as u16) << 15;
+                let bytes: Vec<u8> = pixels
+                    .iter()
+                    .map(|[r, g, b]| {
+                        (*r as u16 >> 3) | (*g as u16 >> 3) << 5 | (*b as u16 >> 3) << 10 | bit15
+                    })
+                    .flat_map(|p| p.to_le_bytes())
+                    .collect();
+                self.push_bytes(&bytes);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn output_mono(&mut self, y: &Block) {
+        let pixels: Vec<u8> = y.iter().map(|&v| self.to_output(v as i32)).collect();
+
+        match self.depth {
+            OutputDepth::D8 => self.push_bytes(&pixels),
+            OutputDepth::D4 => {
+                let bytes: Vec<u8> = pixels
+                    .chunks(2)
+                    .map(|p| (p[0] >> 4) | (p[1] & 0xf0))
+                    .collect();
+                self.push_bytes(&bytes);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for word in bytes.chunks(4) {
+            let mut padded = [0; 4];
+            padded[..word.len()].copy_from_slice(word);
+            self.output.push_back(u32::from_le_bytes(padded));
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        match self.command {
+            Command::None => w.u8(0),
+            Command::Decode => w.u8(1),
+            Command::SetQuantTables { color } => w.u8(2 + color as u8),
+            Command::SetScaleTable => w.u8(4),
+        }
+        w.u32(self.remaining);
+        w.u32(self.input.len() as u32);
+        w.u32s(&self.input);
+        w.u32(self.output.len() as u32);
+        for &word in &self.output {
+            w.u32(word);
+        }
+
+        w.u8(self.depth as u8);
+        w.bool(self.signed);
+        w.bool(self.set_bit15);
+        w.u8(self.current_block);
+
+        w.bool(self.dma_in_enable);
+        w.bool(self.dma_out_enable);
+
+        w.bytes(&self.quant_luma);
+        w.bytes(&self.quant_chroma);
+        for &coefficient in &self.scale {
+            w.u16(coefficient as u16);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.command = match r.u8()? {
+            0 => Command::None,
+            1 => Command::Decode,
+            2 => Command::SetQuantTables { color: false },
+            3 => Command::SetQuantTables { color: true },
+            4 => Command::SetScaleTable,
+            _ => return Err(invalid("MDEC command")),
+        };
+        self.remaining = r.u32()?;
+        self.input = vec![0; r.u32()? as usize];
+        r.u32s(&mut self.input)?;
+        let output_len = r.u32()?;
+        self.output.clear();
+        for _ in 0..output_len {
+            self.output.push_back(r.u32()?);
+        }
+
+        self.depth = match r.u8()? {
+            0 => OutputDepth::D4,
+            1 => OutputDepth::D8,
+            2 => OutputDepth::D24,
+            3 => OutputDepth::D15,
+            _ => return Err(invalid("MDEC output depth")),
+        };
+        self.signed = r.bool()?;
+        self.set_bit15 = r.bool()?;
+        self.current_block = r.u8()?;
+
+        self.dma_in_enable = r.bool()?;
+        self.dma_out_enable = r.bool()?;
+
+        r.bytes_into(&mut self.quant_luma)?;
+        r.bytes_into(&mut self.quant_chroma)?;
+        for coefficient in &mut self.scale {
+            *coefficient = r.u16()? as i16;
+        }
+        Ok(())
+    }
+}
+
+impl Default for MDEC {
+    fn default() -> MDEC {
+        MDEC::new()
+    }
+}
+
+/// Sign-extends the 10-bit coefficient of an RLE halfword.
+fn signed10(n: u16) -> i32 {
+    (((n & 0x3ff) << 6) as i16 >> 6) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First row of the scale table the BIOS uploads, the only one DC-only
+    /// blocks go through.
+    const SCALE: u32 = 0x5a82_5a82;
+
+    fn setup() -> MDEC {
+        let mut mdec = MDEC::new();
+        mdec.write(2 << 29 | 1);
+        for _ in 0..32 {
+            mdec.write(0x0202_0202);
+        }
+        mdec.write(3 << 29);
+        for _ in 0..32 {
+            mdec.write(SCALE);
+        }
+        mdec
+    }
+
+    /// One macroblock of DC-only blocks: Cr 100, Cb -60, and 200 for the four Y
+    /// blocks, which come out as Y 50, Cr 25 and Cb -15.
+    fn decode(mdec: &mut MDEC, depth: u32) {
+        let dc = |value: i16| 1 << 10 | (value as u16 & 0x3ff);
+        let mut halfwords = vec![dc(100), END_OF_BLOCK, dc(-60), END_OF_BLOCK];
+        for _ in 0..4 {
+            halfwords.extend([dc(200), END_OF_BLOCK]);
+        }
+
+        mdec.write(1 << 29 | depth << 27 | (halfwords.len() / 2) as u32);
+        for pair in halfwords.chunks(2) {
+            mdec.write(pair[0] as u32 | (pair[1] as u32) << 16);
+        }
+    }
+
+    fn output(mdec: &mut MDEC) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while mdec.output_len() > 0 {
+            bytes.extend(mdec.read().to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn decode_24bit() {
+        let mut mdec = setup();
+        decode(&mut mdec, OutputDepth::D24 as u32);
+
+        let bytes = output(&mut mdec);
+        assert_eq!(bytes.len(), 16 * 16 * 3);
+        for pixel in bytes.chunks(3) {
+            assert_eq!(pixel, [213, 165, 151]);
+        }
+    }
+
+    #[test]
+    fn decode_15bit() {
+        let mut mdec = setup();
+        decode(&mut mdec, OutputDepth::D15 as u32);
+
+        let bytes = output(&mut mdec);
+        assert_eq!(bytes.len(), 16 * 16 * 2);
+        for pixel in bytes.chunks(2) {
+            assert_eq!(
+                u16::from_le_bytes([pixel[0], pixel[1]]),
+                26 | 20 << 5 | 18 << 10
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_command() {
+        let mut mdec = MDEC::new();
+        mdec.write(5 << 29 | 1 << 27 | 1 << 26 | 0x1234);
+
+        let status = mdec.status();
+        assert_eq!(status & 0xffff, 0x1234);
+        assert_eq!(status >> 24 & 7, 0b011);
+        assert_eq!(status & 1 << 29, 0);
+
+        // Still takes commands
+        mdec.write(3 << 29);
+        assert_ne!(mdec.status() & 1 << 29, 0);
+    }
+}
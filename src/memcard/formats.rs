@@ -0,0 +1,206 @@
+// Whole-card image formats, which all wrap the same 128 KiB raw image with a
+// tool-specific header, and single save formats. The headers follow the layouts
+// MemcardRex reads and writes, which other emulators and tools accept.
+use super::{Card, BLOCK_SIZE, CARD_SIZE, MAX_FILENAME};
+use crate::error::{Error, MemoryCardError};
+use std::fs;
+use std::path::Path;
+
+// DexDrive: magic, then at 0x16 and 0x26 copies of the allocation state and next
+// block bytes of the 15 directory frames, then a 256 byte comment per save.
+const GME_MAGIC: &[u8] = b"123-456-STD";
+const GME_HEADER_SIZE: usize = 0x40 + 15 * 256;
+
+const VMP_MAGIC: &[u8] = b"\0PMV";
+const VMP_HEADER_SIZE: usize = 0x80;
+
+// PS3 single save: magic, a signature the PS3 checks and emulators don't, then
+// the save size, data offset and filename.
+const PSV_MAGIC: &[u8] = b"\0VSP";
+const PSV_HEADER_SIZE: usize = 0x84;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Plain dump (.mcr, .mcd, .mc, .srm)
+    Raw,
+    /// InterAct DexDrive (.gme)
+    Gme,
+    /// PSP/Vita virtual memory card (.vmp)
+    Vmp,
+}
+
+impl Format {
+    pub fn from_extension(path: &Path) -> Result<Format, Error> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("mcr" | "mcd" | "mc" | "srm" | "bin") => Ok(Format::Raw),
+            Some("gme") => Ok(Format::Gme),
+            Some("vmp") => Ok(Format::Vmp),
+            _ => Err(Error::MemoryCard(MemoryCardError::UnknownFormat(
+                path.to_path_buf(),
+            ))),
+        }
+    }
+
+    pub fn detect(data: &[u8]) -> Format {
+        if data.starts_with(GME_MAGIC) {
+            Format::Gme
+        } else if data.starts_with(VMP_MAGIC) {
+            Format::Vmp
+        } else {
+            Format::Raw
+        }
+    }
+
+    fn header_size(self) -> usize {
+        match self {
+            Format::Raw => 0,
+            Format::Gme => GME_HEADER_SIZE,
+            Format::Vmp => VMP_HEADER_SIZE,
+        }
+    }
+}
+
+impl Card {
+    /// Loads a card image in any of the supported formats.
+    pub fn load(path: &Path) -> Result<Card, Error> {
+        Card::open(path).map(|(card, _)| card)
+    }
+
+    /// Loads a card image, along with the format it was in.
+    pub fn open(path: &Path) -> Result<(Card, Format), Error> {
+        let data = fs::read(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Card::from_image(&data)
+    }
+
+    pub fn from_image(data: &[u8]) -> Result<(Card, Format), Error> {
+        let format = Format::detect(data);
+        let start = format.header_size();
+        if data.len() < start + CARD_SIZE {
+            return Err(Error::MemoryCard(MemoryCardError::TruncatedCard {
+                format,
+                len: data.len(),
+            }));
+        }
+        let card = Card::from_raw(data[start..start + CARD_SIZE].to_vec())?;
+        Ok((card, format))
+    }
+
+    pub fn image(&self, format: Format) -> Vec<u8> {
+        let mut data = match format {
+            Format::Raw => Vec::new(),
+            Format::Gme => self.gme_header(),
+            Format::Vmp => vmp_header(),
+        };
+        data.extend_from_slice(&self.data);
+        data
+    }
+
+    pub fn save(&self, path: &Path, format: Format) -> Result<(), Error> {
+        fs::write(path, self.image(format)).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn gme_header(&self) -> Vec<u8> {
+        let mut header = vec![0; GME_HEADER_SIZE];
+        header[..GME_MAGIC.len()].copy_from_slice(GME_MAGIC);
+        header[0x12] = 0x01;
+        header[0x14] = 0x01;
+        header[0x15] = b'M';
+
+        // The comments are left empty
+        for block in 0..super::BLOCK_COUNT {
+            let entry = self.entry(block);
+            header[0x16 + block] = entry[0];
+            header[0x26 + block] = entry[8];
+        }
+        header
+    }
+}
+
+/// The PSP firmware expects a keyed signature at 0x20 that we can't produce, so
+/// exported images only work with emulators that ignore it.
+fn vmp_header() -> Vec<u8> {
+    let mut header = vec![0; VMP_HEADER_SIZE];
+    header[..VMP_MAGIC.len()].copy_from_slice(VMP_MAGIC);
+    header[4..8].copy_from_slice(&(VMP_HEADER_SIZE as u32).to_le_bytes());
+    header
+}
+
+/// Single save file formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveFormat {
+    /// The save's directory frame followed by its blocks (.mcs, .mcb, .mcx)
+    Mcs,
+    /// PS3 virtual memory card save (.psv)
+    Psv,
+}
+
+impl SaveFormat {
+    pub fn from_extension(path: &Path) -> Result<SaveFormat, Error> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("mcs" | "mcb" | "mcx") => Ok(SaveFormat::Mcs),
+            Some("psv") => Ok(SaveFormat::Psv),
+            _ => Err(Error::MemoryCard(MemoryCardError::UnknownSaveFormat(
+                path.to_path_buf(),
+            ))),
+        }
+    }
+
+    pub fn detect(data: &[u8]) -> SaveFormat {
+        if data.starts_with(PSV_MAGIC) {
+            SaveFormat::Psv
+        } else {
+            SaveFormat::Mcs
+        }
+    }
+}
+
+/// Wraps a save's blocks in a PSV header. The signature is left blank, so like
+/// VMP images these only work with emulators and tools that ignore it.
+pub(super) fn psv_wrap(filename: &str, blocks: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; PSV_HEADER_SIZE];
+    data[..PSV_MAGIC.len()].copy_from_slice(PSV_MAGIC);
+    data[0x38..0x3c].copy_from_slice(&0x14u32.to_le_bytes());
+    // PS1 save
+    data[0x3c..0x40].copy_from_slice(&1u32.to_le_bytes());
+    data[0x40..0x44].copy_from_slice(&(blocks.len() as u32).to_le_bytes());
+    data[0x44..0x48].copy_from_slice(&(PSV_HEADER_SIZE as u32).to_le_bytes());
+    data[0x48..0x4c].copy_from_slice(&0x200u32.to_le_bytes());
+    data[0x5c..0x60].copy_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+    data[0x60..0x64].copy_from_slice(&0x9003u32.to_le_bytes());
+    let name = &filename.as_bytes()[..filename.len().min(MAX_FILENAME)];
+    data[0x64..0x64 + name.len()].copy_from_slice(name);
+    data.extend_from_slice(blocks);
+    data
+}
+
+/// The filename and blocks of a PSV save.
+pub(super) fn psv_unwrap(save: &[u8]) -> Result<(String, &[u8]), Error> {
+    let word = |offset: usize| {
+        save.get(offset..offset + 4)
+            .map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap())) as usize
+    };
+    let (size, start) = (word(0x40), word(0x44));
+    if save.len() < PSV_HEADER_SIZE || start < PSV_HEADER_SIZE || save.len() < start + size {
+        return Err(Error::MemoryCard(MemoryCardError::TruncatedSave {
+            len: save.len(),
+        }));
+    }
+    let filename = super::c_string(&save[0x64..0x64 + MAX_FILENAME]);
+    Ok((filename, &save[start..start + size]))
+}
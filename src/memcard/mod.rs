@@ -0,0 +1,435 @@
+/**
+ * Memory card image library: parses the directory and lets us move saves
+ * between cards and files without going through a game.
+ */
+mod formats;
+mod sjis;
+
+use crate::error::{Error, MemoryCardError};
+use std::string::String;
+
+pub use formats::{Format, SaveFormat};
+
+pub const CARD_SIZE: usize = 128 * 1024;
+pub const FRAME_SIZE: usize = 128;
+pub const BLOCK_SIZE: usize = 8 * 1024;
+
+/// Block 0 holds the header and directory, leaving 15 blocks for saves.
+pub const BLOCK_COUNT: usize = 15;
+
+// Directory frame allocation states: the high nibble says whether the block is in
+// use, the low nibble where it sits in its save's chain. Freshly formatted blocks
+// are STATE_FREE with no link.
+const STATE_USED: u32 = 0x50;
+const STATE_FREE: u32 = 0xa0;
+const LINK_FIRST: u32 = 0x1;
+const LINK_MIDDLE: u32 = 0x2;
+const LINK_LAST: u32 = 0x3;
+
+const NO_NEXT: u16 = 0xffff;
+const MAX_FILENAME: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Japan,
+    America,
+    Europe,
+    Unknown,
+}
+
+/// A save as listed on the card, identified by its first block.
+#[derive(Debug, Clone)]
+pub struct SaveInfo {
+    pub first_block: usize,
+    pub blocks: Vec<usize>,
+    pub filename: String,
+    pub title: String,
+    pub region: Region,
+    pub size: u32,
+    pub deleted: bool,
+    pub icon: Icon,
+}
+
+/// The first frame of the save's 16x16 icon.
+#[derive(Debug, Clone)]
+pub struct Icon {
+    pub frames: u8,
+    pub palette: [u16; 16],
+    pub pixels: [u8; 16 * 16],
+}
+
+impl Icon {
+    /// 8-bit RGB color of the pixel at (`x`, `y`).
+    pub fn rgb(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let color = self.palette[self.pixels[y * 16 + x] as usize];
+        let channel = |shift: u16| (((color >> shift) & 0x1f) << 3) as u8;
+        (channel(0), channel(5), channel(10))
+    }
+}
+
+pub struct Card {
+    pub data: Vec<u8>,
+}
+
+impl Card {
+    /// A freshly formatted card: header, 15 free directory frames, an empty broken
+    /// sector list and the write test frame.
+    pub fn formatted() -> Card {
+        let mut card = Card {
+            data: vec![0; CARD_SIZE],
+        };
+
+        card.frame_mut(0)[..2].copy_from_slice(b"MC");
+        for block in 0..BLOCK_COUNT {
+            card.write_entry(block, STATE_FREE, 0, NO_NEXT, "");
+        }
+        for index in 16..36 {
+            let frame = card.frame_mut(index);
+            frame[0..4].copy_from_slice(&[0xff; 4]);
+            frame[8..10].copy_from_slice(&[0xff; 2]);
+        }
+        card.frame_mut(63)[..2].copy_from_slice(b"MC");
+
+        for index in (0..36).chain([63]) {
+            card.update_checksum(index);
+        }
+        card
+    }
+
+    pub fn from_raw(data: Vec<u8>) -> Result<Card, Error> {
+        if data.len() != CARD_SIZE {
+            return Err(Error::MemoryCard(MemoryCardError::InvalidCardSize {
+                len: data.len(),
+            }));
+        }
+        if &data[..2] != b"MC" {
+            return Err(Error::MemoryCard(MemoryCardError::MissingHeader));
+        }
+        Ok(Card { data })
+    }
+
+    fn frame(&self, index: usize) -> &[u8] {
+        &self.data[index * FRAME_SIZE..(index + 1) * FRAME_SIZE]
+    }
+
+    fn frame_mut(&mut self, index: usize) -> &mut [u8] {
+        &mut self.data[index * FRAME_SIZE..(index + 1) * FRAME_SIZE]
+    }
+
+    fn update_checksum(&mut self, index: usize) {
+        let frame = self.frame_mut(index);
+        frame[FRAME_SIZE - 1] = checksum(&frame[..FRAME_SIZE - 1]);
+    }
+
+    /// Directory frame describing `block` (0-based save block).
+    fn entry(&self, block: usize) -> &[u8] {
+        self.frame(block + 1)
+    }
+
+    fn state(&self, block: usize) -> u32 {
+        u32::from_le_bytes(self.entry(block)[0..4].try_into().unwrap())
+    }
+
+    fn next(&self, block: usize) -> u16 {
+        u16::from_le_bytes(self.entry(block)[8..10].try_into().unwrap())
+    }
+
+    fn write_entry(&mut self, block: usize, state: u32, size: u32, next: u16, name: &str) {
+        let frame = self.frame_mut(block + 1);
+        frame.fill(0);
+        frame[0..4].copy_from_slice(&state.to_le_bytes());
+        frame[4..8].copy_from_slice(&size.to_le_bytes());
+        frame[8..10].copy_from_slice(&next.to_le_bytes());
+        let name = &name.as_bytes()[..name.len().min(MAX_FILENAME)];
+        frame[0x0a..0x0a + name.len()].copy_from_slice(name);
+        self.update_checksum(block + 1);
+    }
+
+    fn set_state(&mut self, block: usize, state: u32) {
+        self.frame_mut(block + 1)[0..4].copy_from_slice(&state.to_le_bytes());
+        self.update_checksum(block + 1);
+    }
+
+    pub fn block(&self, block: usize) -> &[u8] {
+        &self.data[(block + 1) * BLOCK_SIZE..(block + 2) * BLOCK_SIZE]
+    }
+
+    fn block_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.data[(block + 1) * BLOCK_SIZE..(block + 2) * BLOCK_SIZE]
+    }
+
+    /// Follows the chain of blocks starting at `first`.
+    fn chain(&self, first: usize) -> Result<Vec<usize>, Error> {
+        let mut blocks = vec![first];
+        let mut block = first;
+
+        loop {
+            let next = self.next(block);
+            if next == NO_NEXT {
+                return Ok(blocks);
+            }
+
+            block = next as usize;
+            if block >= BLOCK_COUNT || blocks.contains(&block) {
+                return Err(Error::MemoryCard(MemoryCardError::BrokenChain {
+                    block: first,
+                }));
+            }
+            blocks.push(block);
+        }
+    }
+
+    pub fn saves(&self) -> Vec<SaveInfo> {
+        (0..BLOCK_COUNT)
+            .filter(|&b| self.state(b) & 0xf == LINK_FIRST)
+            .filter_map(|b| match self.save_info(b) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn save_info(&self, first: usize) -> Result<SaveInfo, Error> {
+        let state = self.state(first);
+        if state & 0xf != LINK_FIRST {
+            return Err(Error::MemoryCard(MemoryCardError::NotFirstBlock {
+                block: first,
+            }));
+        }
+
+        let entry = self.entry(first);
+        let filename = c_string(&entry[0x0a..0x1e]);
+        let size = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+
+        let title_frame = &self.block(first)[..FRAME_SIZE];
+        let title = sjis::decode(&title_frame[0x04..0x44]);
+
+        let mut palette = [0; 16];
+        for (i, color) in palette.iter_mut().enumerate() {
+            *color = u16::from_le_bytes([title_frame[0x60 + i * 2], title_frame[0x61 + i * 2]]);
+        }
+
+        let mut pixels = [0; 16 * 16];
+        let bitmap = &self.block(first)[FRAME_SIZE..2 * FRAME_SIZE];
+        for (i, byte) in bitmap.iter().enumerate() {
+            pixels[i * 2] = byte & 0xf;
+            pixels[i * 2 + 1] = byte >> 4;
+        }
+
+        let region = match filename.get(..2) {
+            Some("BI") => Region::Japan,
+            Some("BA") => Region::America,
+            Some("BE") => Region::Europe,
+            _ => Region::Unknown,
+        };
+
+        Ok(SaveInfo {
+            first_block: first,
+            blocks: self.chain(first)?,
+            filename,
+            title,
+            region,
+            size,
+            deleted: state & 0xf0 == STATE_FREE,
+            icon: Icon {
+                frames: title_frame[2] & 0x3,
+                palette,
+                pixels,
+            },
+        })
+    }
+
+    pub fn free_blocks(&self) -> Vec<usize> {
+        (0..BLOCK_COUNT)
+            .filter(|&b| self.state(b) & 0xf0 == STATE_FREE)
+            .collect()
+    }
+
+    /// Exports the save starting at `first` as a single save file.
+    pub fn export(&self, first: usize, format: SaveFormat) -> Result<Vec<u8>, Error> {
+        let info = self.save_info(first)?;
+        let mut blocks = Vec::with_capacity(info.blocks.len() * BLOCK_SIZE);
+        for &block in &info.blocks {
+            blocks.extend_from_slice(self.block(block));
+        }
+
+        match format {
+            SaveFormat::Mcs => {
+                let mut data = self.entry(first).to_vec();
+                data[0..4].copy_from_slice(&(STATE_USED | LINK_FIRST).to_le_bytes());
+                data[8..10].copy_from_slice(&NO_NEXT.to_le_bytes());
+                data[FRAME_SIZE - 1] = checksum(&data[..FRAME_SIZE - 1]);
+                data.extend_from_slice(&blocks);
+                Ok(data)
+            }
+            SaveFormat::Psv => Ok(formats::psv_wrap(&info.filename, &blocks)),
+        }
+    }
+
+    /// Imports a single save file, in either format, into free blocks. Returns the
+    /// first block.
+    pub fn import(&mut self, save: &[u8]) -> Result<usize, Error> {
+        let (filename, blocks) = match SaveFormat::detect(save) {
+            SaveFormat::Mcs if save.len() >= FRAME_SIZE => {
+                let (header, blocks) = save.split_at(FRAME_SIZE);
+                (c_string(&header[0x0a..0x1e]), blocks)
+            }
+            SaveFormat::Mcs => (String::new(), &[][..]),
+            SaveFormat::Psv => formats::psv_unwrap(save)?,
+        };
+        if blocks.is_empty() || !blocks.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(Error::MemoryCard(MemoryCardError::InvalidSaveSize {
+                len: save.len(),
+            }));
+        }
+
+        if self
+            .saves()
+            .iter()
+            .any(|s| !s.deleted && s.filename == filename)
+        {
+            return Err(Error::MemoryCard(MemoryCardError::NameTaken(filename)));
+        }
+
+        let count = blocks.len() / BLOCK_SIZE;
+        let free = self.free_blocks();
+        if free.len() < count {
+            return Err(Error::MemoryCard(MemoryCardError::NotEnoughBlocks {
+                needed: count,
+                free: free.len(),
+            }));
+        }
+
+        let targets = &free[..count];
+        for (i, &block) in targets.iter().enumerate() {
+            let state = match i {
+                0 => STATE_USED | LINK_FIRST,
+                _ if i == count - 1 => STATE_USED | LINK_LAST,
+                _ => STATE_USED | LINK_MIDDLE,
+            };
+            let next = targets.get(i + 1).map_or(NO_NEXT, |&b| b as u16);
+            let (size, name) = match i {
+                0 => ((count * BLOCK_SIZE) as u32, filename.as_str()),
+                _ => (0, ""),
+            };
+
+            self.write_entry(block, state, size, next, name);
+            self.block_mut(block)
+                .copy_from_slice(&blocks[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE]);
+        }
+        Ok(targets[0])
+    }
+
+    pub fn delete(&mut self, first: usize) -> Result<(), Error> {
+        let info = self.save_info(first)?;
+        if info.deleted {
+            return Err(Error::MemoryCard(MemoryCardError::AlreadyDeleted(
+                info.filename,
+            )));
+        }
+
+        for block in info.blocks {
+            let state = self.state(block);
+            self.set_state(block, STATE_FREE | (state & 0xf));
+        }
+        Ok(())
+    }
+
+    /// Restores a deleted save, as long as none of its blocks have been reused.
+    pub fn undelete(&mut self, first: usize) -> Result<(), Error> {
+        let info = self.save_info(first)?;
+        if !info.deleted {
+            return Err(Error::MemoryCard(MemoryCardError::NotDeleted(
+                info.filename,
+            )));
+        }
+
+        let reused = info
+            .blocks
+            .iter()
+            .any(|&b| self.state(b) & 0xf0 != STATE_FREE);
+        if reused {
+            return Err(Error::MemoryCard(MemoryCardError::Overwritten(
+                info.filename,
+            )));
+        }
+
+        for block in info.blocks {
+            let state = self.state(block);
+            self.set_state(block, STATE_USED | (state & 0xf));
+        }
+        Ok(())
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, b| acc ^ b)
+}
+
+fn c_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A two block .mcs save with recognisable contents.
+    fn mcs_save(filename: &str) -> Vec<u8> {
+        let mut save = vec![0; FRAME_SIZE];
+        save[0..4].copy_from_slice(&(STATE_USED | LINK_FIRST).to_le_bytes());
+        save[4..8].copy_from_slice(&(2 * BLOCK_SIZE as u32).to_le_bytes());
+        save[8..10].copy_from_slice(&NO_NEXT.to_le_bytes());
+        save[0x0a..0x0a + filename.len()].copy_from_slice(filename.as_bytes());
+        save[FRAME_SIZE - 1] = checksum(&save[..FRAME_SIZE - 1]);
+        save.extend((0..2 * BLOCK_SIZE).map(|i| (i * 7 + i / 256) as u8));
+        save
+    }
+
+    fn card_with_saves() -> Card {
+        let mut card = Card::formatted();
+        card.import(&mcs_save("BASLUS-00001GAME")).unwrap();
+        card.import(&mcs_save("BESLES-00002GAME")).unwrap();
+        card
+    }
+
+    #[test]
+    fn card_image_round_trip() {
+        let card = card_with_saves();
+        for format in [Format::Raw, Format::Gme, Format::Vmp] {
+            let image = card.image(format);
+            let (loaded, detected) = Card::from_image(&image).unwrap();
+            assert_eq!(detected, format);
+            assert!(loaded.data == card.data, "{:?}", format);
+            assert!(loaded.image(format) == image, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn mcs_round_trip() {
+        let save = mcs_save("BASLUS-00001GAME");
+        let mut card = Card::formatted();
+        let first = card.import(&save).unwrap();
+        assert!(card.export(first, SaveFormat::Mcs).unwrap() == save);
+    }
+
+    #[test]
+    fn psv_round_trip() {
+        let card = card_with_saves();
+        let psv = card.export(2, SaveFormat::Psv).unwrap();
+        assert_eq!(SaveFormat::detect(&psv), SaveFormat::Psv);
+
+        let mut other = Card::formatted();
+        let first = other.import(&psv).unwrap();
+        assert_eq!(other.save_info(first).unwrap().filename, "BESLES-00002GAME");
+        assert!(other.export(first, SaveFormat::Psv).unwrap() == psv);
+        assert!(
+            other.export(first, SaveFormat::Mcs).unwrap()
+                == card.export(2, SaveFormat::Mcs).unwrap()
+        );
+    }
+}
@@ -0,0 +1,68 @@
+// Save titles are stored as full-width Shift-JIS. We only need something readable
+// in a terminal, so map the full-width ASCII lookalikes back and replace the rest.
+
+const PUNCTUATION: [(u16, char); 30] = [
+    (0x8140, ' '),
+    (0x8143, ','),
+    (0x8144, '.'),
+    (0x8146, ':'),
+    (0x8147, ';'),
+    (0x8148, '?'),
+    (0x8149, '!'),
+    (0x8151, '_'),
+    (0x815b, '-'),
+    (0x815c, '-'),
+    (0x815d, '-'),
+    (0x815e, '/'),
+    (0x815f, '\\'),
+    (0x8160, '~'),
+    (0x8162, '|'),
+    (0x8166, '\''),
+    (0x8168, '"'),
+    (0x8169, '('),
+    (0x816a, ')'),
+    (0x816d, '['),
+    (0x816e, ']'),
+    (0x817b, '+'),
+    (0x817c, '-'),
+    (0x8181, '='),
+    (0x8183, '<'),
+    (0x8184, '>'),
+    (0x8190, '$'),
+    (0x8193, '%'),
+    (0x8194, '#'),
+    (0x8195, '&'),
+];
+
+fn full_width(code: u16) -> char {
+    match code {
+        0x824f..=0x8258 => (b'0' + (code - 0x824f) as u8) as char,
+        0x8260..=0x8279 => (b'A' + (code - 0x8260) as u8) as char,
+        0x8281..=0x829a => (b'a' + (code - 0x8281) as u8) as char,
+        0x8196 => '*',
+        0x8197 => '@',
+        _ => PUNCTUATION
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map_or('?', |(_, c)| *c),
+    }
+}
+
+pub fn decode(data: &[u8]) -> String {
+    let mut title = String::new();
+    let mut bytes = data.iter().copied();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            0x00 => break,
+            0x01..=0x7f => title.push(byte as char),
+            0x81..=0x9f | 0xe0..=0xef => {
+                let low = bytes.next().unwrap_or(0);
+                title.push(full_width((byte as u16) << 8 | low as u16));
+            }
+            _ => title.push('?'),
+        }
+    }
+
+    title.trim_end().to_string()
+}
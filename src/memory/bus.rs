@@ -1,48 +1,228 @@
 // It's bussin my g
 
 use crate::bios::BIOS;
+use crate::error::Error;
 use crate::gpu::GPU;
+use crate::mdec::MDEC;
+use crate::savestate::{StateReader, StateWriter};
+use crate::sio::SIO0;
 use crate::utils;
-use crate::utils::Error;
 
 use super::channel::{AddressMode, Direction, SyncMode};
 use super::dma::{Port, DMA};
+use super::irq::{Interrupt, InterruptController};
 use super::map;
 use super::map::MemoryRegion;
 use super::ram::RAM;
+use super::scratchpad::ScratchPad;
+use super::watch::{WatchHit, Watchpoint};
 
 use crate::renderer::Renderer;
-use std::string::String;
 
 pub struct Bus<R: Renderer> {
     bios: BIOS,
     gpu: GPU<R>,
     ram: RAM,
+    scratchpad: ScratchPad,
     dma: DMA,
+    mdec: MDEC,
+    sio0: SIO0,
+    irq: InterruptController,
+
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
 }
 
 impl<R: Renderer> Bus<R> {
     pub fn new(bios: BIOS, ram: RAM, gpu: GPU<R>) -> Self {
+        let scratchpad = ScratchPad::new();
         let dma = DMA::new();
+        let mdec = MDEC::new();
+        let sio0 = SIO0::new();
         Self {
             bios,
             ram,
+            scratchpad,
             gpu,
             dma,
+            mdec,
+            sio0,
+            irq: InterruptController::new(),
+
+            watchpoints: Vec::new(),
+            watch_hit: None,
+        }
+    }
+
+    /// Power cycle. The BIOS, the renderer and the devices plugged into SIO0 stay.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.ram = RAM::new();
+        self.scratchpad = ScratchPad::new();
+        self.dma = DMA::new();
+        self.mdec = MDEC::new();
+        self.sio0.reset();
+        self.irq = InterruptController::new();
+        self.gpu.gp1(0)
+    }
+
+    /// The devices and memory, not the BIOS or the debugging aids.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram.data);
+        w.bytes(&self.scratchpad.data);
+        self.dma.save_state(w);
+        self.gpu.save_state(w);
+        self.mdec.save_state(w);
+        self.sio0.save_state(w);
+        self.irq.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.bytes_into(&mut self.ram.data)?;
+        r.bytes_into(&mut self.scratchpad.data)?;
+        self.dma.load_state(r)?;
+        self.gpu.load_state(r)?;
+        self.mdec.load_state(r)?;
+        self.sio0.load_state(r)?;
+        self.irq.load_state(r)
+    }
+
+    /// Main RAM, 2 MiB.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram.data
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram.data
+    }
+
+    pub fn bios(&self) -> &BIOS {
+        &self.bios
+    }
+
+    pub fn gpu(&self) -> &GPU<R> {
+        &self.gpu
+    }
+
+    pub fn gpu_mut(&mut self) -> &mut GPU<R> {
+        &mut self.gpu
+    }
+
+    pub fn sio0_mut(&mut self) -> &mut SIO0 {
+        &mut self.sio0
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.irq.request(interrupt);
+    }
+
+    /// Whether the interrupt controller is asking for the CPU's attention.
+    pub fn interrupt_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    pub fn interrupt_controller_mut(&mut self) -> &mut InterruptController {
+        &mut self.irq
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the watchpoints starting at `addr`. Returns false if there were none.
+    pub fn remove_watchpoint(&mut self, addr: u32) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.addr != addr);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// The first watchpoint hit since the last call.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    fn check_watchpoints(&mut self, addr: u32, width: usize, value: Option<u32>) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+
+        let write = value.is_some();
+        self.watch_hit = self
+            .watchpoints
+            .iter()
+            .find(|w| w.matches(addr, width as u32, write))
+            .map(|&watchpoint| WatchHit {
+                watchpoint,
+                addr,
+                write,
+                value,
+            });
+    }
+
+    /// Reads a byte of memory without side effects, for debuggers. Device
+    /// registers aren't readable this way.
+    pub fn peek(&self, addr: u32) -> Option<u8> {
+        match map::find_region(addr)? {
+            (MemoryRegion::RAM, offset) => Some(self.ram.load(offset)),
+            (MemoryRegion::BIOS, offset) => Some(self.bios.load(offset)),
+            (MemoryRegion::ScratchPad, offset) => Some(self.scratchpad.load(offset)),
+            _ => None,
+        }
+    }
+
+    pub fn peek_word(&self, addr: u32) -> Option<u32> {
+        let mut word = 0;
+        for i in 0..4 {
+            word |= (self.peek(addr.wrapping_add(i))? as u32) << (i * 8);
+        }
+        Some(word)
+    }
+
+    /// Debugger write, which can also patch the BIOS. Returns false if `addr` isn't
+    /// memory.
+    pub fn poke(&mut self, addr: u32, value: u8) -> bool {
+        match map::find_region(addr) {
+            Some((MemoryRegion::RAM, offset)) => self.ram.store(offset, value),
+            Some((MemoryRegion::BIOS, offset)) => utils::store(&mut self.bios.data, offset, value),
+            Some((MemoryRegion::ScratchPad, offset)) => self.scratchpad.store(offset, value),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn load<T: TryFrom<u32>>(&mut self, addr: u32) -> Result<T, Error> {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, std::mem::size_of::<T>(), None);
         }
+        self.read(addr)
     }
 
-    pub fn load<T: TryFrom<u32>>(&self, addr: u32) -> Result<T, String> {
-        expect_align(addr, std::mem::size_of::<T>() as u32)?;
-        let (region, offset) = map::find_region(addr)?;
+    /// Instruction fetch, including I-cache refills. Unlike `load`, doesn't set
+    /// off read watchpoints.
+    pub fn fetch(&mut self, addr: u32) -> Result<u32, Error> {
+        self.read(addr)
+    }
+
+    fn read<T: TryFrom<u32>>(&mut self, addr: u32) -> Result<T, Error> {
+        let width = std::mem::size_of::<T>();
+        expect_align(addr, width)?;
+        let Some((region, offset)) = map::find_region(addr) else {
+            return Err(Error::Unmapped { addr, width });
+        };
 
         return match region {
             MemoryRegion::BIOS => Ok(self.bios.load(offset)),
             MemoryRegion::RAM => Ok(self.ram.load(offset)),
-            // FIXME: This is ugly, maybe find a nice way to convert the error from
-            //        T.try_into() into our own error type (String)?
+            MemoryRegion::ScratchPad => Ok(self.scratchpad.load(offset)),
             MemoryRegion::DMA => Ok(utils::to_t(self.dma_register(offset)?)),
-            MemoryRegion::IRQControl | MemoryRegion::Timers | MemoryRegion::SPU => {
+            MemoryRegion::IRQControl => match offset {
+                0 => Ok(utils::to_t(self.irq.status() as u32)),
+                _ => Ok(utils::to_t(self.irq.mask() as u32)),
+            },
+            MemoryRegion::Timers | MemoryRegion::SPU => {
                 trace!("Unhandled load at {:?} range.", region);
                 Ok(utils::to_t(0))
             }
@@ -51,30 +231,54 @@ impl<R: Renderer> Bus<R> {
                 Ok(utils::to_t(0xff))
             }
             MemoryRegion::GPU => Ok(self.gpu.load(offset)),
+            MemoryRegion::PadMemCard => self.sio0.load(offset),
+            MemoryRegion::MDEC => match offset {
+                0 => Ok(utils::to_t(self.mdec.read())),
+                _ => Ok(utils::to_t(self.mdec.status())),
+            },
 
-            _ => Error!(
-                "Unhandled load @ 0x{:08X} (MemoryRegion::{:?})",
-                addr,
-                region
-            ),
+            _ => Err(Error::UnhandledRegister {
+                region,
+                offset,
+                write: false,
+            }),
         };
     }
 
-    pub fn store<T: Into<u32>>(&mut self, addr: u32, value: T) -> Result<(), String> {
-        expect_align(addr, std::mem::size_of::<T>() as u32)?;
-        let (region, offset) = map::find_region(addr)?;
+    pub fn store<T: Into<u32> + Copy>(&mut self, addr: u32, value: T) -> Result<(), Error> {
+        let width = std::mem::size_of::<T>();
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, width, Some(value.into()));
+        }
+        expect_align(addr, width)?;
+        let Some((region, offset)) = map::find_region(addr) else {
+            return Err(Error::Unmapped { addr, width });
+        };
 
         match region {
             MemoryRegion::RAM => self.ram.store(offset, value),
-            MemoryRegion::BIOS => return Error!("Illegal write to BIOS memory"),
+            MemoryRegion::ScratchPad => self.scratchpad.store(offset, value),
+            MemoryRegion::BIOS => {
+                return Err(Error::UnhandledRegister {
+                    region,
+                    offset,
+                    write: true,
+                })
+            }
             MemoryRegion::DMA => return self.set_dma_register(offset, value.into()),
             MemoryRegion::MemControl => {
                 let value = value.into();
                 return match (offset, value) {
                     (0, 0x1f000000) => Ok(()),
-                    (0, _) => Error!("Bad expansion 1 base address"),
+                    (0, _) => Err(Error::UnsupportedSetting {
+                        setting: "expansion 1 base address",
+                        value,
+                    }),
                     (4, 0x1f802000) => Ok(()),
-                    (4, _) => Error!("Bad expansion 2 base address"),
+                    (4, _) => Err(Error::UnsupportedSetting {
+                        setting: "expansion 2 base address",
+                        value,
+                    }),
                     _ => {
                         trace!("Unhandled write to MEMCONTROL register.");
                         Ok(())
@@ -86,11 +290,35 @@ impl<R: Renderer> Bus<R> {
                 match offset {
                     0x0 => return self.gpu.gp0(value),
                     0x4 => return self.gpu.gp1(value),
-                    _ => return Error!("Unhandled GPU write {}: 0x{:08x}", offset, value),
+                    _ => {
+                        return Err(Error::UnhandledRegister {
+                            region,
+                            offset,
+                            write: true,
+                        })
+                    }
+                }
+            }
+            MemoryRegion::PadMemCard => {
+                // The interrupt is on the rising edge of the SIO0 IRQ flag
+                let irq = self.sio0.irq();
+                self.sio0.store(offset, value.into())?;
+                if !irq && self.sio0.irq() {
+                    self.irq.request(Interrupt::PadMemCard);
                 }
             }
-            MemoryRegion::IRQControl
-            | MemoryRegion::Expansion1
+            MemoryRegion::IRQControl => match offset {
+                0 => self.irq.acknowledge(value.into() as u16),
+                _ => self.irq.set_mask(value.into() as u16),
+            },
+            MemoryRegion::MDEC => match offset {
+                0 => {
+                    self.mdec.write(value.into());
+                    return self.do_pending_mdec_dma();
+                }
+                _ => self.mdec.set_control(value.into()),
+            },
+            MemoryRegion::Expansion1
             | MemoryRegion::Expansion2
             | MemoryRegion::RAMSize
             | MemoryRegion::CacheControl
@@ -102,7 +330,7 @@ impl<R: Renderer> Bus<R> {
         Ok(())
     }
 
-    fn dma_register(&self, offset: u32) -> Result<u32, String> {
+    fn dma_register(&self, offset: u32) -> Result<u32, Error> {
         let (major, minor) = (offset >> 4, offset & 0b1111);
         match major {
             // Channels
@@ -113,27 +341,20 @@ impl<R: Renderer> Bus<R> {
                     0x0 => Ok(channel.base()),
                     0x4 => Ok(channel.block_control()),
                     0x8 => Ok(channel.control()),
-                    _ => Error!(
-                        "Unsupported read from minor register {} for channel {}",
-                        minor,
-                        major
-                    ),
+                    _ => Err(unhandled_dma_register(offset, false)),
                 }
             }
             // Common DMA registers
             0x7 => match minor {
                 0x0 => Ok(self.dma.control()),
                 0x4 => Ok(self.dma.interrupt()),
-                _ => Error!(
-                    "Unsupported read from minor register {} for major 0x7",
-                    minor
-                ),
+                _ => Err(unhandled_dma_register(offset, false)),
             },
-            _ => Error!("Unhandled DMA register read: 0x{:08X}", offset),
+            _ => Err(unhandled_dma_register(offset, false)),
         }
     }
 
-    fn set_dma_register(&mut self, offset: u32, value: u32) -> Result<(), String> {
+    fn set_dma_register(&mut self, offset: u32, value: u32) -> Result<(), Error> {
         let (major, minor) = (offset >> 4, offset & 0b1111);
         let active_port = match major {
             // Channels
@@ -143,8 +364,8 @@ impl<R: Renderer> Bus<R> {
                 match minor {
                     0x0 => channel.set_base(value),
                     0x4 => channel.set_block_control(value),
-                    0x8 => channel.set_control(value)?,  // Might fail, so we propagate the error
-                    _ => return Error!("Unsupported write to minor register 0x{:02x} for channel 0x{:02x}, value=0x{:08x}", minor, major, value),
+                    0x8 => channel.set_control(value)?, // Might fail, so we propagate the error
+                    _ => return Err(unhandled_dma_register(offset, true)),
                 }
 
                 if channel.active() {
@@ -158,17 +379,11 @@ impl<R: Renderer> Bus<R> {
                 match minor {
                     0x0 => self.dma.set_control(value),
                     0x4 => self.dma.set_interrupt(value),
-                    _ => return Error!("Unsupported write to minor register 0x{:02x} for channel 0x{:02x}, value=0x{:08x}", minor, major, value),
+                    _ => return Err(unhandled_dma_register(offset, true)),
                 }
                 None
             }
-            _ => {
-                return Error!(
-                    "Unhandled DMA register write: 0x{:04X}, value=0x{:08X}",
-                    offset,
-                    value
-                )
-            }
+            _ => return Err(unhandled_dma_register(offset, true)),
         };
 
         if let Some(port) = active_port {
@@ -178,14 +393,33 @@ impl<R: Renderer> Bus<R> {
         }
     }
 
-    fn do_dma(&mut self, port: Port) -> Result<(), String> {
-        match self.dma.channel(port).sync_mode() {
-            SyncMode::LinkedList => self.do_dma_linked_list(port),
+    fn do_dma(&mut self, port: Port) -> Result<(), Error> {
+        match (port, self.dma.channel(port).sync_mode()) {
+            (_, SyncMode::LinkedList) => self.do_dma_linked_list(port),
+            (Port::MacroDecoderOut, _) => self.do_pending_mdec_dma(),
+            (Port::MacroDecoderIn, _) => {
+                self.do_dma_block(port)?;
+                self.do_pending_mdec_dma()
+            }
             _ => self.do_dma_block(port),
         }
     }
 
-    fn do_dma_block(&mut self, port: Port) -> Result<(), String> {
+    /// Games usually start the MDEC output channel before feeding it, so the
+    /// transfer waits until enough decoded data is available.
+    fn do_pending_mdec_dma(&mut self) -> Result<(), Error> {
+        let channel = self.dma.channel_mut(Port::MacroDecoderOut);
+        if !channel.active() {
+            return Ok(());
+        }
+
+        if self.mdec.output_len() >= channel.transfer_size()? as usize {
+            self.do_dma_block(Port::MacroDecoderOut)?;
+        }
+        Ok(())
+    }
+
+    fn do_dma_block(&mut self, port: Port) -> Result<(), Error> {
         debug!("Doing DMA block ;^) port: {:?}", port);
         let channel = self.dma.channel_mut(port);
         let increment: bool = match channel.address_mode() {
@@ -198,6 +432,7 @@ impl<R: Renderer> Bus<R> {
         let mut remaining = channel.transfer_size()?;
 
         while remaining > 0 {
+            // DMA only ever sees main RAM, scratchpad addresses just wrap into it
             let current_addr = addr & 0x1f_fffc;
 
             match channel.direction() {
@@ -205,7 +440,8 @@ impl<R: Renderer> Bus<R> {
                     let source_word: u32 = self.ram.load(current_addr);
                     match port {
                         Port::GPU => self.gpu.gp0(source_word)?,
-                        _ => return Error!("Unhandled DMA destination port {:?}", port),
+                        Port::MacroDecoderIn => self.mdec.write(source_word),
+                        _ => return Err(Error::UnsupportedDmaPort(port)),
                     }
                 }
                 Direction::ToDevice => {
@@ -214,7 +450,9 @@ impl<R: Renderer> Bus<R> {
                             1 => 0xff_ffff,
                             _ => addr.wrapping_sub(4) & 0x1f_ffff,
                         },
-                        _ => return Error!("Unhandled DMA source port {:?}", port),
+                        Port::MacroDecoderOut => self.mdec.read(),
+                        Port::GPU => self.gpu.read(),
+                        _ => return Err(Error::UnsupportedDmaPort(port)),
                     };
 
                     utils::store::<u32>(&mut self.ram.data, current_addr, source_word);
@@ -231,17 +469,17 @@ impl<R: Renderer> Bus<R> {
         Ok(())
     }
 
-    fn do_dma_linked_list(&mut self, port: Port) -> Result<(), String> {
+    fn do_dma_linked_list(&mut self, port: Port) -> Result<(), Error> {
         let channel = self.dma.channel_mut(port);
 
         let mut addr = channel.base() & 0x1f_fffc;
 
         if channel.direction() == Direction::ToDevice {
-            return Error!("Invalid DMA direction for linked list mode");
+            return Err(Error::InvalidDma("linked list transfer to RAM"));
         }
 
         if port != Port::GPU {
-            return Error!("Attempted linked list DMA on port {:?}", port);
+            return Err(Error::UnsupportedDmaPort(port));
         }
 
         loop {
@@ -266,14 +504,18 @@ impl<R: Renderer> Bus<R> {
     }
 }
 
-fn expect_align(addr: u32, align: u32) -> Result<(), String> {
-    if addr % align != 0 {
-        Error!(
-            "Unaligned memory access for address 0x{:08X}... expected alignment of {}",
-            addr,
-            align
-        )
+fn expect_align(addr: u32, width: usize) -> Result<(), Error> {
+    if !addr.is_multiple_of(width as u32) {
+        Err(Error::Unaligned { addr, width })
     } else {
         Ok(())
     }
 }
+
+fn unhandled_dma_register(offset: u32, write: bool) -> Error {
+    Error::UnhandledRegister {
+        region: MemoryRegion::DMA,
+        offset,
+        write,
+    }
+}
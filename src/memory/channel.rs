@@ -1,9 +1,9 @@
 /**
  * A DMA Channel
  */
-use crate::utils::Error;
+use crate::error::Error;
+use crate::savestate::{invalid, StateReader, StateWriter};
 use log::debug;
-use std::string::String;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Direction {
@@ -99,14 +99,16 @@ impl Channel {
         self.block_count = (block_control >> 16) as u16;
     }
 
-    pub fn transfer_size(&mut self) -> Result<u32, String> {
+    pub fn transfer_size(&mut self) -> Result<u32, Error> {
         let size = self.block_size as u32;
         let count = self.block_count as u32;
 
         match self.sync_mode {
             SyncMode::Manual => Ok(size),
             SyncMode::Request => Ok(count * size),
-            SyncMode::LinkedList => Error!("No linked list mode implemented for DMA channel."),
+            SyncMode::LinkedList => Err(Error::InvalidDma(
+                "linked list channels have no transfer size",
+            )),
         }
     }
 
@@ -128,7 +130,7 @@ impl Channel {
             | (self.dummy as u32) << 29;
     }
 
-    pub fn set_control(&mut self, value: u32) -> Result<(), String> {
+    pub fn set_control(&mut self, value: u32) -> Result<(), Error> {
         debug!("Writing 0x{:08X} to control register", value);
         match value & 0b1 {
             0 => self.direction = Direction::ToDevice,
@@ -148,10 +150,10 @@ impl Channel {
             1 => self.sync_mode = SyncMode::Request,
             2 => self.sync_mode = SyncMode::LinkedList,
             _ => {
-                return Error!(
-                    "Invalid sync mode {} in set control for DMA register",
-                    sync_mode
-                )
+                return Err(Error::UnsupportedSetting {
+                    setting: "DMA sync mode",
+                    value: sync_mode,
+                })
             }
         }
 
@@ -166,4 +168,21 @@ impl Channel {
 
         Ok(())
     }
+
+    /// The registers hold the whole channel state.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.control());
+        w.u32(self.base);
+        w.u32(self.block_control());
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.set_control(r.u32()?)
+            .map_err(|_| invalid("DMA channel control"))?;
+        self.base = r.u32()?;
+        let block_control = r.u32()?;
+        self.block_size = block_control as u16;
+        self.block_count = (block_control >> 16) as u16;
+        Ok(())
+    }
 }
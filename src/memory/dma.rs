@@ -1,4 +1,6 @@
 use super::channel::Channel;
+use crate::error::Error;
+use crate::savestate::{StateReader, StateWriter};
 
 #[derive(Debug)]
 pub struct DMA {
@@ -94,4 +96,29 @@ impl DMA {
     pub fn channel_mut(&mut self, port: Port) -> &mut Channel {
         &mut self.channels[port as usize]
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.control);
+        w.bool(self.irq_enable);
+        w.u8(self.channel_irq_enable);
+        w.u8(self.channel_irq_flags);
+        w.bool(self.force_irq);
+        w.u8(self.irq_dummy);
+        for channel in &self.channels {
+            channel.save_state(w);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.control = r.u32()?;
+        self.irq_enable = r.bool()?;
+        self.channel_irq_enable = r.u8()?;
+        self.channel_irq_flags = r.u8()?;
+        self.force_irq = r.bool()?;
+        self.irq_dummy = r.u8()?;
+        for channel in &mut self.channels {
+            channel.load_state(r)?;
+        }
+        Ok(())
+    }
 }
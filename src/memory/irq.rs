@@ -0,0 +1,76 @@
+// Interrupt controller: I_STAT at 0x1f801070 and I_MASK at 0x1f801074. Devices
+// request an interrupt by setting their I_STAT bit, the CPU sees it on its IP2 line
+// while any requested bit is also enabled in I_MASK.
+use crate::error::Error;
+use crate::savestate::{StateReader, StateWriter};
+
+/// Interrupt sources, by I_STAT bit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interrupt {
+    VBlank = 0,
+    GPU = 1,
+    CDROM = 2,
+    DMA = 3,
+    Timer0 = 4,
+    Timer1 = 5,
+    Timer2 = 6,
+    /// SIO0, the controllers and memory cards.
+    PadMemCard = 7,
+    SIO = 8,
+    SPU = 9,
+    Lightpen = 10,
+}
+
+pub struct InterruptController {
+    status: u16,
+    mask: u16,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController { status: 0, mask: 0 }
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.status |= 1 << interrupt as u16;
+    }
+
+    /// Whether the CPU's IP2 line is up.
+    pub fn pending(&self) -> bool {
+        self.status & self.mask != 0
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Writing I_STAT clears the bits written as 0.
+    pub fn acknowledge(&mut self, value: u16) {
+        self.status &= value;
+    }
+
+    pub fn mask(&self) -> u16 {
+        self.mask
+    }
+
+    pub fn set_mask(&mut self, value: u16) {
+        self.mask = value & 0x7ff;
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.status);
+        w.u16(self.mask);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.status = r.u16()?;
+        self.mask = r.u16()?;
+        Ok(())
+    }
+}
+
+impl Default for InterruptController {
+    fn default() -> InterruptController {
+        InterruptController::new()
+    }
+}
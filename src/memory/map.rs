@@ -1,5 +1,3 @@
-use crate::utils::Error;
-
 // Global sizes and offsets for regions
 
 pub const BIOS_SIZE: u32 = 512 * 1024;
@@ -8,6 +6,9 @@ pub const BIOS_START: u32 = 0x1fc00000;
 pub const RAM_SIZE: u32 = 2 * 1024 * 1024;
 pub const RAM_START: u32 = 0x00000000;
 
+pub const SCRATCHPAD_SIZE: u32 = 1024;
+pub const SCRATCHPAD_START: u32 = 0x1f800000;
+
 // Region masking
 
 const REGION_MASK: [u32; 8] = [
@@ -17,7 +18,7 @@ const REGION_MASK: [u32; 8] = [
     0xffffffff, 0xffffffff, // KSEG2: 1024MB
 ];
 
-fn mask_region(addr: u32) -> u32 {
+pub fn mask_region(addr: u32) -> u32 {
     let index = (addr >> 29) as usize;
     addr & REGION_MASK[index]
 }
@@ -41,6 +42,7 @@ impl Range {
 #[derive(Debug, Clone, Copy)]
 pub enum MemoryRegion {
     RAM,
+    ScratchPad,
     BIOS,
     MemControl,
     RAMSize,
@@ -52,13 +54,19 @@ pub enum MemoryRegion {
     IRQControl,
     Timers,
     CacheControl,
+    PadMemCard,
+    MDEC,
 }
 
 // Note: Increment the array size if you add a new region.
 // Note: Put the most frequently accessed regions first, for performance.
-const ALL_REGIONS: [(MemoryRegion, Range); 12] = [
+const ALL_REGIONS: [(MemoryRegion, Range); 15] = [
     (MemoryRegion::RAM, Range(RAM_START, RAM_SIZE)),
     (MemoryRegion::BIOS, Range(BIOS_START, BIOS_SIZE)),
+    (
+        MemoryRegion::ScratchPad,
+        Range(SCRATCHPAD_START, SCRATCHPAD_SIZE),
+    ),
     (MemoryRegion::Expansion1, Range(0x1f000000, 8 * 1024 * 1024)),
     (MemoryRegion::MemControl, Range(0x1f801000, 36)),
     (MemoryRegion::RAMSize, Range(0x1f801060, 4)),
@@ -69,14 +77,78 @@ const ALL_REGIONS: [(MemoryRegion, Range); 12] = [
     (MemoryRegion::Timers, Range(0x1f801100, 0x30)),
     (MemoryRegion::DMA, Range(0x1f801080, 0x80)),
     (MemoryRegion::GPU, Range(0x1f801810, 8)),
+    (MemoryRegion::PadMemCard, Range(0x1f801040, 16)),
+    (MemoryRegion::MDEC, Range(0x1f801820, 8)),
 ];
 
-pub fn find_region(addr: u32) -> Result<(MemoryRegion, u32), String> {
-    let addr = mask_region(addr);
+/// None if nothing is mapped at `addr`.
+pub fn find_region(addr: u32) -> Option<(MemoryRegion, u32)> {
+    let masked = mask_region(addr);
     for (region, range) in ALL_REGIONS.iter() {
-        if let Some(offset) = range.contains(addr) {
-            return Ok((*region, offset));
+        if let Some(offset) = range.contains(masked) {
+            // The scratchpad is part of the data cache, so the uncached segment can't see it
+            if let MemoryRegion::ScratchPad = region {
+                if addr >> 29 == 5 {
+                    return None;
+                }
+            }
+            return Some((*region, offset));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_mirror_physical_memory() {
+        for addr in [0x0000_1234, 0x8000_1234, 0xa000_1234] {
+            assert!(matches!(
+                find_region(addr),
+                Some((MemoryRegion::RAM, 0x1234))
+            ));
+        }
+        for addr in [0x1fc0_0010, 0x9fc0_0010, 0xbfc0_0010] {
+            assert!(matches!(
+                find_region(addr),
+                Some((MemoryRegion::BIOS, 0x10))
+            ));
         }
     }
-    return Error!("Unknown memory region in store: 0x{:08X}", addr);
+
+    #[test]
+    fn scratchpad_is_cached_only() {
+        for addr in [0x1f80_0010, 0x9f80_0010] {
+            assert!(matches!(
+                find_region(addr),
+                Some((MemoryRegion::ScratchPad, 0x10))
+            ));
+        }
+        assert!(find_region(0xbf80_0010).is_none());
+    }
+
+    #[test]
+    fn kseg2_is_not_mirrored() {
+        assert!(matches!(
+            find_region(0xfffe_0130),
+            Some((MemoryRegion::CacheControl, 0))
+        ));
+        assert!(find_region(0xc000_1234).is_none());
+    }
+
+    #[test]
+    fn region_ends() {
+        assert!(matches!(
+            find_region(RAM_START + RAM_SIZE - 1),
+            Some((MemoryRegion::RAM, offset)) if offset == RAM_SIZE - 1
+        ));
+        assert!(find_region(RAM_START + RAM_SIZE).is_none());
+        assert!(matches!(
+            find_region(0x1f80_1070),
+            Some((MemoryRegion::IRQControl, 0))
+        ));
+        assert!(find_region(0x1f80_1078).is_none());
+    }
 }
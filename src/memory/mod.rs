@@ -1,9 +1,15 @@
 mod bus;
 mod channel;
 mod dma;
+mod irq;
 mod map;
 mod ram;
+mod scratchpad;
+mod watch;
 
 pub use bus::Bus;
-pub use map::{BIOS_SIZE, BIOS_START};
+pub use dma::Port;
+pub use irq::{Interrupt, InterruptController};
+pub use map::{MemoryRegion, BIOS_SIZE, BIOS_START};
 pub use ram::RAM;
+pub use watch::{WatchHit, WatchKind, Watchpoint};
@@ -21,3 +21,9 @@ impl RAM {
         utils::store(&mut self.data, addr, value)
     }
 }
+
+impl Default for RAM {
+    fn default() -> RAM {
+        RAM::new()
+    }
+}
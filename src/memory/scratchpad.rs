@@ -0,0 +1,25 @@
+use super::map::SCRATCHPAD_SIZE;
+use crate::utils;
+
+/// The 1 KiB data cache, which the PSX CPU only uses as fast RAM. It sits on the
+/// CPU side of the bus, so DMA can't reach it.
+pub struct ScratchPad {
+    pub data: Vec<u8>,
+}
+
+impl ScratchPad {
+    pub fn new() -> ScratchPad {
+        let data = vec![0x69; SCRATCHPAD_SIZE as usize];
+        ScratchPad { data }
+    }
+
+    #[inline]
+    pub fn load<T: TryFrom<u32>>(&self, addr: u32) -> T {
+        utils::load(&self.data, addr)
+    }
+
+    #[inline]
+    pub fn store<T: Into<u32>>(&mut self, addr: u32, value: T) {
+        utils::store(&mut self.data, addr, value)
+    }
+}
@@ -0,0 +1,41 @@
+// Debugger watchpoints on the CPU's accesses through the bus. DMA isn't watched.
+use super::map;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// Segment mirrors count as the same address, so watching 0x80010000 also
+    /// catches accesses through 0xa0010000.
+    pub fn matches(&self, addr: u32, width: u32, write: bool) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        };
+
+        let start = map::mask_region(self.addr);
+        let addr = map::mask_region(addr);
+        kind && addr < start.wrapping_add(self.len) && start < addr.wrapping_add(width)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub addr: u32,
+    pub write: bool,
+    /// The value being stored, None for reads.
+    pub value: Option<u32>,
+}
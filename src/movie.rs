@@ -0,0 +1,271 @@
+/**
+ * Input movies: the pad inputs of every frame, from power on or from a save state
+ * embedded in the movie, so a run can be replayed exactly. Every few frames the
+ * CRC32 of RAM goes in too, and playback stops with `Error::Desync` as soon as it
+ * comes out different.
+ *
+ *     "RSXMOVIE"   magic
+ *     u32          format version
+ *     [u8]         MD5 of the BIOS
+ *     bool, [u8]   whether there's a disc, and its `Disc::hash`
+ *     bool, [u8]   whether an EXE was booted with the HLE kernel, and its MD5
+ *     u32          frames between checksums
+ *     [u8]         save state to start from, empty to start from power on
+ *
+ * then for every frame the state of both pads (u16 buttons, [u8] axes), and a u32
+ * checksum after every checksum interval. Byte strings start with their u32
+ * length, like everything a `StateWriter` writes.
+ */
+use crate::error::{Error, MovieError};
+use crate::renderer::Renderer;
+use crate::savestate::{StateReader, StateWriter};
+use crate::sio::PadState;
+use crate::system::System;
+use flate2::Crc;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"RSXMOVIE";
+pub const VERSION: u32 = 2;
+
+/// Once a second.
+pub const CHECKSUM_INTERVAL: u32 = 60;
+
+pub struct Recorder {
+    output: BufWriter<File>,
+    path: PathBuf,
+    frame: u64,
+    /// The inputs of the frame being run, written out at its end.
+    pending: StateWriter,
+}
+
+impl Recorder {
+    /// Starts recording to `path`. A system that hasn't run yet is recorded from
+    /// power on, otherwise its current state goes in the movie.
+    pub fn create<R: Renderer>(path: &Path, system: &mut System<R>) -> Result<Recorder, Error> {
+        let (bios, disc, exe) = identity(system)?;
+        let state = match system.cpu().cycles() {
+            0 => Vec::new(),
+            _ => system.save_state(),
+        };
+
+        let mut w = StateWriter::new();
+        w.u32(VERSION);
+        w.bytes(&bios);
+        w.bool(disc.is_some());
+        w.bytes(&disc.unwrap_or([0; 16]));
+        w.bool(exe.is_some());
+        w.bytes(&exe.unwrap_or([0; 16]));
+        w.u32(CHECKSUM_INTERVAL);
+        w.bytes(&state);
+
+        let io_error = |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut output = BufWriter::new(File::create(path).map_err(io_error)?);
+        output
+            .write_all(MAGIC)
+            .and_then(|_| output.write_all(&w.into_inner()))
+            .map_err(io_error)?;
+
+        info!("Recording movie to {}", path.display());
+        Ok(Recorder {
+            output,
+            path: path.to_path_buf(),
+            frame: 0,
+            pending: StateWriter::new(),
+        })
+    }
+
+    /// Runs a frame with these inputs, recording them.
+    pub fn run_frame<R: Renderer>(
+        &mut self,
+        system: &mut System<R>,
+        inputs: &[PadState; 2],
+    ) -> Result<(), Error> {
+        self.begin_frame(system, inputs);
+        system.run_frame()?;
+        self.end_frame(system)
+    }
+
+    /// Gives the system these inputs for the next frame, for frontends that run
+    /// it themselves. Then call `end_frame`.
+    pub fn begin_frame<R: Renderer>(&mut self, system: &mut System<R>, inputs: &[PadState; 2]) {
+        for (port, input) in inputs.iter().enumerate() {
+            system.set_input(port, input);
+            self.pending.u16(input.buttons);
+            self.pending.bytes(&input.axes);
+        }
+    }
+
+    /// Records the frame that ran since `begin_frame`.
+    pub fn end_frame<R: Renderer>(&mut self, system: &System<R>) -> Result<(), Error> {
+        self.frame += 1;
+        if self.frame.is_multiple_of(CHECKSUM_INTERVAL as u64) {
+            self.pending.u32(ram_checksum(system));
+        }
+
+        // Flushed every frame, the process may well exit without dropping us
+        let frame = std::mem::take(&mut self.pending).into_inner();
+        self.output
+            .write_all(&frame)
+            .and_then(|_| self.output.flush())
+            .map_err(|source| Error::Io {
+                path: self.path.clone(),
+                source,
+            })
+    }
+
+    /// Frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.frame
+    }
+}
+
+pub struct Player {
+    data: Vec<u8>,
+    // Where the next frame starts in `data`
+    pos: usize,
+    interval: u32,
+    frame: u64,
+}
+
+impl Player {
+    /// Loads the movie at `path` and gets `system` ready to play it. Fails if the
+    /// BIOS, the disc or the booted EXE aren't the ones it was recorded with, or
+    /// if it starts from power on and `system` has already run.
+    pub fn open<R: Renderer>(path: &Path, system: &mut System<R>) -> Result<Player, Error> {
+        let data = std::fs::read(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if !data.starts_with(MAGIC) {
+            return Err(Error::InvalidMovie(MovieError::NotAMovie));
+        }
+
+        let mut r = StateReader::new(&data[MAGIC.len()..]);
+        let version = r.u32().map_err(movie_error)?;
+        if version != VERSION {
+            return Err(Error::InvalidMovie(MovieError::Version(version)));
+        }
+
+        let (bios, disc, exe, interval, state) = (|| {
+            let bios = r.bytes()?;
+            let has_disc = r.bool()?;
+            let disc = r.bytes()?;
+            let disc = has_disc.then_some(disc);
+            let has_exe = r.bool()?;
+            let exe = r.bytes()?;
+            let exe = has_exe.then_some(exe);
+            Ok((bios, disc, exe, r.u32()?.max(1), r.bytes()?))
+        })()
+        .map_err(movie_error)?;
+
+        let (system_bios, system_disc, system_exe) = identity(system)?;
+        if bios != system_bios {
+            return Err(Error::InvalidMovie(MovieError::OtherBios));
+        }
+        if disc.as_deref() != system_disc.as_ref().map(|d| &d[..]) {
+            return Err(Error::InvalidMovie(MovieError::OtherDisc));
+        }
+        if exe.as_deref() != system_exe.as_ref().map(|e| &e[..]) {
+            return Err(Error::InvalidMovie(MovieError::OtherExe));
+        }
+
+        match (state.is_empty(), system.cpu().cycles()) {
+            (true, 0) => (),
+            (true, _) => return Err(Error::InvalidMovie(MovieError::AlreadyRunning)),
+            (false, _) => system.load_state(&state)?,
+        }
+
+        Ok(Player {
+            pos: MAGIC.len() + r.position(),
+            data,
+            interval,
+            frame: 0,
+        })
+    }
+
+    /// Runs the next frame with the recorded inputs. Returns false once the movie
+    /// is over.
+    pub fn run_frame<R: Renderer>(&mut self, system: &mut System<R>) -> Result<bool, Error> {
+        if !self.begin_frame(system)? {
+            return Ok(false);
+        }
+        system.run_frame()?;
+        self.end_frame(system)?;
+        Ok(true)
+    }
+
+    /// Gives the system the recorded inputs of the next frame, for frontends that
+    /// run it themselves. Then call `end_frame`. Returns false once the movie is
+    /// over.
+    pub fn begin_frame<R: Renderer>(&mut self, system: &mut System<R>) -> Result<bool, Error> {
+        let mut r = StateReader::new(&self.data[self.pos..]);
+        if r.at_end() {
+            return Ok(false);
+        }
+
+        for port in 0..2 {
+            let mut input = PadState::new();
+            input.buttons = r.u16().map_err(movie_error)?;
+            r.bytes_into(&mut input.axes).map_err(movie_error)?;
+            system.set_input(port, &input);
+        }
+
+        self.pos += r.position();
+        Ok(true)
+    }
+
+    /// Checks the frame that ran since `begin_frame` against the recording.
+    pub fn end_frame<R: Renderer>(&mut self, system: &System<R>) -> Result<(), Error> {
+        self.frame += 1;
+        if !self.frame.is_multiple_of(self.interval as u64) {
+            return Ok(());
+        }
+
+        let mut r = StateReader::new(&self.data[self.pos..]);
+        let expected = r.u32().map_err(movie_error)?;
+        self.pos += r.position();
+        let actual = ram_checksum(system);
+        if actual != expected {
+            return Err(Error::Desync {
+                frame: self.frame,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Frames played so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+type Identity = ([u8; 16], Option<[u8; 16]>, Option<[u8; 16]>);
+
+/// Hashes of the BIOS, of the disc if there's one, and of the EXE if one was
+/// booted with the HLE kernel.
+fn identity<R: Renderer>(system: &mut System<R>) -> Result<Identity, Error> {
+    let bios = system.cpu().bus().bios().hash();
+    let disc = system.disc_mut().map(|disc| disc.hash()).transpose()?;
+    Ok((bios, disc, system.exe_hash()))
+}
+
+/// The movie is read with a `StateReader`, whose errors are about save states.
+fn movie_error(e: Error) -> Error {
+    match e {
+        Error::InvalidSaveState(e) => Error::InvalidMovie(MovieError::Corrupt(e)),
+        e => e,
+    }
+}
+
+fn ram_checksum<R: Renderer>(system: &System<R>) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(system.cpu().bus().ram());
+    crc.sum()
+}
@@ -6,4 +6,21 @@ pub trait Renderer {
     fn draw(&mut self);
     fn display(&mut self);
     fn set_draw_offset(&mut self, position: Position);
+    /// What's displayed of the VRAM area at `x`, `y`, as RGB888 rows at one pixel
+    /// per VRAM pixel, top row first. None if there's nothing to read back.
+    fn read_display(&self, x: u16, y: u16, width: u16, height: u16) -> Option<Vec<u8>>;
+}
+
+/// Draws nothing, for running headless.
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn push_triangle(&mut self, _: [Position; 3], _: [Color; 3]) {}
+    fn push_quad(&mut self, _: [Position; 4], _: [Color; 4]) {}
+    fn draw(&mut self) {}
+    fn display(&mut self) {}
+    fn set_draw_offset(&mut self, _: Position) {}
+    fn read_display(&self, _: u16, _: u16, _: u16, _: u16) -> Option<Vec<u8>> {
+        None
+    }
 }
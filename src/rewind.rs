@@ -0,0 +1,226 @@
+/**
+ * Rewind. Snapshots are taken every few frames, the newest one is kept whole and
+ * each older one as a delta against the one after it: the XOR of the two, with
+ * the runs of zeros (most of RAM and VRAM between two frames) squeezed out. The
+ * oldest deltas are dropped to stay within the memory budget.
+ *
+ * `Session` calls `frame` after every frame, and while rewinding calls `step_back`
+ * then runs a frame to show the picture, without `frame`. There's no audio output
+ * yet; once there is, it should be muted while rewinding.
+ */
+use crate::error::Error;
+use crate::renderer::Renderer;
+use crate::system::System;
+use std::collections::VecDeque;
+
+pub struct Rewind {
+    /// Frames between snapshots.
+    interval: u32,
+    /// Bytes the snapshots may take, deltas included.
+    budget: usize,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    /// Oldest first. Applying the last one to `latest` gives the snapshot before it.
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, budget: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    /// Takes a snapshot if it's time to.
+    pub fn frame<R: Renderer>(&mut self, system: &System<R>) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let snapshot = system.snapshot();
+        if let Some(previous) = self.latest.take() {
+            let delta = diff(&snapshot, &previous);
+            self.used += delta.len();
+            self.used -= previous.len();
+            self.deltas.push_back(delta);
+        }
+        self.used += snapshot.len();
+        self.latest = Some(snapshot);
+
+        while self.used > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Goes back to the snapshot before the last one restored, or to the newest
+    /// one. Returns false when there's nothing older left.
+    pub fn step_back<R: Renderer>(&mut self, system: &mut System<R>) -> Result<bool, Error> {
+        let Some(latest) = self.latest.as_mut() else {
+            return Ok(false);
+        };
+        self.frames = 0;
+
+        let Some(delta) = self.deltas.pop_back() else {
+            system.restore(latest)?;
+            return Ok(false);
+        };
+
+        let older = patch(latest, &delta);
+        self.used -= delta.len();
+        self.used -= latest.len();
+        self.used += older.len();
+        system.restore(&older)?;
+        *latest = older;
+        Ok(true)
+    }
+
+    /// Snapshots held, the newest included.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Memory taken by the snapshots.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+}
+
+/// What turns `newer` back into `older`: the length of `older`, then runs of
+/// (zero count, literal count, literal bytes) over the XOR of the two.
+fn diff(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| older[i] ^ newer.get(i).copied().unwrap_or(0);
+
+    let mut delta = (older.len() as u32).to_le_bytes().to_vec();
+    let mut i = 0;
+    while i < older.len() {
+        let zeros_start = i;
+        while i < older.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let literals_start = i;
+        // A single unchanged byte between changes isn't worth a new run
+        while i < older.len() && (xor(i) != 0 || (i + 1 < older.len() && xor(i + 1) != 0)) {
+            i += 1;
+        }
+
+        delta.extend_from_slice(&((literals_start - zeros_start) as u32).to_le_bytes());
+        delta.extend_from_slice(&((i - literals_start) as u32).to_le_bytes());
+        delta.extend((literals_start..i).map(xor));
+    }
+    delta
+}
+
+/// Undoes `diff`.
+fn patch(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let word = |offset: usize| u32::from_le_bytes(delta[offset..offset + 4].try_into().unwrap());
+
+    let len = word(0) as usize;
+    let mut older: Vec<u8> = newer
+        .iter()
+        .copied()
+        .chain(std::iter::repeat(0))
+        .take(len)
+        .collect();
+
+    let (mut offset, mut i) = (4, 0);
+    while offset < delta.len() {
+        i += word(offset) as usize;
+        let literals = word(offset + 4) as usize;
+        offset += 8;
+        for &byte in &delta[offset..offset + literals] {
+            older[i] ^= byte;
+            i += 1;
+        }
+        offset += literals;
+    }
+    older
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bios::BIOS;
+    use crate::renderer::NullRenderer;
+
+    fn round_trip(newer: &[u8], older: &[u8]) -> Vec<u8> {
+        let delta = diff(newer, older);
+        assert_eq!(patch(newer, &delta), older);
+        delta
+    }
+
+    #[test]
+    fn unchanged() {
+        let data = [1, 2, 3, 4, 5];
+        // Just the length and one run of zeros
+        assert_eq!(round_trip(&data, &data).len(), 12);
+        round_trip(&[], &[]);
+    }
+
+    #[test]
+    fn changes() {
+        round_trip(&[0, 0, 0, 0, 0, 0], &[1, 0, 0, 0, 0, 1]);
+        round_trip(&[1, 2, 3, 4], &[4, 3, 2, 1]);
+        round_trip(&[5; 8], &[5, 5, 5, 5, 5, 5, 5, 6]);
+    }
+
+    #[test]
+    fn changes_one_byte_apart() {
+        let newer = [0; 9];
+        let older = [0, 1, 0, 1, 0, 1, 0, 0, 1];
+        let delta = round_trip(&newer, &older);
+        // The unchanged bytes between changes join them in one run
+        assert_eq!(&delta[4..12], [1, 0, 0, 0, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn different_lengths() {
+        round_trip(&[1, 2, 3], &[1, 2, 3, 4, 5]);
+        round_trip(&[1, 2, 3, 4, 5], &[1, 2, 3]);
+        round_trip(&[1, 2, 3, 4, 5], &[9, 2]);
+        round_trip(&[], &[0, 0, 7]);
+        round_trip(&[1, 2, 3], &[]);
+    }
+
+    #[test]
+    fn step_back() {
+        let mut system = System::new(BIOS::empty(), NullRenderer);
+        let mut rewind = Rewind::new(1, usize::MAX);
+        let mut snapshots = Vec::new();
+        for i in 0..3 {
+            system.cpu_mut().bus_mut().ram_mut()[i * 100] = 0xff;
+            system.step().unwrap();
+            rewind.frame(&system);
+            snapshots.push(system.snapshot());
+        }
+        assert_eq!(rewind.len(), 3);
+
+        assert!(rewind.step_back(&mut system).unwrap());
+        assert_eq!(system.snapshot(), snapshots[1]);
+        assert!(rewind.step_back(&mut system).unwrap());
+        assert_eq!(system.snapshot(), snapshots[0]);
+        assert!(!rewind.step_back(&mut system).unwrap());
+        assert_eq!(system.snapshot(), snapshots[0]);
+    }
+}
@@ -0,0 +1,294 @@
+/**
+ * Save states. A state file starts with a small uncompressed header, so frontends
+ * can list slots with their thumbnails without unpacking anything:
+ *
+ *     "RSXSTATE"   magic
+ *     u32          format version
+ *     [u8; 16]     MD5 of the BIOS the state was made with
+ *     u64          when it was saved, in seconds since the Unix epoch
+ *     u16, u16     thumbnail width and height
+ *     [u8]         thumbnail, RGB888
+ *
+ * followed by the machine state, zlib compressed. Each component writes its
+ * fields in order with a `StateWriter` and reads them back in the same order with
+ * a `StateReader`, so VERSION has to go up whenever one of them changes.
+ */
+use crate::error::{Error, StateError};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 8] = b"RSXSTATE";
+pub const VERSION: u32 = 3;
+
+/// Thumbnails are scaled down to fit in this width.
+const THUMBNAIL_WIDTH: u32 = 160;
+
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    /// RGB888, row by row.
+    pub rgb: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Scales an RGB888 image down by a whole factor, keeping the nearest pixel.
+    pub fn new(width: u32, height: u32, rgb: &[u8]) -> Thumbnail {
+        let scale = width.div_ceil(THUMBNAIL_WIDTH).max(1);
+        let (thumb_width, thumb_height) = (width / scale, height / scale);
+
+        let mut thumb = Vec::with_capacity((thumb_width * thumb_height * 3) as usize);
+        for y in 0..thumb_height {
+            for x in 0..thumb_width {
+                let offset = (((y * scale) * width + x * scale) * 3) as usize;
+                thumb.extend_from_slice(&rgb[offset..offset + 3]);
+            }
+        }
+
+        Thumbnail {
+            width: thumb_width as u16,
+            height: thumb_height as u16,
+            rgb: thumb,
+        }
+    }
+}
+
+pub struct Header {
+    pub version: u32,
+    pub bios_hash: [u8; 16],
+    pub timestamp: u64,
+    pub thumbnail: Thumbnail,
+}
+
+impl Header {
+    /// Reads the header of a state file. Also returns where the compressed state
+    /// starts.
+    pub fn parse(data: &[u8]) -> Result<(Header, usize), Error> {
+        let mut r = StateReader::new(data);
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(Error::InvalidSaveState(StateError::NotAState));
+        }
+
+        let version = r.u32()?;
+        let mut bios_hash = [0; 16];
+        bios_hash.copy_from_slice(r.take(16)?);
+        let timestamp = r.u64()?;
+        let width = r.u16()?;
+        let height = r.u16()?;
+        let rgb = r.take(width as usize * height as usize * 3)?.to_vec();
+
+        let header = Header {
+            version,
+            bios_hash,
+            timestamp,
+            thumbnail: Thumbnail { width, height, rgb },
+        };
+        Ok((header, r.pos))
+    }
+}
+
+/// Puts a state file together.
+pub fn encode(bios_hash: [u8; 16], thumbnail: &Thumbnail, state: &[u8]) -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_secs());
+
+    let mut w = StateWriter::new();
+    w.data.extend_from_slice(MAGIC);
+    w.u32(VERSION);
+    w.data.extend_from_slice(&bios_hash);
+    w.u64(timestamp);
+    w.u16(thumbnail.width);
+    w.u16(thumbnail.height);
+    w.data.extend_from_slice(&thumbnail.rgb);
+
+    let mut encoder = ZlibEncoder::new(w.data, Compression::fast());
+    // Writing to a Vec can't fail
+    encoder.write_all(state).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Checks the header of a state file and unpacks the machine state.
+pub fn decode(data: &[u8]) -> Result<(Header, Vec<u8>), Error> {
+    let (header, start) = Header::parse(data)?;
+    if header.version != VERSION {
+        return Err(Error::InvalidSaveState(StateError::Version(header.version)));
+    }
+
+    let mut state = Vec::new();
+    ZlibDecoder::new(&data[start..])
+        .read_to_end(&mut state)
+        .map_err(|e| Error::InvalidSaveState(StateError::Decompression(e)))?;
+    Ok((header, state))
+}
+
+/// Serializes component state, little endian.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16s(&mut self, values: &[u16]) {
+        for &value in values {
+            self.u16(value);
+        }
+    }
+
+    pub fn u32s(&mut self, values: &[u32]) {
+        for &value in values {
+            self.u32(value);
+        }
+    }
+
+    /// Length prefixed, for data that isn't always the same size.
+    pub fn bytes(&mut self, values: &[u8]) {
+        self.u32(values.len() as u32);
+        self.data.extend_from_slice(values);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+/// Reads back what a `StateWriter` wrote.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(Error::InvalidSaveState(StateError::Truncated))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("boolean")),
+        }
+    }
+
+    pub fn u16s(&mut self, values: &mut [u16]) -> Result<(), Error> {
+        for value in values {
+            *value = self.u16()?;
+        }
+        Ok(())
+    }
+
+    pub fn u32s(&mut self, values: &mut [u32]) -> Result<(), Error> {
+        for value in values {
+            *value = self.u32()?;
+        }
+        Ok(())
+    }
+
+    pub fn bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Length prefixed bytes that have to fill `values` exactly, like RAM.
+    pub fn bytes_into(&mut self, values: &mut [u8]) -> Result<(), Error> {
+        let len = self.u32()? as usize;
+        if len != values.len() {
+            return Err(Error::InvalidSaveState(StateError::LengthMismatch {
+                len,
+                expected: values.len(),
+            }));
+        }
+        values.copy_from_slice(self.take(len)?);
+        Ok(())
+    }
+
+    pub fn string(&mut self) -> Result<String, Error> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid("string"))
+    }
+
+    /// Bytes read so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    /// Errors out if anything is left over, which means the state and the
+    /// components disagree on the layout.
+    pub fn finish(&self) -> Result<(), Error> {
+        match self.pos == self.data.len() {
+            true => Ok(()),
+            false => Err(Error::InvalidSaveState(StateError::TrailingBytes(
+                self.data.len() - self.pos,
+            ))),
+        }
+    }
+}
+
+/// For values out of range, like an enum discriminant that doesn't exist.
+pub fn invalid(what: &'static str) -> Error {
+    Error::InvalidSaveState(StateError::InvalidValue(what))
+}
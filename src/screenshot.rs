@@ -0,0 +1,43 @@
+/**
+ * Screenshots, as PNG files: what's on screen, the display area of VRAM at its
+ * native resolution and depth, or a dump of all of VRAM.
+ */
+use crate::error::Error;
+use crate::gpu::{VRAM_HEIGHT, VRAM_WIDTH};
+use crate::renderer::Renderer;
+use crate::system::System;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// What's on screen.
+pub fn save<R: Renderer>(system: &System<R>, path: &Path) -> Result<(), Error> {
+    let (width, height, rgb) = system.cpu().bus().gpu().display_rgb();
+    write_png(path, width, height, &rgb)
+}
+
+/// All of VRAM, 1024x512.
+pub fn save_vram<R: Renderer>(system: &System<R>, path: &Path) -> Result<(), Error> {
+    let rgb = system.cpu().bus().gpu().vram_rgb();
+    write_png(path, VRAM_WIDTH as u32, VRAM_HEIGHT as u32, &rgb)
+}
+
+/// Writes an RGB888 image.
+fn write_png(path: &Path, width: u32, height: u32, rgb: &[u8]) -> Result<(), Error> {
+    let io_error = |source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let file = File::create(path).map_err(io_error)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgb))
+        .map_err(|e| match e {
+            png::EncodingError::IoError(source) => io_error(source),
+            e => io_error(std::io::Error::other(e)),
+        })
+}
@@ -0,0 +1,339 @@
+/**
+ * Rhai scripts driving the emulator, for automated playthroughs and collecting
+ * data. The script runs once when it's loaded, to register its callbacks, then
+ * the callbacks run as the emulation goes:
+ *
+ *     on_frame(|| print(read16(0x800a1234)));
+ *     on_exec(0x80012345, || set_reg("v0", 1));
+ *     on_write(0x800a1234, |addr, value| print(`${addr} <- ${value}`));
+ *
+ * Functions:
+ *
+ *     read8/16/32(addr), write8/16/32(addr, value)   RAM, BIOS and scratchpad
+ *     reg(name), set_reg(name, value)    "v0", "r2", "pc", "hi", "lo"
+ *     set_input(port, buttons)           pad buttons held, bits as in `Button`
+ *     frame()                            frames run so far
+ *     save_state(path), load_state(path)
+ *     screenshot(path)                   PNG of the display
+ *     vram_dump(path)                    PNG of all of VRAM
+ *     stop()                             ends the run after this frame
+ */
+use crate::cpu::REGISTER_NAMES;
+use crate::error::Error;
+use crate::memory::{WatchKind, Watchpoint};
+use crate::renderer::Renderer;
+use crate::screenshot;
+use crate::sio::PadState;
+use crate::system::System;
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, Scope, AST};
+use std::cell::{RefCell, RefMut};
+use std::path::Path;
+use std::rc::Rc;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Operations the script may run at load time or in one callback before it's
+/// stopped with an error, so an endless loop can't hang the emulator.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+struct Hooks {
+    frame: Vec<FnPtr>,
+    exec: Vec<(u32, FnPtr)>,
+    write: Vec<(u32, FnPtr)>,
+    frames: u64,
+    stopped: bool,
+}
+
+pub struct Script<R: Renderer> {
+    engine: Engine,
+    ast: AST,
+    system: Rc<RefCell<System<R>>>,
+    hooks: Rc<RefCell<Hooks>>,
+}
+
+impl<R: Renderer + 'static> Script<R> {
+    /// Compiles and runs the script at `path`, which then drives `system`.
+    pub fn load(path: &Path, system: System<R>) -> Result<Script<R>, Error> {
+        let system = Rc::new(RefCell::new(system));
+        let hooks = Rc::new(RefCell::new(Hooks {
+            frame: Vec::new(),
+            exec: Vec::new(),
+            write: Vec::new(),
+            frames: 0,
+            stopped: false,
+        }));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        register_memory(&mut engine, &system);
+        register_system(&mut engine, &system, &hooks);
+        register_hooks(&mut engine, &system, &hooks);
+
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(script_error)?;
+        engine
+            .run_ast_with_scope(&mut Scope::new(), &ast)
+            .map_err(script_error)?;
+
+        Ok(Script {
+            engine,
+            ast,
+            system,
+            hooks,
+        })
+    }
+
+    pub fn system(&self) -> RefMut<'_, System<R>> {
+        self.system.borrow_mut()
+    }
+
+    /// Runs a frame, calling the callbacks along the way. Returns false once the
+    /// script has called `stop`.
+    pub fn run_frame(&mut self) -> Result<bool, Error> {
+        if self.hooks.borrow().stopped {
+            return Ok(false);
+        }
+
+        let end = {
+            let system = self.system.borrow();
+            system.cpu().cycles() + system.cycles_per_frame()
+        };
+        loop {
+            let pc = {
+                let system = self.system.borrow();
+                if system.cpu().cycles() >= end {
+                    break;
+                }
+                system.cpu().pc()
+            };
+            let exec = callbacks(&self.hooks.borrow().exec, pc);
+            self.call(&exec, ())?;
+
+            let hit = {
+                let mut system = self.system.borrow_mut();
+                system.step()?;
+                system.cpu_mut().bus_mut().take_watch_hit()
+            };
+            if let Some(hit) = hit {
+                let write = callbacks(&self.hooks.borrow().write, hit.watchpoint.addr);
+                let value = hit.value.unwrap_or(0) as i64;
+                self.call(&write, (hit.addr as i64, value))?;
+            }
+        }
+
+        self.system.borrow_mut().end_frame();
+        self.hooks.borrow_mut().frames += 1;
+        let frame = self.hooks.borrow().frame.clone();
+        self.call(&frame, ())?;
+        Ok(!self.hooks.borrow().stopped)
+    }
+
+    fn call(&self, callbacks: &[FnPtr], args: impl FuncArgs + Clone) -> Result<(), Error> {
+        for callback in callbacks {
+            // Whatever they return is ignored
+            let _: Dynamic = callback
+                .call(&self.engine, &self.ast, args.clone())
+                .map_err(script_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn register_memory<R: Renderer + 'static>(engine: &mut Engine, system: &Rc<RefCell<System<R>>>) {
+    for (name, bytes) in [("8", 1), ("16", 2), ("32", 4)] {
+        let s = system.clone();
+        engine.register_fn(format!("read{}", name), move |addr: i64| {
+            let system = s.borrow();
+            (0..bytes).try_fold(0, |value, i| {
+                let addr = (addr as u32).wrapping_add(i);
+                let byte = system
+                    .cpu()
+                    .bus()
+                    .peek(addr)
+                    .ok_or_else(|| unmapped(addr))?;
+                ScriptResult::Ok(value | (byte as i64) << (i * 8))
+            })
+        });
+
+        let s = system.clone();
+        engine.register_fn(format!("write{}", name), move |addr: i64, value: i64| {
+            let mut system = s.borrow_mut();
+            for i in 0..bytes {
+                let addr = (addr as u32).wrapping_add(i);
+                if !system
+                    .cpu_mut()
+                    .bus_mut()
+                    .poke(addr, (value >> (i * 8)) as u8)
+                {
+                    return Err(unmapped(addr));
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+fn register_system<R: Renderer + 'static>(
+    engine: &mut Engine,
+    system: &Rc<RefCell<System<R>>>,
+    hooks: &Rc<RefCell<Hooks>>,
+) {
+    let s = system.clone();
+    engine.register_fn("reg", move |name: &str| -> ScriptResult<i64> {
+        let system = s.borrow();
+        let cpu = system.cpu();
+        let value = match name {
+            "pc" => cpu.pc(),
+            "hi" => cpu.hi(),
+            "lo" => cpu.lo(),
+            name => cpu.registers()[register(name)?],
+        };
+        Ok(value as i64)
+    });
+
+    let s = system.clone();
+    engine.register_fn(
+        "set_reg",
+        move |name: &str, value: i64| -> ScriptResult<()> {
+            let mut system = s.borrow_mut();
+            let cpu = system.cpu_mut();
+            let value = value as u32;
+            match name {
+                "pc" => cpu.set_pc(value),
+                "hi" => cpu.set_hi(value),
+                "lo" => cpu.set_lo(value),
+                name => cpu.set_gpr(register(name)?, value),
+            }
+            Ok(())
+        },
+    );
+
+    let s = system.clone();
+    engine.register_fn("set_input", move |port: i64, buttons: i64| {
+        let mut state = PadState::new();
+        state.buttons = buttons as u16;
+        s.borrow_mut().set_input(port as usize & 1, &state);
+    });
+
+    let s = system.clone();
+    engine.register_fn("save_state", move |path: &str| -> ScriptResult<()> {
+        let data = s.borrow().save_state();
+        std::fs::write(path, data).map_err(|e| format!("{}: {}", path, e).into())
+    });
+
+    let s = system.clone();
+    engine.register_fn("load_state", move |path: &str| -> ScriptResult<()> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        s.borrow_mut()
+            .load_state(&data)
+            .map_err(|e| e.to_string().into())
+    });
+
+    let s = system.clone();
+    engine.register_fn("screenshot", move |path: &str| -> ScriptResult<()> {
+        screenshot::save(&s.borrow(), Path::new(path)).map_err(|e| e.to_string().into())
+    });
+
+    let s = system.clone();
+    engine.register_fn("vram_dump", move |path: &str| -> ScriptResult<()> {
+        screenshot::save_vram(&s.borrow(), Path::new(path)).map_err(|e| e.to_string().into())
+    });
+
+    let h = hooks.clone();
+    engine.register_fn("frame", move || h.borrow().frames as i64);
+
+    let h = hooks.clone();
+    engine.register_fn("stop", move || h.borrow_mut().stopped = true);
+}
+
+fn register_hooks<R: Renderer + 'static>(
+    engine: &mut Engine,
+    system: &Rc<RefCell<System<R>>>,
+    hooks: &Rc<RefCell<Hooks>>,
+) {
+    let h = hooks.clone();
+    engine.register_fn("on_frame", move |callback: FnPtr| {
+        h.borrow_mut().frame.push(callback);
+    });
+
+    let h = hooks.clone();
+    engine.register_fn("on_exec", move |pc: i64, callback: FnPtr| {
+        h.borrow_mut().exec.push((pc as u32, callback));
+    });
+
+    // Caught with a watchpoint on the byte
+    let (h, s) = (hooks.clone(), system.clone());
+    engine.register_fn("on_write", move |addr: i64, callback: FnPtr| {
+        let addr = addr as u32;
+        s.borrow_mut()
+            .cpu_mut()
+            .bus_mut()
+            .add_watchpoint(Watchpoint {
+                addr,
+                len: 1,
+                kind: WatchKind::Write,
+            });
+        h.borrow_mut().write.push((addr, callback));
+    });
+}
+
+/// The callbacks registered for `addr`.
+fn callbacks(hooks: &[(u32, FnPtr)], addr: u32) -> Vec<FnPtr> {
+    hooks
+        .iter()
+        .filter(|(a, _)| *a == addr)
+        .map(|(_, callback)| callback.clone())
+        .collect()
+}
+
+/// Index of a general purpose register, by name or as rN.
+fn register(name: &str) -> ScriptResult<usize> {
+    let name = name.trim_start_matches('$');
+    REGISTER_NAMES
+        .iter()
+        .position(|&n| n == name)
+        .or_else(|| {
+            name.strip_prefix('r')
+                .and_then(|n| n.parse().ok())
+                .filter(|&n| n < 32)
+        })
+        .ok_or_else(|| format!("Unknown register '{}'", name).into())
+}
+
+fn unmapped(addr: u32) -> Box<EvalAltResult> {
+    format!("0x{:08X} isn't memory", addr).into()
+}
+
+fn script_error(e: Box<EvalAltResult>) -> Error {
+    Error::Script(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bios::BIOS;
+    use crate::renderer::NullRenderer;
+
+    fn load(name: &str, source: &str) -> Result<Script<NullRenderer>, Error> {
+        let path = std::env::temp_dir().join(format!("rstationx-{}.rhai", name));
+        std::fs::write(&path, source).unwrap();
+        let script = Script::load(&path, System::new(BIOS::empty(), NullRenderer));
+        std::fs::remove_file(&path).unwrap();
+        script
+    }
+
+    #[test]
+    fn endless_loop_at_load() {
+        assert!(matches!(
+            load("loop-load", "loop {}"),
+            Err(Error::Script(_))
+        ));
+    }
+
+    #[test]
+    fn endless_loop_in_callback() {
+        let mut script = load("loop-frame", "on_frame(|| { loop {} });").unwrap();
+        assert!(matches!(script.run_frame(), Err(Error::Script(_))));
+    }
+}
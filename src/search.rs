@@ -0,0 +1,180 @@
+/**
+ * RAM search, to find where a game keeps a value: take a snapshot, then narrow the
+ * candidates down by comparing RAM with the previous snapshot (or with a value)
+ * after each change in the game. And a watch list, to keep an eye on the addresses
+ * found.
+ *
+ * Addresses are offsets in RAM, like the ones GameShark codes use.
+ */
+use std::fmt;
+
+/// How values are read: 8, 16 or 32 bits, signed or not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Kind {
+    pub bytes: u32,
+    pub signed: bool,
+}
+
+impl Kind {
+    /// "u8", "s16", "u32"...
+    pub fn parse(name: &str) -> Option<Kind> {
+        let (signed, bits) = match name.split_at_checked(1)? {
+            ("u", bits) => (false, bits),
+            ("s", bits) => (true, bits),
+            _ => return None,
+        };
+        let bytes = match bits {
+            "8" => 1,
+            "16" => 2,
+            "32" => 4,
+            _ => return None,
+        };
+        Some(Kind { bytes, signed })
+    }
+
+    /// The value at `addr`, sign extended if signed. Reads wrap around RAM.
+    pub fn read(&self, ram: &[u8], addr: u32) -> i64 {
+        let value = (0..self.bytes).fold(0u32, |value, i| {
+            let byte = ram[(addr + i) as usize % ram.len()];
+            value | (byte as u32) << (i * 8)
+        });
+
+        let shift = 32 - self.bytes * 8;
+        match self.signed {
+            true => ((value << shift) as i32 >> shift) as i64,
+            false => value as i64,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.signed { 's' } else { 'u' };
+        write!(f, "{}{}", sign, self.bytes * 8)
+    }
+}
+
+/// How a value has to compare with the previous snapshot to stay a candidate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Equal to this value, whatever it was before.
+    Equal(i64),
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+}
+
+impl Filter {
+    fn matches(&self, previous: i64, current: i64) -> bool {
+        match *self {
+            Filter::Equal(value) => current == value,
+            Filter::Unchanged => current == previous,
+            Filter::Changed => current != previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+        }
+    }
+}
+
+pub struct Search {
+    kind: Kind,
+    snapshot: Vec<u8>,
+    /// Still in the running, in order.
+    candidates: Vec<u32>,
+}
+
+impl Search {
+    /// Starts a search over every aligned value in RAM.
+    pub fn new(ram: &[u8], kind: Kind) -> Search {
+        Search {
+            kind,
+            snapshot: ram.to_vec(),
+            candidates: (0..ram.len() as u32).step_by(kind.bytes as usize).collect(),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Keeps the candidates matching `filter`, and takes a new snapshot. Returns
+    /// how many are left.
+    pub fn filter(&mut self, ram: &[u8], filter: Filter) -> usize {
+        let kind = self.kind;
+        let snapshot = &self.snapshot;
+        self.candidates
+            .retain(|&addr| filter.matches(kind.read(snapshot, addr), kind.read(ram, addr)));
+        self.snapshot.copy_from_slice(ram);
+        self.candidates.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// The candidates, with their value in the last snapshot.
+    pub fn results(&self) -> impl Iterator<Item = (u32, i64)> + '_ {
+        self.candidates
+            .iter()
+            .map(|&addr| (addr, self.kind.read(&self.snapshot, addr)))
+    }
+}
+
+pub struct Watch {
+    pub addr: u32,
+    pub kind: Kind,
+}
+
+pub struct WatchList {
+    watches: Vec<Watch>,
+}
+
+impl WatchList {
+    pub fn new() -> WatchList {
+        WatchList {
+            watches: Vec::new(),
+        }
+    }
+
+    /// Replaces the watch on `addr` if there's one already.
+    pub fn add(&mut self, addr: u32, kind: Kind) {
+        self.remove(addr);
+        self.watches.push(Watch { addr, kind });
+    }
+
+    /// Returns false if `addr` wasn't watched.
+    pub fn remove(&mut self, addr: u32) -> bool {
+        let count = self.watches.len();
+        self.watches.retain(|w| w.addr != addr);
+        self.watches.len() != count
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// A line with every watched value, like `0x0A1234 u16 99  0x0B0000 s8 -1`.
+    pub fn report(&self, ram: &[u8]) -> String {
+        let values: Vec<String> = self
+            .watches
+            .iter()
+            .map(|w| format!("0x{:06X} {} {}", w.addr, w.kind, w.kind.read(ram, w.addr)))
+            .collect();
+        values.join("  ")
+    }
+}
+
+impl Default for WatchList {
+    fn default() -> WatchList {
+        WatchList::new()
+    }
+}
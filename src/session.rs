@@ -0,0 +1,251 @@
+/**
+ * What frontends keep track of around the emulation, whatever their window and
+ * input: save state slots, rewinding, movies, screenshots and video recording. They map their keys and command
+ * line onto a `Session`, and run frames through it rather than through the
+ * system, or call `begin_frame` and `end_frame` around the frames they step
+ * through themselves.
+ *
+ * State files and screenshots are named after the game, and numbered.
+ */
+use crate::avrecord::AVRecorder;
+use crate::error::Error;
+use crate::movie::{Player, Recorder};
+use crate::renderer::Renderer;
+use crate::rewind::Rewind;
+use crate::screenshot;
+use crate::sio::PadState;
+use crate::system::System;
+use std::path::{Path, PathBuf};
+
+pub const STATE_DIR: &str = "./states";
+pub const SCREENSHOT_DIR: &str = "./screenshots";
+pub const STATE_SLOTS: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    /// What's on screen.
+    Screen,
+    /// All of VRAM.
+    VRAM,
+}
+
+pub struct Session {
+    game: String,
+    slot: u8,
+    rewind: Option<Rewind>,
+    rewinding: bool,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    /// Captures to take once the system gets to their frame.
+    captures: Vec<(u64, Capture)>,
+    av: Option<AVRecorder>,
+}
+
+impl Session {
+    pub fn new(game: &str) -> Session {
+        Session {
+            game: game.to_string(),
+            slot: 0,
+            rewind: None,
+            rewinding: false,
+            recorder: None,
+            player: None,
+            captures: Vec::new(),
+            av: None,
+        }
+    }
+
+    /// Records the inputs of the first pad to a movie. There's no rewinding or
+    /// loading states while recording.
+    pub fn record_movie<R: Renderer>(
+        &mut self,
+        path: &Path,
+        system: &mut System<R>,
+    ) -> Result<(), Error> {
+        self.recorder = Some(Recorder::create(path, system)?);
+        self.rewind = None;
+        Ok(())
+    }
+
+    /// Plays a movie back, its inputs replacing the ones given to `begin_frame`
+    /// until it's over. There's no rewinding while playing.
+    pub fn play_movie<R: Renderer>(
+        &mut self,
+        path: &Path,
+        system: &mut System<R>,
+    ) -> Result<(), Error> {
+        self.player = Some(Player::open(path, system)?);
+        self.rewind = None;
+        Ok(())
+    }
+
+    /// Keeps snapshots to rewind to, see `Rewind::new`. Not with a movie.
+    pub fn enable_rewind(&mut self, interval: u32, budget: usize) {
+        if self.recorder.is_none() && self.player.is_none() {
+            self.rewind = Some(Rewind::new(interval, budget));
+        }
+    }
+
+    /// While set, each frame goes back a snapshot instead of forward.
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+    }
+
+    /// Takes a capture once the system has run `frame` frames.
+    pub fn schedule_capture(&mut self, frame: u64, capture: Capture) {
+        self.captures.push((frame, capture));
+    }
+
+    /// Records every frame from now on, see `AVRecorder`.
+    pub fn record_av(&mut self, path: &Path) -> Result<(), Error> {
+        self.av = Some(AVRecorder::create(path)?);
+        Ok(())
+    }
+
+    /// Runs a frame with `input` on the first pad, see `begin_frame`. Returns
+    /// false, without running it, once the movie being played is over.
+    pub fn run_frame<R: Renderer>(
+        &mut self,
+        system: &mut System<R>,
+        input: &PadState,
+    ) -> Result<bool, Error> {
+        if !self.begin_frame(system, input)? {
+            return Ok(false);
+        }
+        system.run_frame()?;
+        self.end_frame(system)?;
+        Ok(true)
+    }
+
+    /// What comes before a frame: `input` on the first pad, or the movie's inputs
+    /// while one is playing, and going back a snapshot while rewinding. Returns
+    /// false once the movie being played is over, frames then run with `input`.
+    pub fn begin_frame<R: Renderer>(
+        &mut self,
+        system: &mut System<R>,
+        input: &PadState,
+    ) -> Result<bool, Error> {
+        if let Some(player) = self.player.as_mut() {
+            if player.begin_frame(system)? {
+                return Ok(true);
+            }
+            info!("Movie over after {} frames, no desync", player.frame());
+            self.player = None;
+            system.set_input(0, input);
+            return Ok(false);
+        }
+
+        if let (Some(rewind), true) = (self.rewind.as_mut(), self.rewinding) {
+            rewind.step_back(system)?;
+        }
+        match self.recorder.as_mut() {
+            Some(recorder) => recorder.begin_frame(system, &[*input, PadState::new()]),
+            None => system.set_input(0, input),
+        }
+        Ok(true)
+    }
+
+    /// What comes after a frame: checking or recording the movie, the snapshot to
+    /// rewind to unless rewinding, then `capture_frame`.
+    pub fn end_frame<R: Renderer>(&mut self, system: &System<R>) -> Result<(), Error> {
+        if let Some(player) = self.player.as_mut() {
+            player.end_frame(system)?;
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.end_frame(system)?;
+        }
+        if let (Some(rewind), false) = (self.rewind.as_mut(), self.rewinding) {
+            rewind.frame(system);
+        }
+        self.capture_frame(system);
+        Ok(())
+    }
+
+    /// Takes the captures due and records the frame. `end_frame` does this,
+    /// frontends that run frames some other way, like scripts, call it
+    /// themselves. Failures are logged, and stop the video recording.
+    pub fn capture_frame<R: Renderer>(&mut self, system: &System<R>) {
+        let (due, pending) = self
+            .captures
+            .iter()
+            .partition(|&&(frame, _)| system.frame() >= frame);
+        self.captures = pending;
+        for (_, capture) in due {
+            if let Err(e) = self.capture(system, capture) {
+                error!("Failed to save {:?} capture: {}", capture, e);
+            }
+        }
+
+        if let Some(Err(e)) = self.av.as_mut().map(|av| av.frame(system)) {
+            error!("Video recording stopped: {}", e);
+            self.av = None;
+        }
+    }
+
+    /// Saves a screenshot or a VRAM dump in the screenshot directory, numbered
+    /// after the ones already there.
+    pub fn capture<R: Renderer>(
+        &self,
+        system: &System<R>,
+        capture: Capture,
+    ) -> Result<PathBuf, Error> {
+        let kind = match capture {
+            Capture::Screen => "screen",
+            Capture::VRAM => "vram",
+        };
+        let path = (0..)
+            .map(|n| Path::new(SCREENSHOT_DIR).join(format!("{}.{}.{}.png", self.game, kind, n)))
+            .find(|path| !path.exists())
+            .unwrap();
+
+        std::fs::create_dir_all(SCREENSHOT_DIR).map_err(|source| Error::Io {
+            path: SCREENSHOT_DIR.into(),
+            source,
+        })?;
+        match capture {
+            Capture::Screen => screenshot::save(system, &path)?,
+            Capture::VRAM => screenshot::save_vram(system, &path)?,
+        }
+        info!("Saved {}", path.display());
+        Ok(path)
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    /// Moves on to the next save state slot, going round.
+    pub fn next_slot(&mut self) -> u8 {
+        self.slot = (self.slot + 1) % STATE_SLOTS;
+        info!("Save state slot {}", self.slot);
+        self.slot
+    }
+
+    /// Saves to the current slot.
+    pub fn save_state<R: Renderer>(&self, system: &System<R>) -> Result<(), Error> {
+        let path = self.state_path();
+        std::fs::create_dir_all(STATE_DIR)
+            .and_then(|_| std::fs::write(&path, system.save_state()))
+            .map_err(|source| Error::Io { path, source })?;
+        info!("Saved state to slot {}", self.slot);
+        Ok(())
+    }
+
+    /// Loads the current slot, unless a movie is being recorded.
+    pub fn load_state<R: Renderer>(&self, system: &mut System<R>) -> Result<(), Error> {
+        if self.recorder.is_some() {
+            warn!("Can't load states while recording a movie");
+            return Ok(());
+        }
+
+        let path = self.state_path();
+        let data = std::fs::read(&path).map_err(|source| Error::Io { path, source })?;
+        system.load_state(&data)?;
+        info!("Loaded state from slot {}", self.slot);
+        Ok(())
+    }
+
+    fn state_path(&self) -> PathBuf {
+        Path::new(STATE_DIR).join(format!("{}.{}.state", self.game, self.slot))
+    }
+}
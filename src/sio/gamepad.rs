@@ -0,0 +1,345 @@
+/**
+ * A DualShock (SCPH-1200) controller, which also covers the plain digital pad
+ * when analog mode is off.
+ */
+use super::Device;
+use crate::error::Error;
+use crate::savestate::{invalid, StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Button {
+    Select = 0,
+    L3 = 1,
+    R3 = 2,
+    Start = 3,
+    Up = 4,
+    Right = 5,
+    Down = 6,
+    Left = 7,
+    L2 = 8,
+    R2 = 9,
+    L1 = 10,
+    R1 = 11,
+    Triangle = 12,
+    Circle = 13,
+    Cross = 14,
+    Square = 15,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Axis {
+    RightX = 0,
+    RightY = 1,
+    LeftX = 2,
+    LeftY = 3,
+}
+
+/// Everything the player controls on a pad, as fed by the frontend.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PadState {
+    /// Bit n is set while the `Button` with value n is held.
+    pub buttons: u16,
+    /// Indexed by `Axis`, 0x00 is fully left/up, 0x80 centered and 0xff fully
+    /// right/down.
+    pub axes: [u8; 4],
+}
+
+impl PadState {
+    /// Nothing pressed, sticks centered.
+    pub fn new() -> PadState {
+        PadState {
+            buttons: 0,
+            axes: [0x80; 4],
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let mask = 1 << button as u16;
+        if pressed {
+            self.buttons |= mask;
+        } else {
+            self.buttons &= !mask;
+        }
+    }
+
+    pub fn set_axis(&mut self, axis: Axis, value: u8) {
+        self.axes[axis as usize] = value;
+    }
+}
+
+impl Default for PadState {
+    fn default() -> PadState {
+        PadState::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Idle,
+    Command,
+    Data,
+}
+
+const ID_DIGITAL: u8 = 0x41;
+const ID_ANALOG: u8 = 0x73;
+const ID_CONFIG: u8 = 0xf3;
+
+const MOTOR_SMALL: u8 = 0x00;
+const MOTOR_LARGE: u8 = 0x01;
+const MOTOR_NONE: u8 = 0xff;
+
+pub struct DualShock {
+    // Active low, like on the wire
+    buttons: u16,
+    axes: [u8; 4],
+
+    analog: bool,
+    analog_locked: bool,
+    config_mode: bool,
+
+    rumble_mapping: [u8; 6],
+    motors: (u8, u8),
+
+    stage: Stage,
+    command: u8,
+    response: [u8; 8],
+    response_len: usize,
+    index: usize,
+}
+
+impl DualShock {
+    pub fn new() -> DualShock {
+        DualShock {
+            buttons: 0xffff,
+            axes: [0x80; 4],
+
+            analog: false,
+            analog_locked: false,
+            config_mode: false,
+
+            rumble_mapping: [MOTOR_NONE; 6],
+            motors: (0, 0),
+
+            stage: Stage::Idle,
+            command: 0,
+            response: [0; 8],
+            response_len: 0,
+            index: 0,
+        }
+    }
+
+    pub fn set_state(&mut self, state: &PadState) {
+        self.buttons = !state.buttons;
+        self.axes = state.axes;
+    }
+
+    /// Held buttons, active low.
+    pub fn buttons(&self) -> u16 {
+        self.buttons
+    }
+
+    /// The "ANALOG" button. Ignored while the game has locked the mode.
+    pub fn toggle_analog(&mut self) {
+        if !self.analog_locked {
+            self.analog = !self.analog;
+            info!("Controller switched to {} mode", self.mode_name());
+        }
+    }
+
+    /// Current (small, large) motor strength. The small motor is either off or
+    /// at full strength.
+    pub fn motors(&self) -> (u8, u8) {
+        self.motors
+    }
+
+    fn mode_name(&self) -> &'static str {
+        match self.analog {
+            true => "analog",
+            false => "digital",
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match (self.config_mode, self.analog) {
+            (true, _) => ID_CONFIG,
+            (false, true) => ID_ANALOG,
+            (false, false) => ID_DIGITAL,
+        }
+    }
+
+    fn set_response(&mut self, data: &[u8]) {
+        self.response[..data.len()].copy_from_slice(data);
+        self.response_len = data.len();
+    }
+
+    fn input_response(&mut self) {
+        let [lo, hi] = self.buttons.to_le_bytes();
+        let [rx, ry, lx, ly] = self.axes;
+
+        match self.analog || self.config_mode {
+            true => self.set_response(&[self.id(), 0x5a, lo, hi, rx, ry, lx, ly]),
+            false => self.set_response(&[self.id(), 0x5a, lo, hi]),
+        }
+    }
+
+    /// Builds the whole reply for `command`. The first byte is sent back while the
+    /// command byte itself is being received.
+    fn start_command(&mut self, command: u8) -> bool {
+        self.command = command;
+
+        match (command, self.config_mode) {
+            (0x42, _) => {
+                self.input_response();
+                // The game re-sends the rumble bytes every poll, motors stop otherwise
+                self.motors = (0, 0);
+            }
+            (0x43, false) => self.input_response(),
+            (0x43, true) => self.set_response(&[ID_CONFIG, 0x5a, 0, 0, 0, 0, 0, 0]),
+            (0x44, true) => self.set_response(&[ID_CONFIG, 0x5a, 0, 0, 0, 0, 0, 0]),
+            (0x45, true) => {
+                let led = self.analog as u8;
+                self.set_response(&[ID_CONFIG, 0x5a, 0x01, 0x02, led, 0x02, 0x01, 0x00]);
+            }
+            (0x46, true) | (0x47, true) | (0x4c, true) => {
+                // Depends on the parameter byte, patched in `parameter`
+                self.set_response(&[ID_CONFIG, 0x5a, 0, 0, 0, 0, 0, 0]);
+                if command == 0x47 {
+                    self.response[4..8].copy_from_slice(&[0x02, 0x00, 0x01, 0x00]);
+                }
+            }
+            (0x4d, true) => {
+                let mut response = [ID_CONFIG, 0x5a, 0, 0, 0, 0, 0, 0];
+                response[2..].copy_from_slice(&self.rumble_mapping);
+                self.set_response(&response);
+            }
+            (0x40..=0x4f, true) => self.set_response(&[ID_CONFIG, 0x5a, 0, 0, 0, 0, 0, 0]),
+            _ => {
+                debug!(
+                    "Unhandled controller command 0x{:02X} (config mode: {})",
+                    command, self.config_mode
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Handles the byte the host sent at `index` (0 being the command byte).
+    fn parameter(&mut self, index: usize, value: u8) {
+        match (self.command, index) {
+            (0x42, 2..=7) => match self.rumble_mapping[index - 2] {
+                MOTOR_SMALL => self.motors.0 = if value & 1 != 0 { 0xff } else { 0 },
+                MOTOR_LARGE => self.motors.1 = value,
+                _ => (),
+            },
+            (0x43, 2) => {
+                // Entering/leaving config mode only takes effect for the next command
+                self.config_mode = value == 0x01;
+            }
+            (0x44, 2) if self.config_mode => {
+                self.analog = value == 0x01;
+                info!("Game switched controller to {} mode", self.mode_name());
+            }
+            (0x44, 3) if self.config_mode => self.analog_locked = value == 0x03,
+            (0x46, 2) if self.config_mode => {
+                let data = match value {
+                    0x00 => [0x01, 0x02, 0x00, 0x0a],
+                    0x01 => [0x01, 0x01, 0x01, 0x14],
+                    _ => [0x00; 4],
+                };
+                self.response[4..8].copy_from_slice(&data);
+            }
+            (0x4c, 2) if self.config_mode => {
+                self.response[5] = match value {
+                    0x00 => 0x04,
+                    0x01 => 0x07,
+                    _ => 0x00,
+                };
+            }
+            (0x4d, 2..=7) if self.config_mode => self.rumble_mapping[index - 2] = value,
+            _ => (),
+        }
+    }
+
+    /// Mode and protocol state. The buttons and sticks come from the frontend.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.analog);
+        w.bool(self.analog_locked);
+        w.bool(self.config_mode);
+        w.bytes(&self.rumble_mapping);
+        w.u8(self.motors.0);
+        w.u8(self.motors.1);
+
+        w.u8(self.stage as u8);
+        w.u8(self.command);
+        w.bytes(&self.response);
+        w.u32(self.response_len as u32);
+        w.u32(self.index as u32);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.analog = r.bool()?;
+        self.analog_locked = r.bool()?;
+        self.config_mode = r.bool()?;
+        r.bytes_into(&mut self.rumble_mapping)?;
+        self.motors = (r.u8()?, r.u8()?);
+
+        self.stage = match r.u8()? {
+            0 => Stage::Idle,
+            1 => Stage::Command,
+            2 => Stage::Data,
+            _ => return Err(invalid("pad stage")),
+        };
+        self.command = r.u8()?;
+        r.bytes_into(&mut self.response)?;
+        self.response_len = (r.u32()? as usize).min(self.response.len());
+        self.index = r.u32()? as usize;
+        Ok(())
+    }
+}
+
+impl Default for DualShock {
+    fn default() -> DualShock {
+        DualShock::new()
+    }
+}
+
+impl Device for DualShock {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        match self.stage {
+            Stage::Idle => {
+                if tx != 0x01 {
+                    return (0xff, false);
+                }
+                self.stage = Stage::Command;
+                (0xff, true)
+            }
+            Stage::Command => {
+                if !self.start_command(tx) {
+                    self.stage = Stage::Idle;
+                    return (0xff, false);
+                }
+                self.stage = Stage::Data;
+                self.index = 1;
+                (self.response[0], true)
+            }
+            Stage::Data => {
+                let index = self.index;
+                self.parameter(index, tx);
+
+                let rx = self.response[index];
+                self.index += 1;
+
+                let more = self.index < self.response_len;
+                if !more {
+                    self.stage = Stage::Idle;
+                }
+                (rx, more)
+            }
+        }
+    }
+
+    fn deselect(&mut self) {
+        self.stage = Stage::Idle;
+    }
+}
@@ -0,0 +1,284 @@
+/**
+ * A 128 KiB memory card (SCPH-1020) backed by a raw .mcr/.mcd image on disk.
+ */
+use super::Device;
+use crate::error::Error;
+use crate::memcard::{Card, CARD_SIZE, FRAME_SIZE as SECTOR_SIZE};
+use crate::savestate::{invalid, StateReader, StateWriter};
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const SECTOR_COUNT: u16 = (CARD_SIZE / SECTOR_SIZE) as u16;
+
+// FLAG bits
+const FLAG_WRITE_ERROR: u8 = 1 << 2;
+const FLAG_NOT_WRITTEN: u8 = 1 << 3;
+
+const END_GOOD: u8 = 0x47;
+const END_BAD_CHECKSUM: u8 = 0x4e;
+const END_BAD_SECTOR: u8 = 0xff;
+
+// Games write a save as a burst of sectors, only touch the disk once they're done.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    None,
+    Read,
+    Write,
+    Id,
+}
+
+pub struct MemoryCard {
+    path: PathBuf,
+    data: Vec<u8>,
+    flag: u8,
+
+    command: Command,
+    index: usize,
+    sector: u16,
+    checksum: u8,
+    previous: u8,
+    buffer: [u8; SECTOR_SIZE],
+
+    last_write: Option<Instant>,
+}
+
+impl MemoryCard {
+    /// Opens the card image at `path`, creating a freshly formatted one if it
+    /// doesn't exist yet.
+    pub fn open(path: &Path) -> std::io::Result<MemoryCard> {
+        let data = match fs::File::open(path) {
+            Ok(file) => {
+                info!("Reading memory card from {}.", path.display());
+                let mut data = Vec::new();
+                file.take(CARD_SIZE as u64 + 1).read_to_end(&mut data)?;
+
+                if data.len() != CARD_SIZE {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "Invalid memory card file.",
+                    ));
+                }
+                data
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("Creating new memory card at {}.", path.display());
+                let data = Card::formatted().data;
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, &data)?;
+                data
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(MemoryCard {
+            path: path.to_path_buf(),
+            data,
+            flag: FLAG_NOT_WRITTEN,
+
+            command: Command::None,
+            index: 0,
+            sector: 0,
+            checksum: 0,
+            previous: 0,
+            buffer: [0; SECTOR_SIZE],
+
+            last_write: None,
+        })
+    }
+
+    /// Writes the image back to disk if the game stopped writing to it a while ago.
+    pub fn flush_if_settled(&mut self) -> std::io::Result<()> {
+        match self.last_write {
+            Some(time) if time.elapsed() >= SETTLE_TIME => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the image back to disk if it has unsaved changes.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if self.last_write.is_none() {
+            return Ok(());
+        }
+
+        debug!("Saving memory card to {}.", self.path.display());
+        fs::write(&self.path, &self.data)?;
+        self.last_write = None;
+        Ok(())
+    }
+
+    fn sector_offset(&self) -> usize {
+        self.sector as usize * SECTOR_SIZE
+    }
+
+    fn read(&mut self, tx: u8) -> (u8, bool) {
+        let [msb, lsb] = self.sector.to_be_bytes();
+
+        let rx = match self.index {
+            2 => 0x5a,
+            3 => 0x5d,
+            4 => {
+                self.sector = (tx as u16) << 8;
+                0x00
+            }
+            5 => {
+                self.sector |= tx as u16;
+                self.previous
+            }
+            6 => 0x5c,
+            7 => 0x5d,
+            8 if self.sector >= SECTOR_COUNT => {
+                debug!("Memory card read from invalid sector 0x{:04X}", self.sector);
+                return (0xff, false);
+            }
+            8 => {
+                self.checksum = msb ^ lsb;
+                msb
+            }
+            9 => lsb,
+            10..=137 => {
+                let byte = self.data[self.sector_offset() + self.index - 10];
+                self.checksum ^= byte;
+                byte
+            }
+            138 => self.checksum,
+            139 => return (END_GOOD, false),
+            _ => return (0xff, false),
+        };
+        (rx, true)
+    }
+
+    fn write(&mut self, tx: u8) -> (u8, bool) {
+        let rx = match self.index {
+            2 => 0x5a,
+            3 => 0x5d,
+            4 => {
+                self.sector = (tx as u16) << 8;
+                self.checksum = tx;
+                0x00
+            }
+            5 => {
+                self.sector |= tx as u16;
+                self.checksum ^= tx;
+                self.previous
+            }
+            6..=133 => {
+                self.buffer[self.index - 6] = tx;
+                self.checksum ^= tx;
+                self.previous
+            }
+            134 => {
+                // Ends up as zero if the checksum matches
+                self.checksum ^= tx;
+                self.previous
+            }
+            135 => 0x5c,
+            136 => 0x5d,
+            137 => return (self.finish_write(), false),
+            _ => return (0xff, false),
+        };
+        (rx, true)
+    }
+
+    fn finish_write(&mut self) -> u8 {
+        if self.sector >= SECTOR_COUNT {
+            debug!("Memory card write to invalid sector 0x{:04X}", self.sector);
+            self.flag |= FLAG_WRITE_ERROR;
+            return END_BAD_SECTOR;
+        }
+
+        if self.checksum != 0 {
+            debug!(
+                "Bad checksum in memory card write to sector {}",
+                self.sector
+            );
+            self.flag |= FLAG_WRITE_ERROR;
+            return END_BAD_CHECKSUM;
+        }
+
+        let offset = self.sector_offset();
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(&self.buffer);
+        self.flag &= !(FLAG_WRITE_ERROR | FLAG_NOT_WRITTEN);
+        self.last_write = Some(Instant::now());
+        END_GOOD
+    }
+
+    fn id(&mut self) -> (u8, bool) {
+        let response = [0x5a, 0x5d, 0x5c, 0x5d, 0x04, 0x00, 0x00, 0x80];
+        match self.index {
+            2..=8 => (response[self.index - 2], true),
+            9 => (response[7], false),
+            _ => (0xff, false),
+        }
+    }
+
+    /// The transfer in progress. The card contents are on disk, not in states.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.flag);
+        w.u8(self.command as u8);
+        w.u32(self.index as u32);
+        w.u16(self.sector);
+        w.u8(self.checksum);
+        w.u8(self.previous);
+        w.bytes(&self.buffer);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.flag = r.u8()?;
+        self.command = match r.u8()? {
+            0 => Command::None,
+            1 => Command::Read,
+            2 => Command::Write,
+            3 => Command::Id,
+            _ => return Err(invalid("memory card command")),
+        };
+        self.index = r.u32()? as usize;
+        self.sector = r.u16()?;
+        self.checksum = r.u8()?;
+        self.previous = r.u8()?;
+        r.bytes_into(&mut self.buffer)
+    }
+}
+
+impl Device for MemoryCard {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let response = match (self.index, self.command) {
+            (0, _) => {
+                if tx != 0x81 {
+                    return (0xff, false);
+                }
+                (0xff, true)
+            }
+            (1, _) => {
+                self.command = match tx {
+                    b'R' => Command::Read,
+                    b'W' => Command::Write,
+                    b'S' => Command::Id,
+                    _ => {
+                        debug!("Unhandled memory card command 0x{:02X}", tx);
+                        return (self.flag, false);
+                    }
+                };
+                (self.flag, true)
+            }
+            (_, Command::Read) => self.read(tx),
+            (_, Command::Write) => self.write(tx),
+            (_, Command::Id) => self.id(),
+            (_, Command::None) => (0xff, false),
+        };
+
+        self.index += 1;
+        self.previous = tx;
+        response
+    }
+
+    fn deselect(&mut self) {
+        self.command = Command::None;
+        self.index = 0;
+    }
+}
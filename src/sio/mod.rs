@@ -0,0 +1,334 @@
+/**
+ * SIO0, the serial port shared by the controllers and the memory cards.
+ */
+mod gamepad;
+mod memcard;
+
+use crate::error::Error;
+use crate::memory::MemoryRegion;
+use crate::savestate::{invalid, StateReader, StateWriter};
+use crate::utils;
+
+pub use gamepad::{Axis, Button, DualShock, PadState};
+pub use memcard::MemoryCard;
+
+/// Something plugged into one of the two SIO0 slots.
+pub trait Device {
+    /// Exchanges one byte with the device. Returns the response byte and whether
+    /// the device pulled /ACK low, i.e. whether it expects more bytes.
+    fn transfer(&mut self, tx: u8) -> (u8, bool);
+
+    /// Called when /JOYn goes high again, ending the current transaction.
+    fn deselect(&mut self);
+}
+
+/// The device currently talking on the bus, picked from the first byte of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    None,
+    Pad,
+    MemoryCard,
+    Ignored,
+}
+
+pub struct Slot {
+    pub pad: Option<DualShock>,
+    pub memcard: Option<MemoryCard>,
+}
+
+pub struct SIO0 {
+    slots: [Slot; 2],
+    target: Target,
+
+    rx: Option<u8>,
+    ack: bool,
+    irq: bool,
+
+    mode: u16,
+    baud: u16,
+
+    tx_enable: bool,
+    select: bool,
+    rx_enable: bool,
+    rx_irq_mode: u8,
+    tx_irq_enable: bool,
+    rx_irq_enable: bool,
+    ack_irq_enable: bool,
+    port: usize,
+}
+
+impl SIO0 {
+    pub fn new() -> SIO0 {
+        SIO0 {
+            slots: [
+                Slot {
+                    pad: Some(DualShock::new()),
+                    memcard: None,
+                },
+                Slot {
+                    pad: None,
+                    memcard: None,
+                },
+            ],
+            target: Target::None,
+
+            rx: None,
+            ack: false,
+            irq: false,
+
+            mode: 0,
+            baud: 0,
+
+            tx_enable: false,
+            select: false,
+            rx_enable: false,
+            rx_irq_mode: 0,
+            tx_irq_enable: false,
+            rx_irq_enable: false,
+            ack_irq_enable: false,
+            port: 0,
+        }
+    }
+
+    /// Back to the power-on state, keeping whatever is plugged in.
+    pub fn reset(&mut self) {
+        let mut fresh = SIO0::new();
+        std::mem::swap(&mut fresh.slots, &mut self.slots);
+        *self = fresh;
+    }
+
+    /// Status bit 9, set when /ACK comes with ACK interrupts enabled and cleared
+    /// by the control register's acknowledge bit.
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    pub fn pad_mut(&mut self, index: usize) -> Option<&mut DualShock> {
+        self.slots[index].pad.as_mut()
+    }
+
+    pub fn set_memcard(&mut self, index: usize, memcard: Option<MemoryCard>) {
+        self.slots[index].memcard = memcard;
+    }
+
+    /// Saves the memory cards that have pending changes. Unless `force` is set, cards
+    /// that are still being written to are left alone.
+    pub fn flush_memcards(&mut self, force: bool) -> std::io::Result<()> {
+        for memcard in self.slots.iter_mut().filter_map(|s| s.memcard.as_mut()) {
+            match force {
+                true => memcard.flush()?,
+                false => memcard.flush_if_settled()?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load<T: TryFrom<u32>>(&mut self, offset: u32) -> Result<T, Error> {
+        let value = match offset {
+            0x0 => self.rx.take().unwrap_or(0xff) as u32,
+            0x4 => self.status(),
+            0x8 => self.mode as u32,
+            0xa => self.control() as u32,
+            0xe => self.baud as u32,
+            _ => {
+                return Err(Error::UnhandledRegister {
+                    region: MemoryRegion::PadMemCard,
+                    offset,
+                    write: false,
+                })
+            }
+        };
+        Ok(utils::to_t(value))
+    }
+
+    pub fn store(&mut self, offset: u32, value: u32) -> Result<(), Error> {
+        match offset {
+            0x0 => self.transfer(value as u8),
+            0x8 => self.mode = value as u16,
+            0xa => self.set_control(value as u16),
+            0xe => self.baud = value as u16,
+            _ => {
+                return Err(Error::UnhandledRegister {
+                    region: MemoryRegion::PadMemCard,
+                    offset,
+                    write: true,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> u32 {
+        // Transfers complete instantly, so the TX side is always ready.
+        1 << 0
+            | (self.rx.is_some() as u32) << 1
+            | 1 << 2
+            | (self.ack as u32) << 7
+            | (self.irq as u32) << 9
+    }
+
+    fn control(&self) -> u16 {
+        (self.tx_enable as u16)
+            | (self.select as u16) << 1
+            | (self.rx_enable as u16) << 2
+            | (self.rx_irq_mode as u16) << 8
+            | (self.tx_irq_enable as u16) << 10
+            | (self.rx_irq_enable as u16) << 11
+            | (self.ack_irq_enable as u16) << 12
+            | (self.port as u16) << 13
+    }
+
+    fn set_control(&mut self, value: u16) {
+        if value & (1 << 6) != 0 {
+            // Reset most registers
+            self.rx = None;
+            self.ack = false;
+            self.irq = false;
+            self.mode = 0;
+            self.baud = 0;
+        }
+
+        if value & (1 << 4) != 0 {
+            // Acknowledge
+            self.irq = false;
+        }
+
+        let select = value & (1 << 1) != 0;
+        let port = ((value >> 13) & 1) as usize;
+
+        if (self.select && !select) || port != self.port {
+            self.deselect();
+        }
+
+        self.tx_enable = value & 1 != 0;
+        self.select = select;
+        self.rx_enable = value & (1 << 2) != 0;
+        self.rx_irq_mode = ((value >> 8) & 3) as u8;
+        self.tx_irq_enable = value & (1 << 10) != 0;
+        self.rx_irq_enable = value & (1 << 11) != 0;
+        self.ack_irq_enable = value & (1 << 12) != 0;
+        self.port = port;
+    }
+
+    fn deselect(&mut self) {
+        let slot = &mut self.slots[self.port];
+        if let Some(pad) = slot.pad.as_mut() {
+            pad.deselect();
+        }
+        if let Some(memcard) = slot.memcard.as_mut() {
+            memcard.deselect();
+        }
+        self.target = Target::None;
+        self.ack = false;
+    }
+
+    fn transfer(&mut self, tx: u8) {
+        if !self.tx_enable || !self.select {
+            trace!("SIO0 transfer of 0x{:02X} without a selected slot", tx);
+            self.rx = Some(0xff);
+            return;
+        }
+
+        if self.target == Target::None {
+            self.target = match tx {
+                0x01 => Target::Pad,
+                0x81 => Target::MemoryCard,
+                _ => Target::Ignored,
+            };
+        }
+
+        let slot = &mut self.slots[self.port];
+        let device: Option<&mut dyn Device> = match self.target {
+            Target::Pad => slot.pad.as_mut().map(|d| d as &mut dyn Device),
+            Target::MemoryCard => slot.memcard.as_mut().map(|d| d as &mut dyn Device),
+            _ => None,
+        };
+        let (rx, ack) = match device {
+            Some(device) => device.transfer(tx),
+            None => (0xff, false),
+        };
+
+        if !ack {
+            self.target = Target::Ignored;
+        }
+
+        self.rx = Some(rx);
+        self.ack = ack;
+        if ack && self.ack_irq_enable {
+            self.irq = true;
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.target as u8);
+        w.bool(self.rx.is_some());
+        w.u8(self.rx.unwrap_or(0));
+        w.bool(self.ack);
+        w.bool(self.irq);
+        w.u16(self.mode);
+        w.u16(self.baud);
+        w.u16(self.control());
+
+        // Each device as a blob, empty when nothing is plugged in, so a state
+        // still loads with different devices plugged in
+        for slot in &self.slots {
+            let mut pad = StateWriter::new();
+            if let Some(p) = slot.pad.as_ref() {
+                p.save_state(&mut pad);
+            }
+            w.bytes(&pad.into_inner());
+
+            let mut memcard = StateWriter::new();
+            if let Some(m) = slot.memcard.as_ref() {
+                m.save_state(&mut memcard);
+            }
+            w.bytes(&memcard.into_inner());
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.target = match r.u8()? {
+            0 => Target::None,
+            1 => Target::Pad,
+            2 => Target::MemoryCard,
+            3 => Target::Ignored,
+            _ => return Err(invalid("SIO0 target")),
+        };
+        let has_rx = r.bool()?;
+        let rx = r.u8()?;
+        self.rx = has_rx.then_some(rx);
+        self.ack = r.bool()?;
+        self.irq = r.bool()?;
+        self.mode = r.u16()?;
+        self.baud = r.u16()?;
+
+        let control = r.u16()?;
+        self.tx_enable = control & 1 != 0;
+        self.select = control & (1 << 1) != 0;
+        self.rx_enable = control & (1 << 2) != 0;
+        self.rx_irq_mode = ((control >> 8) & 3) as u8;
+        self.tx_irq_enable = control & (1 << 10) != 0;
+        self.rx_irq_enable = control & (1 << 11) != 0;
+        self.ack_irq_enable = control & (1 << 12) != 0;
+        self.port = ((control >> 13) & 1) as usize;
+
+        for slot in &mut self.slots {
+            let pad = r.bytes()?;
+            if let (Some(p), false) = (slot.pad.as_mut(), pad.is_empty()) {
+                p.load_state(&mut StateReader::new(&pad))?;
+            }
+
+            let memcard = r.bytes()?;
+            if let (Some(m), false) = (slot.memcard.as_mut(), memcard.is_empty()) {
+                m.load_state(&mut StateReader::new(&memcard))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SIO0 {
+    fn default() -> SIO0 {
+        SIO0::new()
+    }
+}
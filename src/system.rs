@@ -0,0 +1,335 @@
+/**
+ * The whole console: what frontends drive. Owns the CPU, which owns the bus and
+ * everything hanging off it.
+ */
+use crate::bios::{Region, BIOS};
+use crate::cheats::Cheats;
+use crate::cpu::CPU;
+use crate::disc::Disc;
+use crate::error::Error;
+use crate::gpu::GPU;
+use crate::kernel::{Boot, Call, Kernel};
+use crate::memory::{Bus, RAM};
+use crate::renderer::Renderer;
+use crate::savestate::{self, StateReader, StateWriter, Thumbnail};
+use crate::sio::{DualShock, MemoryCard, PadState};
+use crate::tracer::Tracer;
+
+/// 33.8688 MHz
+pub const CPU_CLOCK: u64 = 33_868_800;
+/// Emulated time per call to `run_frame` on NTSC consoles: one frame.
+pub const CYCLES_PER_FRAME: u64 = CPU_CLOCK / 60;
+pub const CYCLES_PER_FRAME_PAL: u64 = CPU_CLOCK / 50;
+
+/// What `step` does when the guest uses hardware the emulator doesn't implement
+/// (`Error::is_unimplemented`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorPolicy {
+    /// Return the error.
+    Stop,
+    /// Log and count it, and carry on with the next instruction.
+    Continue,
+}
+
+pub struct System<R: Renderer> {
+    cpu: CPU<R>,
+    disc: Option<Disc>,
+    tracer: Option<Tracer>,
+    kernel: Kernel,
+    cheats: Cheats,
+    /// From the BIOS, None if it couldn't tell.
+    region: Option<Region>,
+    error_policy: ErrorPolicy,
+    unimplemented_errors: u64,
+}
+
+impl<R: Renderer> System<R> {
+    pub fn new(bios: BIOS, renderer: R) -> System<R> {
+        let region = bios.region();
+        let mut gpu = GPU::new(renderer);
+        gpu.set_pal(region.is_some_and(Region::is_pal));
+        let bus = Bus::new(bios, RAM::new(), gpu);
+
+        System {
+            cpu: CPU::new(bus),
+            disc: None,
+            tracer: None,
+            kernel: Kernel::new(),
+            cheats: Cheats::new(),
+            region,
+            error_policy: ErrorPolicy::Stop,
+            unimplemented_errors: 0,
+        }
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /// Overrides the region detected from the BIOS, e.g. for HLE boots.
+    pub fn set_region(&mut self, region: Option<Region>) {
+        self.region = region;
+        self.cpu
+            .bus_mut()
+            .gpu_mut()
+            .set_pal(region.is_some_and(Region::is_pal));
+    }
+
+    /// Frames run since power on, going by the cycle count.
+    pub fn frame(&self) -> u64 {
+        self.cpu.cycles() / self.cycles_per_frame()
+    }
+
+    /// Length of a frame for the console's region, NTSC when it's unknown.
+    pub fn cycles_per_frame(&self) -> u64 {
+        match self.region {
+            Some(region) if region.is_pal() => CYCLES_PER_FRAME_PAL,
+            _ => CYCLES_PER_FRAME,
+        }
+    }
+
+    /// Runs one frame worth of CPU cycles.
+    pub fn run_frame(&mut self) -> Result<(), Error> {
+        let end = self.cpu.cycles() + self.cycles_per_frame();
+        while self.cpu.cycles() < end {
+            self.step()?;
+        }
+        self.end_frame();
+        Ok(())
+    }
+
+    /// What happens between frames: the kernel's vblank handler and cheats. For
+    /// frontends that `step` through frames themselves.
+    pub fn end_frame(&mut self) {
+        let buttons = [0, 1].map(|port| self.pad_mut(port).map(|pad| pad.buttons()));
+        self.kernel.vblank(self.cpu.bus_mut(), buttons);
+        self.cheats.apply(self.cpu.bus_mut().ram_mut());
+    }
+
+    /// Cheats applied at the end of every frame.
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cheats = cheats;
+    }
+
+    /// Stop by default.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    /// Unimplemented hardware errors carried on after so far.
+    pub fn unimplemented_errors(&self) -> u64 {
+        self.unimplemented_errors
+    }
+
+    /// Runs a single instruction.
+    pub fn step(&mut self) -> Result<(), Error> {
+        if self.kernel.hle_step(&mut self.cpu, self.disc.as_mut())? {
+            return Ok(());
+        }
+
+        let call = Call::at(&self.cpu);
+
+        let result = match self.tracer.as_mut() {
+            Some(tracer) => tracer.step(&mut self.cpu),
+            None => self.cpu.exec_next_instruction(),
+        };
+        match result {
+            Err(e) if e.is_unimplemented() && self.error_policy == ErrorPolicy::Continue => {
+                warn!("{}", e);
+                self.unimplemented_errors += 1;
+            }
+            result => result?,
+        }
+
+        // Unless an interrupt came first
+        if let Some(call) = call.filter(|c| self.cpu.pc() == c.pc.wrapping_add(4)) {
+            self.kernel.called(&call, self.cpu.bus());
+        }
+        Ok(())
+    }
+
+    /// Where the guest's TTY output (putchar, printf, ...) goes. It's logged if
+    /// there's nowhere else.
+    pub fn set_tty_output(&mut self, output: Option<Box<dyn std::io::Write>>) {
+        self.kernel.set_tty_output(output);
+    }
+
+    /// Traces every instruction from now on. Returns the previous tracer, which
+    /// still needs a `flush`.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// Like pressing the reset button. Discs, pads and memory cards stay in.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.cpu.reset()?;
+        match self.kernel.hle_boot() {
+            Some(boot) => self.boot_hle(boot),
+            None => Ok(()),
+        }
+    }
+
+    /// The machine state, uncompressed and without a header. Cheaper than
+    /// `save_state` for keeping states in memory.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        self.cpu.save_state(&mut w);
+        self.kernel.save_state(&mut w);
+        w.into_inner()
+    }
+
+    /// Goes back to a snapshot. If that fails, the machine is left half restored
+    /// and should be reset.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), Error> {
+        let mut r = StateReader::new(snapshot);
+        self.cpu.load_state(&mut r)?;
+        self.kernel.load_state(&mut r)?;
+        r.finish()
+    }
+
+    /// A state file, with a thumbnail of the current picture.
+    pub fn save_state(&self) -> Vec<u8> {
+        let bus = self.cpu.bus();
+        let (width, height, rgb) = bus.gpu().display_rgb();
+        let thumbnail = Thumbnail::new(width, height, &rgb);
+        savestate::encode(bus.bios().hash(), &thumbnail, &self.snapshot())
+    }
+
+    /// Loads a state file. The machine is left as it was if that fails.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        let (header, snapshot) = savestate::decode(data)?;
+        if header.bios_hash != self.cpu.bus().bios().hash() {
+            warn!("Save state was made with another BIOS, expect trouble");
+        }
+
+        let backup = self.snapshot();
+        self.restore(&snapshot).inspect_err(|_| {
+            // Can't fail, it was just made
+            self.restore(&backup).unwrap();
+        })
+    }
+
+    /// Runs without the BIOS, with calls to the kernel emulated instead. Use with
+    /// `BIOS::empty`. Booting from the disc needs one inserted first.
+    pub fn boot_hle(&mut self, boot: Boot) -> Result<(), Error> {
+        self.kernel
+            .boot_hle(boot, &mut self.cpu, self.disc.as_mut())
+    }
+
+    /// MD5 of the EXE booted with `Boot::Exe`, if that's how the system started.
+    pub fn exe_hash(&self) -> Option<[u8; 16]> {
+        self.kernel.exe_hash()
+    }
+
+    /// There's no CD-ROM controller yet, so the disc is only there for whoever
+    /// reads it through `disc_mut`. Warns when it's from another region than the
+    /// console, which a real drive would refuse to boot.
+    pub fn insert_disc(&mut self, mut disc: Disc) {
+        match (disc.region(), self.region) {
+            (Some(disc_region), Some(region)) if disc_region != region => warn!(
+                "Disc is licensed for {}, the console is {}",
+                disc_region.licence(),
+                region.licence()
+            ),
+            (Some(disc_region), _) => info!("Disc is licensed for {}", disc_region.licence()),
+            (None, _) => warn!("No licence string on the disc"),
+        }
+        self.disc = Some(disc);
+    }
+
+    pub fn eject_disc(&mut self) -> Option<Disc> {
+        self.disc.take()
+    }
+
+    pub fn disc_mut(&mut self) -> Option<&mut Disc> {
+        self.disc.as_mut()
+    }
+
+    /// Sets what the pad in `port` (0 or 1) reports from now on. Does nothing if no
+    /// pad is plugged in there.
+    pub fn set_input(&mut self, port: usize, state: &PadState) {
+        if let Some(pad) = self.pad_mut(port) {
+            pad.set_state(state);
+        }
+    }
+
+    pub fn pad_mut(&mut self, port: usize) -> Option<&mut DualShock> {
+        self.cpu.bus_mut().sio0_mut().pad_mut(port)
+    }
+
+    pub fn set_memcard(&mut self, slot: usize, memcard: Option<MemoryCard>) {
+        self.cpu.bus_mut().sio0_mut().set_memcard(slot, memcard);
+    }
+
+    /// See `SIO0::flush_memcards`.
+    pub fn flush_memcards(&mut self, force: bool) -> std::io::Result<()> {
+        self.cpu.bus_mut().sio0_mut().flush_memcards(force)
+    }
+
+    pub fn cpu(&self) -> &CPU<R> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<R> {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::NullRenderer;
+
+    const GP0: u32 = 0x1f80_1810;
+
+    /// A machine that's been doing things: an image loaded into the displayed
+    /// area of VRAM, RAM written and a few instructions run.
+    fn system() -> System<NullRenderer> {
+        let mut system = System::new(BIOS::empty(), NullRenderer);
+        let bus = system.cpu_mut().bus_mut();
+        for word in [0xa000_0000u32, 0x0000_0010, 0x0001_0002, 0x7fff_7fff] {
+            bus.store(GP0, word).unwrap();
+        }
+        bus.ram_mut()[0x1234] = 0x56;
+        for _ in 0..100 {
+            system.step().unwrap();
+        }
+        system
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let system = system();
+        let snapshot = system.snapshot();
+        assert!(system.cpu().bus().gpu().vram().iter().any(|&p| p != 0));
+
+        let mut restored = System::new(BIOS::empty(), NullRenderer);
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let system = system();
+        let state = system.save_state();
+
+        let mut restored = System::new(BIOS::empty(), NullRenderer);
+        restored.load_state(&state).unwrap();
+
+        // All but the timestamp, and the image shows in the thumbnail
+        let (header, snapshot) = savestate::decode(&state).unwrap();
+        let (restored_header, restored_snapshot) =
+            savestate::decode(&restored.save_state()).unwrap();
+        assert_eq!(restored_snapshot, snapshot);
+        assert_eq!(restored_header.thumbnail.rgb, header.thumbnail.rgb);
+        assert!(header.thumbnail.rgb.iter().any(|&c| c != 0));
+    }
+}
@@ -0,0 +1,156 @@
+/**
+ * Execution tracer. Writes one line per instruction, in the same layout as the
+ * usual reference traces so they can be diffed:
+ *
+ *     80010000 27bdffe8 addiu $sp, $sp, -0x18       sp=801fffe8
+ *
+ * PC, instruction word, disassembly, then the registers it changed. Loads show
+ * up one instruction late, when the load delay slot ends.
+ */
+use crate::cpu::{Instruction, NoSymbols, CPU, REGISTER_NAMES};
+use crate::error::Error;
+use crate::renderer::Renderer;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// When to start or stop tracing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// On reaching this address.
+    Pc(u32),
+    /// After this many instructions since the tracer was attached.
+    Count(u64),
+}
+
+impl Trigger {
+    /// `0x` prefixed numbers are addresses, plain ones instruction counts.
+    pub fn parse(s: &str) -> Option<Trigger> {
+        match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok().map(Trigger::Pc),
+            None => s.parse().ok().map(Trigger::Count),
+        }
+    }
+
+    fn hit(&self, pc: u32, count: u64) -> bool {
+        match *self {
+            Trigger::Pc(addr) => addr == pc,
+            Trigger::Count(n) => n == count,
+        }
+    }
+}
+
+pub struct Tracer {
+    output: Box<dyn Write>,
+    start: Option<Trigger>,
+    stop: Option<Trigger>,
+    active: bool,
+    count: u64,
+    /// In ring buffer mode only the last lines are kept, and written by `flush`.
+    ring: Option<VecDeque<String>>,
+    ring_size: usize,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>) -> Tracer {
+        Tracer {
+            output,
+            start: None,
+            stop: None,
+            active: true,
+            count: 0,
+            ring: None,
+            ring_size: 0,
+        }
+    }
+
+    /// Only start tracing once `trigger` fires.
+    pub fn start_at(&mut self, trigger: Trigger) {
+        self.start = Some(trigger);
+        self.active = false;
+    }
+
+    pub fn stop_at(&mut self, trigger: Trigger) {
+        self.stop = Some(trigger);
+    }
+
+    /// Keeps only the last `size` instructions, for looking at what led to a crash.
+    pub fn ring_buffer(&mut self, size: usize) {
+        self.ring = Some(VecDeque::with_capacity(size));
+        self.ring_size = size.max(1);
+    }
+
+    /// Runs one instruction, tracing it if needed.
+    pub fn step<R: Renderer>(&mut self, cpu: &mut CPU<R>) -> Result<(), Error> {
+        let pc = cpu.pc();
+
+        if !self.active && self.start.is_some_and(|t| t.hit(pc, self.count)) {
+            self.active = true;
+        }
+        if self.active && self.stop.is_some_and(|t| t.hit(pc, self.count)) {
+            self.active = false;
+            self.start = None;
+            self.stop = None;
+        }
+        self.count += 1;
+
+        if !self.active {
+            return cpu.exec_next_instruction();
+        }
+
+        let before = (*cpu.registers(), cpu.hi(), cpu.lo());
+        let word = cpu.bus().peek_word(pc);
+
+        let result = cpu.exec_next_instruction();
+
+        let mut line = match word {
+            Some(value) => {
+                let text = Instruction { value }.disassemble(pc, &NoSymbols);
+                format!("{:08x} {:08x} {:<32}", pc, value, text)
+            }
+            None => format!("{:08x} ???????? {:<32}", pc, ""),
+        };
+
+        let changes = (0..32)
+            .filter(|&r| cpu.registers()[r] != before.0[r])
+            .map(|r| (REGISTER_NAMES[r], cpu.registers()[r]))
+            .chain((cpu.hi() != before.1).then(|| ("hi", cpu.hi())))
+            .chain((cpu.lo() != before.2).then(|| ("lo", cpu.lo())));
+        for (name, value) in changes {
+            line.push_str(&format!(" {}={:08x}", name, value));
+        }
+
+        if let Err(e) = self.write_line(line.trim_end().to_string()) {
+            error!("Failed to write trace: {}", e);
+        }
+        if result.is_err() {
+            // That was probably the crash the trace is for
+            if let Err(e) = self.flush() {
+                error!("Failed to write trace: {}", e);
+            }
+        }
+        result
+    }
+
+    fn write_line(&mut self, line: String) -> io::Result<()> {
+        match self.ring.as_mut() {
+            Some(ring) => {
+                if ring.len() == self.ring_size {
+                    ring.pop_front();
+                }
+                ring.push_back(line);
+                Ok(())
+            }
+            None => writeln!(self.output, "{}", line),
+        }
+    }
+
+    /// Writes out the ring buffer, if there's one, and flushes the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(ring) = self.ring.as_mut() {
+            for line in ring.drain(..) {
+                writeln!(self.output, "{}", line)?;
+            }
+        }
+        self.output.flush()
+    }
+}